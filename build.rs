@@ -0,0 +1,32 @@
+// Build script: embed the git commit in the binary so INFO can report which
+// build is deployed. Falls back to "unknown" outside a git checkout (e.g. in
+// Docker); set REDISTILL_GIT_SHA to override.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let sha = std::env::var("REDISTILL_GIT_SHA")
+        .ok()
+        .or_else(git_sha)
+        .filter(|sha| !sha.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=REDISTILL_GIT_SHA={}", sha);
+    println!("cargo:rerun-if-env-changed=REDISTILL_GIT_SHA");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs/heads");
+    }
+}
+
+fn git_sha() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
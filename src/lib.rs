@@ -1,20 +1,46 @@
-// Redistill library - exposes internal components for testing and potential future SDK usage
-// This module provides access to the core data structures and functions without requiring
-// the full binary to be run.
-
-#![allow(dead_code)] // Some items may only be used in tests
+// Redistill library - the server's storage engine, RESP protocol handling and
+// command execution. The redistill binary is a thin runtime around it, and the
+// tests drive the same code directly.
 
 use ahash::AHasher;
+use bytes::Buf;
 pub use bytes::{Bytes, BytesMut};
+use crossbeam::queue::SegQueue;
 pub use dashmap::DashMap;
+use http_body_util::Full;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
 use std::hash::Hasher;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::ServerConfig as RustlsServerConfig;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::TlsAcceptor;
+use subtle::ConstantTimeEq;
 
-// ==================== Configuration Structures ====================
+// Security limits for RESP protocol parsing (prevent DoS attacks)
+pub const MAX_STRING_LEN: usize = 512_000_000;   // Max 512MB per string (Redis default)
+pub const MAX_BUFFER_SIZE: usize = 1_073_741_824; // Max 1GB buffer per connection (DoS protection)
+pub const MAX_PENDING_REPLY_BYTES: usize = 64 * 1024 * 1024; // Flush point cap, whatever reply_flush_threshold says
 
+// Configuration structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     #[serde(default = "default_bind")]
@@ -23,32 +49,81 @@ pub struct ServerConfig {
     pub port: u16,
     #[serde(default = "default_num_shards")]
     pub num_shards: usize,
+    #[serde(default = "default_databases")]
+    pub databases: usize, // Number of logical databases reachable with SELECT
     #[serde(default = "default_batch_size")]
     pub batch_size: usize,
     #[serde(default = "default_buffer_size")]
-    pub buffer_size: usize,
+    pub buffer_size: usize, // Ceiling a busy connection's buffers grow toward
+    #[serde(default = "default_min_buffer_size")]
+    pub min_buffer_size: usize, // What a new or idle connection's buffers start from
     #[serde(default = "default_buffer_pool_size")]
     pub buffer_pool_size: usize,
+    #[serde(default = "default_reply_flush_threshold")]
+    pub reply_flush_threshold: usize, // Pending reply bytes that force a flush mid-batch
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
     #[serde(default = "default_connection_timeout")]
     pub connection_timeout: u64,
     #[serde(default)]
-    pub connection_rate_limit: u64,
+    pub connection_rate_limit: u64, // Max new connections per second (0 = unlimited)
+    #[serde(default)]
+    pub accept_rate: u64, // Accept throttle: connections handled per second (0 = disabled)
+    #[serde(default = "default_accept_burst")]
+    pub accept_burst: u64, // Accept throttle: connections handled without delay
+    #[serde(default)]
+    pub health_check_port: u16, // HTTP health check port (0 = disabled)
+    #[serde(default = "default_health_check_bind")]
+    pub health_check_bind: String, // HTTP health check bind address
+    #[serde(default = "default_max_command_args")]
+    pub max_command_args: usize, // Max arguments in a single command (name included)
+    #[serde(default = "default_max_command_bytes")]
+    pub max_command_bytes: usize, // Max total size of a single command's arguments
+    #[serde(default = "default_max_inline_len")]
+    pub max_inline_len: usize, // Max unterminated inline command line (Redis' PROTO_INLINE_MAX_SIZE)
+    #[serde(default = "default_keys_stream_threshold")]
+    pub keys_stream_threshold: usize, // KEYS replies larger than this are streamed in chunks
+    #[serde(default)]
+    pub command_latency_metrics: bool, // Per-command latency histograms on /metrics
     #[serde(default)]
-    pub health_check_port: u16,
+    pub strict_protocol: bool, // Refuse bare \n endings and length prefixes such as $007
+    #[serde(default)]
+    pub command_timeout: u64, // Milliseconds KEYS/SCAN/SMEMBERS/SORT may iterate (0 = no limit)
+    #[serde(default)]
+    pub track_hot_keys: bool, // Sample key accesses for DEBUG HOTKEYS
+    #[serde(default)]
+    pub latency_monitor_threshold: u64, // Milliseconds; slower commands are LATENCY events (0 = off)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SecurityConfig {
     #[serde(default)]
     pub password: String,
+    #[serde(default = "default_auth_failure_delay_ms")]
+    pub auth_failure_delay_ms: u64, // Delay before a failed AUTH is answered (0 = none)
+    #[serde(default)]
+    pub acl_rules: String, // e.g. "+@all -@dangerous" (empty = every command allowed)
+    #[serde(default = "default_true")]
+    pub enable_debug_command: bool, // DEBUG, including its test hooks (SET-SAVE-DELAY, ...)
     #[serde(default)]
     pub tls_enabled: bool,
     #[serde(default)]
     pub tls_cert_path: String,
     #[serde(default)]
     pub tls_key_path: String,
+    #[serde(default)]
+    pub tls_alpn_protocols: Vec<String>, // Advertised in the handshake, in preference order
+    #[serde(default)]
+    pub tls_sni: Vec<TlsSniConfig>, // Certificates picked by server name; others get tls_cert_path
+}
+
+// A [[security.tls_sni]] entry: the certificate served to clients that ask
+// for `server_name`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsSniConfig {
+    pub server_name: String,
+    pub cert_path: String,
+    pub key_path: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,17 +142,155 @@ pub struct PerformanceConfig {
     pub tcp_keepalive: u64,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersistenceConfig {
+    #[serde(default)]
+    pub snapshot_path: String, // SAVE/BGSAVE target, loaded at startup ("" = disabled)
+    #[serde(default)]
+    pub save: String, // Autosave points, "seconds changes ..." as in Redis ("" = none)
+}
+
+// BGSAVE once `changes` writes have happened and `seconds` have passed since
+// the last save
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+// Parse a Redis `save` line such as "3600 1 300 100"
+pub fn parse_save_points(spec: &str) -> Result<Vec<SavePoint>, String> {
+    let numbers = spec
+        .split_whitespace()
+        .map(|n| n.parse::<u64>().map_err(|_| format!("save point '{}' is not a number", n)))
+        .collect::<Result<Vec<u64>, String>>()?;
+    if numbers.len() % 2 != 0 {
+        return Err("save needs pairs of seconds and changes".to_string());
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| SavePoint { seconds: pair[0], changes: pair[1] })
+        .collect())
+}
+
+// Whether any save point is met by `changes` writes over `elapsed` seconds
+pub fn save_due(points: &[SavePoint], changes: u64, elapsed: u64) -> bool {
+    changes > 0
+        && points
+            .iter()
+            .any(|point| changes >= point.changes && elapsed >= point.seconds)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryConfig {
     #[serde(default)]
-    pub max_memory: u64,
+    pub max_memory: u64, // 0 = unlimited
     #[serde(default = "default_eviction_policy")]
     pub eviction_policy: String,
     #[serde(default = "default_eviction_sample_size")]
     pub eviction_sample_size: usize,
+    #[serde(default = "default_max_memory_low_pct")]
+    pub max_memory_low_pct: u64, // Once eviction starts, free down to this % of max_memory
+    #[serde(default)]
+    pub expiry_index: bool, // Index keys by expiry time so the sweeper finds them directly
+    #[serde(default)]
+    pub max_value_size: u64, // Largest string value a write may store (0 = unlimited)
+    #[serde(default = "default_set_max_intset_entries")]
+    pub set_max_intset_entries: usize, // Integer-only sets up to this size stay compact
+    #[serde(default = "default_list_max_listpack_size")]
+    pub list_max_listpack_size: i64, // Small lists stay packed: > 0 = max entries, -1..-5 = 4KB..64KB
+    #[serde(default)]
+    pub max_memory_clients: u64, // Budget for all clients' unsent replies together (0 = unlimited)
+    #[serde(default)]
+    pub list_max_entries: usize, // Most elements one list may hold (0 = unlimited)
+    #[serde(default)]
+    pub set_max_entries: usize, // Most members one set may hold (0 = unlimited)
+    #[serde(default)]
+    pub zset_max_entries: usize, // Most members one sorted set may hold (0 = unlimited)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub fn default_eviction_policy() -> String {
+    "allkeys-lru".to_string()
+}
+
+pub fn default_eviction_sample_size() -> usize {
+    5
+}
+
+pub fn default_max_memory_low_pct() -> u64 {
+    90
+}
+
+pub fn default_set_max_intset_entries() -> usize {
+    512
+}
+
+pub fn default_list_max_listpack_size() -> i64 {
+    -2
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            max_memory: 0,
+            eviction_policy: default_eviction_policy(),
+            eviction_sample_size: default_eviction_sample_size(),
+            max_memory_low_pct: default_max_memory_low_pct(),
+            expiry_index: false,
+            max_value_size: 0,
+            set_max_intset_entries: default_set_max_intset_entries(),
+            list_max_listpack_size: default_list_max_listpack_size(),
+            max_memory_clients: 0,
+            list_max_entries: 0,
+            set_max_entries: 0,
+            zset_max_entries: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    AllKeysRandom,
+}
+
+impl EvictionPolicy {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        Self::parse(s).unwrap_or(EvictionPolicy::AllKeysLru)
+    }
+
+    // None for a name that is not a policy (validate() refuses those)
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "allkeys-lru" => Some(EvictionPolicy::AllKeysLru),
+            "allkeys-random" => Some(EvictionPolicy::AllKeysRandom),
+            "noeviction" => Some(EvictionPolicy::NoEviction),
+            _ => None,
+        }
+    }
+
+    pub const ALL: [Self; 3] = [
+        EvictionPolicy::NoEviction,
+        EvictionPolicy::AllKeysLru,
+        EvictionPolicy::AllKeysRandom,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+        }
+    }
+}
+
+// Environment variables starting with this and a section name override
+// single settings (Config::apply_env)
+pub const ENV_PREFIX: &str = "REDISTILL_";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub server: ServerConfig,
@@ -89,51 +302,77 @@ pub struct Config {
     pub performance: PerformanceConfig,
     #[serde(default)]
     pub memory: MemoryConfig,
+    #[serde(default)]
+    pub persistence: PersistenceConfig,
 }
 
 // Default functions
-fn default_bind() -> String {
+pub fn default_bind() -> String {
+    "127.0.0.1".to_string()
+}
+pub fn default_health_check_bind() -> String {
     "127.0.0.1".to_string()
 }
-fn default_port() -> u16 {
+pub fn default_port() -> u16 {
     6379
 }
-fn default_num_shards() -> usize {
+pub fn default_num_shards() -> usize {
     256
 }
-fn default_batch_size() -> usize {
+pub fn default_databases() -> usize {
     16
 }
-fn default_buffer_size() -> usize {
+pub fn default_batch_size() -> usize {
+    16
+}
+pub fn default_buffer_size() -> usize {
     16 * 1024
 }
-fn default_buffer_pool_size() -> usize {
+pub fn default_min_buffer_size() -> usize {
+    1024
+}
+pub fn default_buffer_pool_size() -> usize {
     1024
 }
-fn default_max_connections() -> usize {
+pub fn default_max_connections() -> usize {
     10000
 }
-fn default_connection_timeout() -> u64 {
+pub fn default_connection_timeout() -> u64 {
     300
 }
-fn default_log_level() -> String {
+pub fn default_max_command_args() -> usize {
+    1_000_000
+}
+pub fn default_max_command_bytes() -> usize {
+    536_870_912 // 512MB
+}
+pub fn default_max_inline_len() -> usize {
+    64 * 1024
+}
+pub fn default_accept_burst() -> u64 {
+    100
+}
+pub fn default_keys_stream_threshold() -> usize {
+    10_000
+}
+pub fn default_reply_flush_threshold() -> usize {
+    8192
+}
+pub fn default_auth_failure_delay_ms() -> u64 {
+    5
+}
+pub fn default_log_level() -> String {
     "info".to_string()
 }
-fn default_log_format() -> String {
+pub fn default_log_format() -> String {
     "text".to_string()
 }
-fn default_true() -> bool {
+pub fn default_true() -> bool {
     true
 }
-fn default_tcp_keepalive() -> u64 {
+pub fn default_tcp_keepalive() -> u64 {
     60
 }
-fn default_eviction_policy() -> String {
-    "allkeys-lru".to_string()
-}
-fn default_eviction_sample_size() -> usize {
-    5
-}
 
 impl Default for ServerConfig {
     fn default() -> Self {
@@ -141,13 +380,28 @@ impl Default for ServerConfig {
             bind: default_bind(),
             port: default_port(),
             num_shards: default_num_shards(),
+            databases: default_databases(),
             batch_size: default_batch_size(),
             buffer_size: default_buffer_size(),
+            min_buffer_size: default_min_buffer_size(),
             buffer_pool_size: default_buffer_pool_size(),
+            reply_flush_threshold: default_reply_flush_threshold(),
             max_connections: default_max_connections(),
             connection_timeout: default_connection_timeout(),
             connection_rate_limit: 0,
+            accept_rate: 0,
+            accept_burst: default_accept_burst(),
             health_check_port: 0,
+            health_check_bind: default_health_check_bind(),
+            max_command_args: default_max_command_args(),
+            max_command_bytes: default_max_command_bytes(),
+            max_inline_len: default_max_inline_len(),
+            keys_stream_threshold: default_keys_stream_threshold(),
+            command_latency_metrics: false,
+            strict_protocol: false,
+            command_timeout: 0,
+            track_hot_keys: false,
+            latency_monitor_threshold: 0,
         }
     }
 }
@@ -156,9 +410,14 @@ impl Default for SecurityConfig {
     fn default() -> Self {
         Self {
             password: String::new(),
+            auth_failure_delay_ms: default_auth_failure_delay_ms(),
+            acl_rules: String::new(),
+            enable_debug_command: true,
             tls_enabled: false,
             tls_cert_path: String::new(),
             tls_key_path: String::new(),
+            tls_alpn_protocols: Vec::new(),
+            tls_sni: Vec::new(),
         }
     }
 }
@@ -181,266 +440,8104 @@ impl Default for PerformanceConfig {
     }
 }
 
-impl Default for MemoryConfig {
-    fn default() -> Self {
-        Self {
-            max_memory: 0,
-            eviction_policy: default_eviction_policy(),
-            eviction_sample_size: default_eviction_sample_size(),
-        }
+impl Config {
+    // Custom config path from env var, otherwise the default
+    pub fn path() -> String {
+        std::env::var("REDISTILL_CONFIG").unwrap_or_else(|_| "redistill.toml".to_string())
     }
-}
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            server: ServerConfig::default(),
-            security: SecurityConfig::default(),
-            logging: LoggingConfig::default(),
-            performance: PerformanceConfig::default(),
-            memory: MemoryConfig::default(),
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let config_path = Self::path();
+
+        let mut config = if std::path::Path::new(&config_path).exists() {
+            let contents = std::fs::read_to_string(&config_path)?;
+            toml::from_str(&contents)?
+        } else {
+            if config_path != "redistill.toml" {
+                eprintln!(
+                    "⚠️  Config file '{}' not found, using defaults",
+                    config_path
+                );
+            }
+            Config::default()
+        };
+
+        // Allow ENV vars to override config
+        if let Ok(password) = std::env::var("REDIS_PASSWORD") {
+            config.security.password = password;
         }
-    }
-}
 
-// ==================== Eviction Policy ====================
+        if let Ok(port) = std::env::var("REDIS_PORT")
+            && let Ok(p) = port.parse()
+        {
+            config.server.port = p;
+        }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum EvictionPolicy {
-    NoEviction,
-    AllKeysLru,
-    AllKeysRandom,
-}
+        if let Ok(bind) = std::env::var("REDIS_BIND") {
+            config.server.bind = bind;
+        }
 
-impl EvictionPolicy {
-    pub fn from_str(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "allkeys-lru" => EvictionPolicy::AllKeysLru,
-            "allkeys-random" => EvictionPolicy::AllKeysRandom,
-            "noeviction" => EvictionPolicy::NoEviction,
-            _ => EvictionPolicy::AllKeysLru, // default
+        if let Ok(health_port) = std::env::var("REDIS_HEALTH_CHECK_PORT")
+            && let Ok(p) = health_port.parse()
+        {
+            config.server.health_check_port = p;
         }
-    }
 
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            EvictionPolicy::NoEviction => "noeviction",
-            EvictionPolicy::AllKeysLru => "allkeys-lru",
-            EvictionPolicy::AllKeysRandom => "allkeys-random",
+        if let Ok(health_bind) = std::env::var("REDIS_HEALTH_CHECK_BIND") {
+            config.server.health_check_bind = health_bind;
         }
-    }
-}
 
-// ==================== Storage Entry ====================
+        // Performance tuning via environment variables
+        if let Ok(num_shards) = std::env::var("REDIS_NUM_SHARDS")
+            && let Ok(n) = num_shards.parse()
+        {
+            config.server.num_shards = n;
+        }
 
-pub struct Entry {
-    pub value: Bytes,
-    pub expiry: Option<u64>,
-    pub last_accessed: AtomicU32,
-}
+        if let Ok(batch_size) = std::env::var("REDIS_BATCH_SIZE")
+            && let Ok(b) = batch_size.parse()
+        {
+            config.server.batch_size = b;
+        }
 
-impl Clone for Entry {
-    fn clone(&self) -> Self {
-        Self {
-            value: self.value.clone(),
-            expiry: self.expiry,
-            last_accessed: AtomicU32::new(self.last_accessed.load(Ordering::Relaxed)),
+        if let Ok(buffer_size) = std::env::var("REDIS_BUFFER_SIZE")
+            && let Ok(b) = buffer_size.parse()
+        {
+            config.server.buffer_size = b;
         }
-    }
-}
 
-// ==================== Sharded Store ====================
+        if let Ok(buffer_pool_size) = std::env::var("REDIS_BUFFER_POOL_SIZE")
+            && let Ok(b) = buffer_pool_size.parse()
+        {
+            config.server.buffer_pool_size = b;
+        }
 
-pub struct ShardedStore {
-    pub shards: Vec<Arc<DashMap<Bytes, Entry>>>,
-    pub num_shards: usize,
-}
+        if let Ok(max_connections) = std::env::var("REDIS_MAX_CONNECTIONS")
+            && let Ok(m) = max_connections.parse()
+        {
+            config.server.max_connections = m;
+        }
 
-impl ShardedStore {
-    pub fn new(num_shards: usize) -> Self {
-        let mut shards = Vec::with_capacity(num_shards);
-        for _ in 0..num_shards {
-            shards.push(Arc::new(DashMap::with_capacity(1000)));
+        // Memory management via environment variables
+        if let Ok(max_memory) = std::env::var("REDIS_MAX_MEMORY")
+            && let Ok(m) = max_memory.parse()
+        {
+            config.memory.max_memory = m;
         }
-        Self { shards, num_shards }
-    }
 
-    pub fn clone(&self) -> Self {
-        Self {
-            shards: self.shards.clone(),
-            num_shards: self.num_shards,
+        if let Ok(eviction_policy) = std::env::var("REDIS_EVICTION_POLICY") {
+            config.memory.eviction_policy = eviction_policy;
         }
-    }
 
-    // Fast AHash with hardware acceleration (AES-NI)
-    #[inline(always)]
-    pub fn hash(&self, key: &[u8]) -> usize {
-        let mut hasher = AHasher::default();
-        hasher.write(key);
-        hasher.finish() as usize % self.num_shards
-    }
+        // Performance settings via environment variables
+        if let Ok(tcp_nodelay) = std::env::var("REDIS_TCP_NODELAY") {
+            config.performance.tcp_nodelay = tcp_nodelay.parse().unwrap_or(true);
+        }
 
-    #[inline(always)]
-    pub fn set(&self, key: Bytes, value: Bytes, ttl: Option<u64>, now: u64) {
-        let expiry = ttl.map(|s| now + s);
-        let shard = &self.shards[self.hash(&key)];
-        shard.insert(
-            key,
-            Entry {
-                value,
-                expiry,
-                last_accessed: AtomicU32::new(0), // Will be set by get_uptime_seconds() in real usage
-            },
-        );
-    }
+        if let Ok(tcp_keepalive) = std::env::var("REDIS_TCP_KEEPALIVE")
+            && let Ok(k) = tcp_keepalive.parse()
+        {
+            config.performance.tcp_keepalive = k;
+        }
 
-    #[inline(always)]
-    pub fn get(&self, key: &[u8], now: u64) -> Option<Bytes> {
-        let shard = &self.shards[self.hash(key)];
+        // Any setting, by its REDISTILL_<SECTION>_<SETTING> name
+        config.apply_env(std::env::vars())?;
 
-        // Try read-only access first
-        if let Some(entry) = shard.get(key) {
-            if let Some(expiry) = entry.expiry {
-                if now >= expiry {
-                    // Expired - need to remove
-                    drop(entry);
-                    shard.remove(key);
-                    return None;
-                }
-            }
+        // Validate configuration
+        config.validate()?;
 
-            return Some(entry.value.clone());
+        Ok(config)
+    }
+
+    /// Override settings from `REDISTILL_<SECTION>_<SETTING>` variables,
+    /// e.g. `REDISTILL_SERVER_NUM_SHARDS=64`. Names are matched ignoring
+    /// case and underscores, so `REDISTILL_MEMORY_MAXMEMORY` sets
+    /// `memory.max_memory` too. A value is read as the setting's type, with
+    /// lists written as TOML arrays. Variables that do not start with a
+    /// section name (REDISTILL_CONFIG, build metadata) are left alone, but an
+    /// unknown setting in a known section, or a value of the wrong type, is
+    /// an error rather than being ignored.
+    pub fn apply_env<I>(&mut self, vars: I) -> Result<(), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut root = toml::Table::try_from(&*self)?;
+        let mut changed = false;
+        for (name, raw) in vars {
+            let Some((section, setting)) = name.strip_prefix(ENV_PREFIX).and_then(|rest| rest.split_once('_'))
+            else {
+                continue;
+            };
+            let section = section.to_ascii_lowercase();
+            let Some(toml::Value::Table(table)) = root.get_mut(&section) else {
+                continue;
+            };
+            let wanted = setting.replace('_', "").to_ascii_lowercase();
+            let Some(key) = table.keys().find(|key| key.replace('_', "") == wanted).cloned() else {
+                return Err(format!("{}: [{}] has no such setting", name, section).into());
+            };
+            let invalid = || format!("{}: invalid value {:?} for {}.{}", name, raw, section, key);
+            let value = match &table[&key] {
+                toml::Value::String(_) => toml::Value::String(raw.clone()),
+                toml::Value::Integer(_) => toml::Value::Integer(raw.trim().parse().map_err(|_| invalid())?),
+                toml::Value::Float(_) => toml::Value::Float(raw.trim().parse().map_err(|_| invalid())?),
+                toml::Value::Boolean(_) => match raw.trim().to_ascii_lowercase().as_str() {
+                    "true" | "yes" | "1" => toml::Value::Boolean(true),
+                    "false" | "no" | "0" => toml::Value::Boolean(false),
+                    _ => return Err(invalid().into()),
+                },
+                _ => toml::from_str::<toml::Table>(&format!("value = {}", raw))
+                    .ok()
+                    .and_then(|mut parsed| parsed.remove("value"))
+                    .ok_or_else(invalid)?,
+            };
+            table.insert(key, value);
+            changed = true;
         }
-        None
+        if changed {
+            *self = toml::Value::Table(root).try_into()?;
+        }
+        Ok(())
     }
 
-    #[inline(always)]
-    pub fn delete(&self, keys: &[Bytes]) -> usize {
-        // Group by shard for efficiency
-        let mut shard_keys: Vec<Vec<&Bytes>> = vec![Vec::new(); self.num_shards];
-        for key in keys {
-            shard_keys[self.hash(key)].push(key);
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Prevent division by zero and other critical errors
+        if self.server.num_shards == 0 {
+            return Err("num_shards must be greater than 0".into());
+        }
+        if self.server.databases == 0 {
+            return Err("databases must be greater than 0".into());
+        }
+        if self.server.buffer_size == 0 {
+            return Err("buffer_size must be greater than 0".into());
+        }
+        if self.server.min_buffer_size == 0 {
+            return Err("min_buffer_size must be greater than 0".into());
+        }
+        if self.server.batch_size == 0 {
+            return Err("batch_size must be greater than 0".into());
+        }
+        if self.server.reply_flush_threshold == 0 {
+            return Err("reply_flush_threshold must be greater than 0".into());
+        }
+        if self.server.max_command_args == 0 {
+            return Err("max_command_args must be greater than 0".into());
+        }
+        if self.server.max_command_bytes == 0 {
+            return Err("max_command_bytes must be greater than 0".into());
+        }
+        if self.server.max_inline_len == 0 {
+            return Err("max_inline_len must be greater than 0".into());
+        }
+        if self.server.accept_rate > 0 && self.server.accept_burst == 0 {
+            return Err("accept_burst must be greater than 0 when accept_rate is set".into());
+        }
+        if self.server.health_check_port > 0 && self.server.health_check_port == self.server.port {
+            return Err("health_check_port must differ from port".into());
+        }
+        if self.memory.list_max_listpack_size == 0 || self.memory.list_max_listpack_size < -5 {
+            return Err("list_max_listpack_size must be a positive entry count or -1 to -5".into());
+        }
+        
+        // Eviction config validation
+        if EvictionPolicy::parse(&self.memory.eviction_policy).is_none() {
+            return Err(format!(
+                "eviction_policy must be noeviction, allkeys-lru or allkeys-random, not '{}'",
+                self.memory.eviction_policy
+            )
+            .into());
+        }
+        if self.memory.max_memory > 0 && self.memory.eviction_sample_size == 0 {
+            return Err("eviction_sample_size must be > 0 when max_memory is set".into());
+        }
+        if self.memory.max_memory_low_pct == 0 || self.memory.max_memory_low_pct > 100 {
+            return Err("max_memory_low_pct must be between 1 and 100".into());
+        }
+        
+        if !self.security.acl_rules.is_empty() {
+            AclRules::parse(&self.security.acl_rules)?;
         }
 
-        let mut count = 0;
-        for (idx, keys_in_shard) in shard_keys.iter().enumerate() {
-            if !keys_in_shard.is_empty() {
-                let shard = &self.shards[idx];
-                for key in keys_in_shard {
-                    if shard.remove(*key).is_some() {
-                        count += 1;
-                    }
-                }
-            }
+        if !parse_save_points(&self.persistence.save)?.is_empty()
+            && self.persistence.snapshot_path.is_empty()
+        {
+            return Err("save points need a snapshot_path to save to".into());
         }
-        count
-    }
 
-    #[inline(always)]
-    pub fn exists(&self, keys: &[Bytes], now: u64) -> usize {
-        let mut count = 0;
-        for key in keys {
-            let shard = &self.shards[self.hash(key)];
-            if let Some(entry) = shard.get(key.as_ref()) {
-                if entry.expiry.is_none() || entry.expiry.unwrap() > now {
-                    count += 1;
-                }
+        // TLS config validation
+        if self.security.tls_enabled {
+            if self.security.tls_cert_path.is_empty() {
+                return Err("tls_cert_path is required when TLS is enabled".into());
+            }
+            if self.security.tls_key_path.is_empty() {
+                return Err("tls_key_path is required when TLS is enabled".into());
             }
         }
-        count
+        if let Some(protocol) = self
+            .security
+            .tls_alpn_protocols
+            .iter()
+            .find(|p| p.is_empty() || p.len() > 255)
+        {
+            return Err(format!("invalid tls_alpn_protocols entry {:?}: must be 1-255 bytes", protocol).into());
+        }
+        for sni in &self.security.tls_sni {
+            if sni.server_name.is_empty() || sni.cert_path.is_empty() || sni.key_path.is_empty() {
+                return Err("tls_sni entries need server_name, cert_path and key_path".into());
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn keys(&self, now: u64) -> Vec<Bytes> {
-        let mut result = Vec::new();
-        for shard in &self.shards {
-            for entry in shard.iter() {
-                let (key, val) = entry.pair();
-                if val.expiry.is_none() || val.expiry.unwrap() > now {
-                    result.push(key.clone());
-                }
-            }
+    /// Settings that are valid but probably not what was meant, printed at
+    /// startup. validate() refuses the ones that cannot work at all.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.memory.max_memory > 0
+            && EvictionPolicy::from_str(&self.memory.eviction_policy) == EvictionPolicy::NoEviction
+        {
+            warnings.push(format!(
+                "max_memory is {} with eviction_policy = noeviction: every write is refused once it is full",
+                format_bytes(self.memory.max_memory)
+            ));
+        }
+        if self.server.connection_rate_limit > 0 && self.server.max_connections == 0 {
+            warnings.push(format!(
+                "connection_rate_limit = {} with max_connections = 0: new connections are rate limited but their total is not",
+                self.server.connection_rate_limit
+            ));
         }
-        result
+        if self.server.min_buffer_size > self.server.buffer_size {
+            warnings.push(format!(
+                "min_buffer_size ({}) is above buffer_size ({}): buffers stay at buffer_size",
+                self.server.min_buffer_size, self.server.buffer_size
+            ));
+        }
+        let tls_paths_set =
+            !self.security.tls_cert_path.is_empty() || !self.security.tls_key_path.is_empty();
+        if tls_paths_set && !self.security.tls_enabled {
+            warnings.push("TLS paths are set but tls_enabled = false: connections are plaintext".to_string());
+        }
+        warnings
     }
 
-    pub fn len(&self) -> usize {
-        self.shards.iter().map(|s| s.len()).sum()
+    // Autosave points (validate() has already checked they parse)
+    pub fn save_points(&self) -> Vec<SavePoint> {
+        parse_save_points(&self.persistence.save).unwrap_or_default()
     }
 
-    pub fn clear(&self) {
-        for shard in &self.shards {
-            shard.clear();
+    pub fn entry_limits(&self) -> EntryLimits {
+        EntryLimits {
+            list: self.memory.list_max_entries,
+            set: self.memory.set_max_entries,
+            zset: self.memory.zset_max_entries,
         }
     }
-}
 
-// ==================== Helper Functions ====================
+    /// Redis parameter names and values for CONFIG GET. Settings with a
+    /// Redistill equivalent report it; the rest report what a client should
+    /// assume here (no AOF, no keyspace events).
+    pub fn parameters(&self) -> Vec<(&'static str, String)> {
+        let snapshot = std::path::Path::new(&self.persistence.snapshot_path);
+        let dir = match snapshot.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => std::env::current_dir().unwrap_or_default(),
+        };
+        let dbfilename = snapshot
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let port = match self.server.port {
+            0 => BOUND_PORT.load(Ordering::Relaxed),
+            port => port,
+        };
+        let tls_port = if self.security.tls_enabled { port } else { 0 };
 
-#[inline(always)]
-pub fn get_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
+        vec![
+            ("bind", self.server.bind.clone()),
+            ("port", port.to_string()),
+            ("tls-port", tls_port.to_string()),
+            ("databases", self.server.databases.to_string()),
+            ("maxclients", self.server.max_connections.to_string()),
+            ("timeout", self.server.connection_timeout.to_string()),
+            ("tcp-keepalive", self.performance.tcp_keepalive.to_string()),
+            ("proto-max-bulk-len", MAX_STRING_LEN.to_string()),
+            ("proto-max-inline-len", self.server.max_inline_len.to_string()),
+            ("maxmemory", self.memory.max_memory.to_string()),
+            ("maxmemory-policy", self.memory.eviction_policy.clone()),
+            ("maxmemory-samples", self.memory.eviction_sample_size.to_string()),
+            ("maxmemory-clients", self.memory.max_memory_clients.to_string()),
+            ("set-max-intset-entries", self.memory.set_max_intset_entries.to_string()),
+            ("list-max-listpack-size", self.memory.list_max_listpack_size.to_string()),
+            ("list-max-entries", self.memory.list_max_entries.to_string()),
+            ("set-max-entries", self.memory.set_max_entries.to_string()),
+            ("zset-max-entries", self.memory.zset_max_entries.to_string()),
+            ("loglevel", self.logging.level.clone()),
+            ("dir", dir.to_string_lossy().into_owned()),
+            ("dbfilename", dbfilename),
+            ("save", self.persistence.save.clone()),
+            ("appendonly", "no".to_string()),
+            ("appendfsync", "everysec".to_string()),
+            ("cluster-enabled", "no".to_string()),
+            (
+                "enable-debug-command",
+                if self.security.enable_debug_command { "yes" } else { "no" }.to_string(),
+            ),
+            ("replica-read-only", "yes".to_string()),
+            ("notify-keyspace-events", String::new()),
+            ("protected-mode", "no".to_string()),
+            ("daemonize", "no".to_string()),
+            ("io-threads", "1".to_string()),
+            ("hz", "10".to_string()),
+            ("latency-monitor-threshold", self.server.latency_monitor_threshold.to_string()),
+            ("slowlog-log-slower-than", "10000".to_string()),
+            ("slowlog-max-len", "128".to_string()),
+        ]
+    }
+
+    /// Write this config to `path` as TOML, one table per section. The file
+    /// is written beside the target and renamed over it, so readers never
+    /// see a partial config. Comments in the old file are not kept.
+    pub fn rewrite(&self, path: &str) -> io::Result<()> {
+        let contents = toml::to_string_pretty(self).map_err(io::Error::other)?;
+        let tmp_path = format!("{}.tmp", path);
+        let mut out = std::fs::File::create(&tmp_path)?;
+        io::Write::write_all(&mut out, contents.as_bytes())?;
+        out.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    }
+}
+
+// The validated configuration, set by the binary before anything reads
+// CONFIG. Without it (tests, embedders) CONFIG loads its own, and falls back
+// to the defaults if that does not validate.
+static LOADED_CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Install the configuration CONFIG serves. Returns false, leaving CONFIG
+/// as it was, once CONFIG has been read or another config set.
+pub fn set_config(config: Config) -> bool {
+    LOADED_CONFIG.set(config).is_ok()
+}
+
+// Global configuration
+pub static CONFIG: Lazy<&'static Config> = Lazy::new(|| {
+    LOADED_CONFIG.get_or_init(|| {
+        Config::load().unwrap_or_else(|e| {
+            eprintln!("⚠️  Invalid configuration in {}: {}, using defaults", Config::path(), e);
+            Config::default()
+        })
+    })
+});
+
+// ==================== Runtime Settings ====================
+
+// Settings a SIGHUP reload can change while the server runs. They start out
+// as CONFIG's values, and code that must follow a reload reads them here.
+pub static MAX_MEMORY: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(CONFIG.memory.max_memory));
+pub static EVICTION_POLICY: Lazy<AtomicU8> = Lazy::new(|| {
+    AtomicU8::new(EvictionPolicy::from_str(&CONFIG.memory.eviction_policy) as u8)
+});
+pub static PASSWORD: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(CONFIG.security.password.clone()));
+pub static CONNECTION_RATE_LIMIT: Lazy<AtomicU64> =
+    Lazy::new(|| AtomicU64::new(CONFIG.server.connection_rate_limit));
+pub static LOG_LEVEL: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(log_rank(&CONFIG.logging.level)));
+
+// Settings applied by a reload. The TLS and accept_* ones are applied by
+// the accept loop, which owns the TLS acceptor and the throttle.
+pub const RELOADABLE_SETTINGS: &[&str] = &[
+    "memory.max_memory",
+    "memory.eviction_policy",
+    "security.password",
+    "server.connection_rate_limit",
+    "server.accept_rate",
+    "server.accept_burst",
+    "logging.level",
+    "security.tls_cert_path",
+    "security.tls_key_path",
+    "security.tls_alpn_protocols",
+    "security.tls_sni",
+];
 
-// Calculate approximate size of an entry
 #[inline(always)]
-pub fn entry_size(key_len: usize, value_len: usize) -> usize {
-    key_len + value_len + 64 // ~64 bytes overhead for Arc, Entry struct, etc.
+pub fn max_memory() -> u64 {
+    MAX_MEMORY.load(Ordering::Relaxed)
 }
 
-// Format bytes as human-readable string
-pub fn format_bytes(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = 1024 * KB;
-    const GB: u64 = 1024 * MB;
+pub fn eviction_policy() -> EvictionPolicy {
+    EvictionPolicy::ALL[EVICTION_POLICY.load(Ordering::Relaxed) as usize]
+}
 
-    if bytes >= GB {
-        format!("{:.2}GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.2}MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.2}KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{}B", bytes)
+// CONFIG with the reloadable settings at their current values, for CONFIG
+// GET and CONFIG REWRITE
+pub fn live_config() -> Config {
+    let mut config = CONFIG.clone();
+    config.memory.max_memory = max_memory();
+    config.memory.eviction_policy = eviction_policy().as_str().to_string();
+    config.security.password = PASSWORD.read().clone();
+    config.server.connection_rate_limit = CONNECTION_RATE_LIMIT.load(Ordering::Relaxed);
+    config.logging.level = LOG_LEVELS[LOG_LEVEL.load(Ordering::Relaxed)].to_string();
+    config
+}
+
+// "section.key" for every setting whose value differs between two configs
+pub fn changed_settings(old: &Config, new: &Config) -> Vec<String> {
+    let (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) =
+        (toml::Value::try_from(old), toml::Value::try_from(new))
+    else {
+        return Vec::new();
+    };
+    let mut changed = Vec::new();
+    for (section, values) in &new {
+        let (toml::Value::Table(values), Some(toml::Value::Table(before))) = (values, old.get(section)) else {
+            continue;
+        };
+        for (key, value) in values {
+            if before.get(key) != Some(value) {
+                changed.push(format!("{}.{}", section, key));
+            }
+        }
     }
+    changed
 }
 
-// Fast byte comparison helpers
-#[inline(always)]
-pub fn eq_ignore_case_3(a: &[u8], b: &[u8; 3]) -> bool {
-    a.len() == 3 && (a[0] | 0x20) == b[0] && (a[1] | 0x20) == b[1] && (a[2] | 0x20) == b[2]
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigReload {
+    pub applied: Vec<String>,          // "setting: old -> new" (passwords are not shown)
+    pub restart_required: Vec<String>, // Differ from the config the server started with
 }
 
-#[inline(always)]
-pub fn eq_ignore_case_6(a: &[u8], b: &[u8; 6]) -> bool {
-    a.len() == 6
-        && (a[0] | 0x20) == b[0]
-        && (a[1] | 0x20) == b[1]
-        && (a[2] | 0x20) == b[2]
-        && (a[3] | 0x20) == b[3]
-        && (a[4] | 0x20) == b[4]
-        && (a[5] | 0x20) == b[5]
+/// Apply the reloadable settings of a freshly loaded config. Everything
+/// else that differs from the config the server started with is listed as
+/// needing a restart. Turning on a max_memory limit counts the keys already
+/// stored, since memory is not tracked while there is no limit.
+pub fn apply_config_reload(dbs: &[ShardedStore], new: &Config) -> ConfigReload {
+    let mut applied = Vec::new();
+
+    let (old_max, new_max) = (max_memory(), new.memory.max_memory);
+    if old_max != new_max {
+        if old_max == 0 {
+            MEMORY_USED.store(dataset_size(dbs), Ordering::Relaxed);
+        }
+        MAX_MEMORY.store(new_max, Ordering::Relaxed);
+        applied.push(format!("memory.max_memory: {} -> {}", old_max, new_max));
+    }
+    let policy = EvictionPolicy::from_str(&new.memory.eviction_policy);
+    if policy != eviction_policy() {
+        applied.push(format!(
+            "memory.eviction_policy: {} -> {}",
+            eviction_policy().as_str(),
+            policy.as_str()
+        ));
+        EVICTION_POLICY.store(policy as u8, Ordering::Relaxed);
+    }
+    let mut password = PASSWORD.write();
+    if *password != new.security.password {
+        password.clone_from(&new.security.password);
+        applied.push("security.password".to_string());
+    }
+    drop(password);
+    let rate_limit = CONNECTION_RATE_LIMIT.swap(new.server.connection_rate_limit, Ordering::Relaxed);
+    if rate_limit != new.server.connection_rate_limit {
+        applied.push(format!(
+            "server.connection_rate_limit: {} -> {}",
+            rate_limit, new.server.connection_rate_limit
+        ));
+    }
+    let level = LOG_LEVEL.swap(log_rank(&new.logging.level), Ordering::Relaxed);
+    if level != log_rank(&new.logging.level) {
+        applied.push(format!(
+            "logging.level: {} -> {}",
+            LOG_LEVELS[level],
+            LOG_LEVELS[log_rank(&new.logging.level)]
+        ));
+    }
+
+    let restart_required = changed_settings(&CONFIG, new)
+        .into_iter()
+        .filter(|name| !RELOADABLE_SETTINGS.contains(&name.as_str()))
+        .collect();
+    ConfigReload { applied, restart_required }
 }
 
-// Connection state for authentication
-pub struct ConnectionState {
-    pub authenticated: bool,
+// Memory accounted for every key, as the write paths would have counted it
+pub fn dataset_size(dbs: &[ShardedStore]) -> u64 {
+    let mut total = 0;
+    for shard in dbs.iter().flat_map(|store| store.shards.iter()) {
+        for entry in shard.iter() {
+            total += entry_size(entry.key().len(), entry.value().value.mem_size()) as u64;
+        }
+    }
+    total
 }
 
-impl ConnectionState {
+/// SIGHUP as a stream of reload requests. On platforms without it, recv()
+/// never completes.
+pub struct ReloadSignal {
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl ReloadSignal {
     pub fn new() -> Self {
         Self {
-            // If no password is set, authentication is not required
-            authenticated: true, // Default to true for testing
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok(),
+        }
+    }
+
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(hangup) = self.hangup.as_mut() {
+            hangup.recv().await;
+            return;
         }
+        std::future::pending::<()>().await
     }
 }
 
-// Eviction stub for testing (actual implementation uses global config)
+impl Default for ReloadSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Global allocator the binary is built with (see main.rs), for INFO
+pub const MEM_ALLOCATOR: &str = if cfg!(all(feature = "jemalloc", not(target_env = "msvc"))) {
+    "jemalloc"
+} else {
+    "libc"
+};
+
+// ASCII art printed at startup and returned by LOLWUT
+pub const BANNER: &str = r#" /$$$$$$$                  /$$ /$$             /$$     /$$ /$$ /$$
+| $$__  $$                | $$|__/            | $$    |__/| $$| $$
+| $$  \ $$  /$$$$$$   /$$$$$$$ /$$  /$$$$$$$ /$$$$$$   /$$| $$| $$
+| $$$$$$$/ /$$__  $$ /$$__  $$| $$ /$$_____/|_  $$_/  | $$| $$| $$
+| $$__  $$| $$$$$$$$| $$  | $$| $$|  $$$$$$   | $$    | $$| $$| $$
+| $$  \ $$| $$_____/| $$  | $$| $$ \____  $$  | $$ /$$| $$| $$| $$
+| $$  | $$|  $$$$$$$|  $$$$$$$| $$ /$$$$$$$/  |  $$$$/| $$| $$| $$
+|__/  |__/ \_______/ \_______/|__/|_______/    \___/  |__/|__/|__/"#;
+
+// Global metrics
+pub static TOTAL_COMMANDS: AtomicU64 = AtomicU64::new(0);
+pub static TOTAL_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+pub static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Memory tracking (approximate)
+pub static MEMORY_USED: AtomicU64 = AtomicU64::new(0);
+pub static MEMORY_PEAK: AtomicU64 = AtomicU64::new(0); // Highest MEMORY_USED since start or CONFIG RESETSTAT
+pub static EVICTED_KEYS: AtomicU64 = AtomicU64::new(0);
+pub static EXPIRED_KEYS: AtomicU64 = AtomicU64::new(0); // Removed past their TTL, lazily or by the sweeper
+pub static KEYSPACE_HITS: AtomicU64 = AtomicU64::new(0); // String reads that found the key
+pub static KEYSPACE_MISSES: AtomicU64 = AtomicU64::new(0); // String reads of a missing or expired key
+pub static CLIENT_OUTPUT_BYTES: AtomicU64 = AtomicU64::new(0); // Reply bytes waiting to be sent, all clients
+pub static EVICTED_CLIENTS: AtomicU64 = AtomicU64::new(0); // Closed for memory.max_memory_clients
+pub static EVICTING: AtomicBool = AtomicBool::new(false); // A bulk eviction pass is running
+pub static BGSAVE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+pub static DIRTY: AtomicU64 = AtomicU64::new(0); // Writes since the last successful save
+pub static LAST_SAVE: AtomicU64 = AtomicU64::new(0); // Unix time of the last successful save, or of startup
+pub static LAST_SAVE_OK: AtomicBool = AtomicBool::new(true);
+pub static LOADING: AtomicBool = AtomicBool::new(true); // Startup snapshot load not finished
+pub static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+// Set once shutdown starts. Connections parked waiting for a command watch it
+// so they can close instead of holding up the drain.
+pub static SHUTDOWN: Lazy<tokio::sync::watch::Sender<bool>> =
+    Lazy::new(|| tokio::sync::watch::channel(false).0);
+pub static ACTIVE_EXPIRE: AtomicBool = AtomicBool::new(true); // Background sweeper on (DEBUG SET-ACTIVE-EXPIRE)
+pub static SAVE_KEY_DELAY_US: AtomicU64 = AtomicU64::new(0); // Pause per key written by SAVE/BGSAVE (DEBUG SET-SAVE-DELAY)
+pub static SERVER_START_TIME: AtomicU32 = AtomicU32::new(0);
+pub static BOUND_PORT: AtomicU16 = AtomicU16::new(0); // Port main's listener got, which port = 0 leaves to the OS
+
+// Port clients connect to: the configured one, or the ephemeral port picked
+// for port = 0 once the listener is bound
+pub fn server_port() -> u16 {
+    match BOUND_PORT.load(Ordering::Relaxed) {
+        0 => CONFIG.server.port,
+        bound => bound,
+    }
+}
+
+// Account bytes added to the dataset and raise the peak to match
 #[inline(always)]
-pub fn evict_if_needed(_store: &ShardedStore, _needed_size: usize) -> bool {
-    // In tests with default config (max_memory = 0), always allow
-    true
+pub fn add_memory_used(bytes: u64) {
+    let used = MEMORY_USED.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    MEMORY_PEAK.fetch_max(used, Ordering::Relaxed);
+}
+
+// Connection rate limiting
+pub static LAST_CONNECTION_CHECK: AtomicU64 = AtomicU64::new(0);
+pub static CONNECTIONS_THIS_SECOND: AtomicU64 = AtomicU64::new(0);
+pub static REJECTED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+pub static START_TIME: Lazy<SystemTime> = Lazy::new(SystemTime::now);
+
+// Replication identity shown in INFO replication. There are no replicas yet;
+// the offset still advances per write so lag can be computed once there are.
+pub static REPL_ID: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(new_repl_id()));
+pub static REPL_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// Buffer pool for zero-allocation response writing. Pooled buffers are the
+// small size every connection starts from; see BufferSizer.
+pub static BUFFER_POOL: Lazy<SegQueue<Vec<u8>>> = Lazy::new(|| {
+    let pool = SegQueue::new();
+    for _ in 0..CONFIG.server.buffer_pool_size {
+        pool.push(Vec::with_capacity(min_buffer_size()));
+    }
+    pool
+});
+
+#[inline(always)]
+pub fn get_buffer() -> Vec<u8> {
+    BUFFER_POOL
+        .pop()
+        .unwrap_or_else(|| Vec::with_capacity(min_buffer_size()))
+}
+
+#[inline(always)]
+pub fn return_buffer(mut buf: Vec<u8>) {
+    buf.clear();
+    buf.shrink_to(min_buffer_size());
+    BUFFER_POOL.push(buf);
+}
+
+// The starting buffer size, never above buffer_size
+#[inline(always)]
+pub fn min_buffer_size() -> usize {
+    CONFIG.server.min_buffer_size.min(CONFIG.server.buffer_size)
+}
+
+// Consecutive reads or flushes using at most a quarter of the target before
+// the target halves
+pub const BUFFER_SHRINK_AFTER: u32 = 16;
+
+// How long a connection waits on its client before its buffers drop back to
+// min_buffer_size
+pub const BUFFER_IDLE_RELEASE: Duration = Duration::from_secs(1);
+
+// Tracks how much buffer a connection actually uses. The target jumps up to
+// the next power of two as soon as one read or flush needs more, so a busy
+// connection reaches its working size in a few round trips, and only halves
+// after BUFFER_SHRINK_AFTER quiet ones in a row, so a connection whose
+// traffic alternates between sizes is not reallocating every time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferSizer {
+    pub target: usize,
+    pub min: usize,
+    pub max: usize,
+    pub quiet: u32,
+}
+
+impl BufferSizer {
+    pub fn new(min: usize, max: usize) -> Self {
+        Self {
+            target: min,
+            min,
+            max: max.max(min),
+            quiet: 0,
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(min_buffer_size(), CONFIG.server.buffer_size)
+    }
+
+    // Record one read or flush of `used` bytes
+    #[inline]
+    pub fn observe(&mut self, used: usize) {
+        if used >= self.target {
+            self.target = (used + 1).next_power_of_two().clamp(self.min, self.max);
+            self.quiet = 0;
+        } else if used <= self.target / 4 {
+            self.quiet += 1;
+            if self.quiet >= BUFFER_SHRINK_AFTER {
+                self.target = (self.target / 2).max(self.min);
+                self.quiet = 0;
+            }
+        } else {
+            self.quiet = 0;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.target = self.min;
+        self.quiet = 0;
+    }
+}
+
+// Error for commands run against a key holding the wrong kind of value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WrongTypeError;
+
+impl WrongTypeError {
+    pub const MESSAGE: &'static [u8] =
+        b"WRONGTYPE Operation against a key holding the wrong kind of value";
+}
+
+// Why a writing BITFIELD failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitfieldError {
+    WrongType,
+    TooLarge, // The result would pass memory.max_value_size
+}
+
+// Why an INCR-family update failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegerError {
+    WrongType,
+    NotInteger,
+    Overflow,
+}
+
+// Why a push or add to a list, set or sorted set failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollectionError {
+    WrongType,
+    TooManyEntries(&'static str), // Would pass the store's EntryLimits; holds the type name
+}
+
+impl CollectionError {
+    pub fn reply(self) -> Reply {
+        match self {
+            CollectionError::WrongType => Reply::wrongtype(),
+            CollectionError::TooManyEntries(type_name) => {
+                Reply::error(format!("{} exceeds maximum allowed entries", type_name).as_bytes())
+            }
+        }
+    }
+}
+
+// A command ran out of server.command_timeout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedOut;
+
+impl TimedOut {
+    pub const MESSAGE: &'static [u8] = b"command exceeded time limit";
+}
+
+// Items visited between clock reads in CommandBudget::tick
+pub const BUDGET_CHECK_INTERVAL: usize = 1024;
+
+// Time limit for a command that walks a whole keyspace or collection. Its
+// loops call `tick` once per item and stop with TimedOut when it fails.
+// They only read, so stopping early leaves nothing half done.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandBudget {
+    pub deadline: Option<Instant>,
+    pub ticks: usize,
+}
+
+impl CommandBudget {
+    pub fn new(limit: Option<Duration>) -> Self {
+        Self {
+            deadline: limit.map(|limit| Instant::now() + limit),
+            ticks: 0,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None)
+    }
+
+    // A budget of server.command_timeout from now
+    pub fn start() -> Self {
+        let timeout = CONFIG.server.command_timeout;
+        Self::new((timeout > 0).then(|| Duration::from_millis(timeout)))
+    }
+
+    #[inline(always)]
+    pub fn tick(&mut self) -> Result<(), TimedOut> {
+        self.ticks += 1;
+        match self.deadline {
+            Some(deadline)
+                if self.ticks.is_multiple_of(BUDGET_CHECK_INTERVAL) && Instant::now() >= deadline =>
+            {
+                Err(TimedOut)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn collect<T>(&mut self, items: impl Iterator<Item = T>) -> Result<Vec<T>, TimedOut> {
+        let mut collected = Vec::with_capacity(items.size_hint().0);
+        for item in items {
+            self.tick()?;
+            collected.push(item);
+        }
+        Ok(collected)
+    }
+}
+
+// Typed value stored under a key. `Int` is a string whose bytes are the
+// canonical decimal form of an i64 (Redis' "int" encoding): it needs no heap
+// allocation and INCR updates it in place. Strings reach it through
+// `Value::string`, so commands never see the difference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    Int(i64),
+    List(ListValue),
+    Set(SetValue),
+    ZSet(SortedSet),
+}
+
+// Longest string OBJECT ENCODING reports as `embstr` rather than `raw`
+// (Redis' OBJ_ENCODING_EMBSTR_SIZE_LIMIT)
+pub const EMBSTR_SIZE_LIMIT: usize = 44;
+
+impl Value {
+    // A string value, int-encoded when it reads back byte for byte the same
+    #[inline(always)]
+    pub fn string(value: Bytes) -> Self {
+        match canonical_i64(&value) {
+            Some(n) => Value::Int(n),
+            None => Value::String(value),
+        }
+    }
+
+    // Name reported by the TYPE command
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) | Value::Int(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+        }
+    }
+
+    // Redis encoding name reported by OBJECT ENCODING and DEBUG OBJECT
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(s) if canonical_i64(s).is_some() => "int",
+            Value::Int(_) => "int",
+            Value::String(s) if s.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            Value::String(_) => "raw",
+            Value::List(list) => list.encoding(),
+            Value::Set(set) => set.encoding(),
+            Value::ZSet(_) => "skiplist",
+        }
+    }
+
+    // Payload size used for memory accounting
+    #[inline(always)]
+    pub fn mem_size(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            // Counted as its decimal form, so accounting matches the string it stands for
+            Value::Int(n) => decimal_len(*n),
+            Value::List(list) => list.mem_size(),
+            Value::Set(set) => set.mem_size(),
+            Value::ZSet(zset) => zset.scores.keys().map(|m| SortedSet::member_size(m)).sum(),
+        }
+    }
+
+    // True for a list, set or sorted set with nothing left in it
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            Value::String(_) | Value::Int(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::ZSet(zset) => zset.is_empty(),
+        }
+    }
+
+    // Member count for values that take part in set intersections:
+    // SINTERCARD accepts sets only, ZINTERCARD sorted sets and sets
+    pub fn member_count(&self, allow_zset: bool) -> Option<usize> {
+        match self {
+            Value::Set(set) => Some(set.len()),
+            Value::ZSet(zset) if allow_zset => Some(zset.len()),
+            _ => None,
+        }
+    }
+
+    // A list's elements, or a set's or sorted set's members (SORT's input)
+    pub fn elements(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Value::List(list) => list.iter(),
+            Value::Set(set) => set.iter(),
+            Value::ZSet(zset) => Box::new(zset.iter().map(|(member, _)| member.clone())),
+            Value::String(_) | Value::Int(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    pub fn has_member(&self, member: &[u8]) -> bool {
+        match self {
+            Value::Set(set) => set.contains(member),
+            Value::ZSet(zset) => zset.score(member).is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn members(&self) -> Vec<Bytes> {
+        match self {
+            Value::Set(set) => set.members(),
+            Value::ZSet(zset) => zset.iter().map(|(member, _)| member.clone()).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Byte limits for list_max_listpack_size -1 to -5, as in Redis
+pub const LISTPACK_SIZE_LIMITS: [usize; 5] = [4096, 8192, 16384, 32768, 65536];
+// Bytes a listpack spends per element besides the element itself
+pub const LISTPACK_ENTRY_HEADER: usize = 4;
+
+// Largest listpack allowed by memory.list_max_listpack_size, as (entries,
+// packed bytes); only one of the two is limited
+pub fn listpack_limit() -> (usize, usize) {
+    match CONFIG.memory.list_max_listpack_size {
+        max if max > 0 => (max as usize, usize::MAX),
+        level => (usize::MAX, LISTPACK_SIZE_LIMITS[(level.unsigned_abs() as usize).clamp(1, 5) - 1]),
+    }
+}
+
+// A small list packed into one buffer: each element is its length (u32,
+// little-endian) followed by its bytes, so there is no allocation per element
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListPack {
+    pub data: Vec<u8>,
+    pub len: usize,
+}
+
+impl ListPack {
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let mut rest = &self.data[..];
+        std::iter::from_fn(move || {
+            let (header, tail) = rest.split_first_chunk::<LISTPACK_ENTRY_HEADER>()?;
+            let (element, tail) = tail.split_at(u32::from_le_bytes(*header) as usize);
+            rest = tail;
+            Some(element)
+        })
+    }
+
+    pub fn push_back(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(value);
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, value: &[u8]) {
+        let header = (value.len() as u32).to_le_bytes();
+        self.data.splice(0..0, header.into_iter().chain(value.iter().copied()));
+        self.len += 1;
+    }
+
+    // Remove the first `count` elements (or the last, in pop order: last first)
+    pub fn pop(&mut self, count: usize, front: bool) -> Vec<Bytes> {
+        let count = count.min(self.len);
+        let skip = if front { 0 } else { self.len - count };
+        let mut popped: Vec<Bytes> = self.iter().skip(skip).take(count).map(Bytes::copy_from_slice).collect();
+        let removed: usize = popped.iter().map(|e| LISTPACK_ENTRY_HEADER + e.len()).sum();
+        if front {
+            self.data.drain(..removed);
+        } else {
+            self.data.truncate(self.data.len() - removed);
+            popped.reverse();
+        }
+        self.len -= count;
+        popped
+    }
+}
+
+// List elements in one of two encodings, as in Redis. A list starts as a
+// listpack and converts to a quicklist, a VecDeque<Bytes>, once it outgrows
+// memory.list_max_listpack_size. Pops that leave a quicklist at half the
+// limit or less pack it again, so a list hovering at the limit does not
+// convert back and forth on every push.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListValue {
+    ListPack(ListPack),
+    QuickList(VecDeque<Bytes>),
+}
+
+impl Default for ListValue {
+    fn default() -> Self {
+        ListValue::ListPack(ListPack::default())
+    }
+}
+
+impl ListValue {
+    pub fn from_elements(elements: impl IntoIterator<Item = Bytes>) -> Self {
+        let mut list = Self::default();
+        for element in elements {
+            list.push(element, false);
+        }
+        list
+    }
+
+    pub fn push(&mut self, value: Bytes, front: bool) {
+        if let ListValue::ListPack(packed) = self {
+            let (max_entries, max_bytes) = listpack_limit();
+            let bytes = packed.data.len() + LISTPACK_ENTRY_HEADER + value.len();
+            if packed.len < max_entries && bytes <= max_bytes {
+                if front {
+                    packed.push_front(&value);
+                } else {
+                    packed.push_back(&value);
+                }
+                return;
+            }
+            *self = ListValue::QuickList(self.iter().collect());
+        }
+        if let ListValue::QuickList(list) = self {
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+    }
+
+    // Remove up to `count` elements from the head (or tail), in pop order
+    pub fn pop(&mut self, count: usize, front: bool) -> Vec<Bytes> {
+        let popped = match self {
+            ListValue::ListPack(packed) => return packed.pop(count, front),
+            ListValue::QuickList(list) => {
+                let n = count.min(list.len());
+                if front {
+                    list.drain(..n).collect()
+                } else {
+                    list.drain(list.len() - n..).rev().collect()
+                }
+            }
+        };
+        if let ListValue::QuickList(list) = self
+            && Self::within_half_limit(list)
+        {
+            let mut packed = ListPack::default();
+            for element in list.iter() {
+                packed.push_back(element);
+            }
+            *self = ListValue::ListPack(packed);
+        }
+        popped
+    }
+
+    fn within_half_limit(list: &VecDeque<Bytes>) -> bool {
+        let (max_entries, max_bytes) = listpack_limit();
+        // Every element costs at least its header, which rules out long
+        // lists before their sizes are added up
+        if list.len() * 2 > max_entries || list.len() * LISTPACK_ENTRY_HEADER * 2 > max_bytes {
+            return false;
+        }
+        let bytes: usize = list.iter().map(|e| LISTPACK_ENTRY_HEADER + e.len()).sum();
+        bytes * 2 <= max_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ListValue::ListPack(packed) => packed.len,
+            ListValue::QuickList(list) => list.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            ListValue::ListPack(packed) => Box::new(packed.iter().map(Bytes::copy_from_slice)),
+            ListValue::QuickList(list) => Box::new(list.iter().cloned()),
+        }
+    }
+
+    // Elements `start..=stop`, already clamped to the list
+    pub fn range(&self, start: usize, stop: usize) -> Vec<Bytes> {
+        match self {
+            ListValue::ListPack(packed) => packed
+                .iter()
+                .skip(start)
+                .take(stop + 1 - start)
+                .map(Bytes::copy_from_slice)
+                .collect(),
+            ListValue::QuickList(list) => list.range(start..=stop).cloned().collect(),
+        }
+    }
+
+    // Element bytes, the same in either encoding so accounting survives a conversion
+    pub fn mem_size(&self) -> usize {
+        match self {
+            ListValue::ListPack(packed) => packed.data.len() - packed.len * LISTPACK_ENTRY_HEADER,
+            ListValue::QuickList(list) => list.iter().map(|item| item.len()).sum(),
+        }
+    }
+
+    // Redis encoding name reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            ListValue::ListPack(_) => "listpack",
+            ListValue::QuickList(_) => "quicklist",
+        }
+    }
 }
+
+// Set members in one of two encodings, as in Redis. A set holding only
+// integers starts as an intset, a sorted Vec<i64>, and converts itself to a
+// hash table when a non-integer member is added or it grows past
+// memory.set_max_intset_entries. It never converts back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetValue {
+    IntSet(Vec<i64>),
+    HashTable(HashSet<Bytes>),
+}
+
+impl Default for SetValue {
+    fn default() -> Self {
+        SetValue::IntSet(Vec::new())
+    }
+}
+
+impl SetValue {
+    // Integer an intset may hold in place of `member`: only the canonical
+    // spelling ("7", not "07" or "+7"), so SMEMBERS returns the same bytes
+    pub fn intset_member(member: &[u8]) -> Option<i64> {
+        let digits = member.strip_prefix(b"-").unwrap_or(member);
+        if digits.first() == Some(&b'0') && member.len() > 1 {
+            return None;
+        }
+        parse_i64(member)
+    }
+
+    /// Add a member, converting to a hash table if the intset can no longer
+    /// hold the set. Returns true if the member is new.
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if let SetValue::IntSet(ints) = self {
+            if let Some(n) = Self::intset_member(&member) {
+                let Err(pos) = ints.binary_search(&n) else {
+                    return false;
+                };
+                if ints.len() < CONFIG.memory.set_max_intset_entries {
+                    ints.insert(pos, n);
+                    return true;
+                }
+            }
+            *self = SetValue::HashTable(self.members().into_iter().collect());
+        }
+        match self {
+            SetValue::HashTable(set) => set.insert(member),
+            SetValue::IntSet(_) => unreachable!("converted above"),
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            SetValue::IntSet(ints) => match Self::intset_member(member).map(|n| ints.binary_search(&n)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            SetValue::HashTable(set) => set.remove(member),
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            SetValue::IntSet(ints) => {
+                Self::intset_member(member).is_some_and(|n| ints.binary_search(&n).is_ok())
+            }
+            SetValue::HashTable(set) => set.contains(member),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SetValue::IntSet(ints) => ints.len(),
+            SetValue::HashTable(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn members(&self) -> Vec<Bytes> {
+        self.iter().collect()
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            SetValue::IntSet(ints) => Box::new(ints.iter().map(|n| Bytes::from(n.to_string()))),
+            SetValue::HashTable(set) => Box::new(set.iter().cloned()),
+        }
+    }
+
+    // Member bytes, the same in either encoding so SREM accounting matches SADD
+    pub fn mem_size(&self) -> usize {
+        match self {
+            SetValue::IntSet(ints) => ints.iter().map(|n| decimal_len(*n)).sum(),
+            SetValue::HashTable(set) => set.iter().map(|member| member.len()).sum(),
+        }
+    }
+
+    // Redis encoding name reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            SetValue::IntSet(_) => "intset",
+            SetValue::HashTable(_) => "hashtable",
+        }
+    }
+}
+
+// Length of i64::MIN written in decimal
+pub const I64_MAX_DECIMAL_LEN: usize = 20;
+
+// Length of n written in decimal
+pub fn decimal_len(n: i64) -> usize {
+    let sign = usize::from(n < 0);
+    sign + n.unsigned_abs().checked_ilog10().map_or(1, |digits| digits as usize + 1)
+}
+
+// The i64 whose decimal form is exactly `value`: no sign other than '-', no
+// leading zeros, no "-0"
+#[inline(always)]
+pub fn canonical_i64(value: &[u8]) -> Option<i64> {
+    if value.is_empty() || value.len() > I64_MAX_DECIMAL_LEN {
+        return None;
+    }
+    parse_i64(value).filter(|&n| decimal_len(n) == value.len())
+}
+
+// Sorted-set score with a total order, so it can key a BTreeSet. NaN never
+// gets this far: scores are validated when parsed.
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.total_cmp(&other.0).is_eq()
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// Sorted set: members ordered by (score, member), plus a member -> score
+// index for updates and ZSCORE
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    pub scores: HashMap<Bytes, f64>,
+    pub ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    // Accounted size of one member: its bytes plus the score
+    #[inline(always)]
+    pub fn member_size(member: &[u8]) -> usize {
+        member.len() + std::mem::size_of::<f64>()
+    }
+
+    /// Add a member or update its score. Returns true if the member is new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.ordered.remove(&(Score(old), member.clone()));
+                self.ordered.insert((Score(score), member));
+                false
+            }
+            None => {
+                self.ordered.insert((Score(score), member));
+                true
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Members in score order, as (member, score).
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// Members ranked start..=stop (negative = from the end), lowest score first.
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(Bytes, f64)> {
+        let len = self.len() as i64;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+        if start > stop || start >= len {
+            return Vec::new();
+        }
+        self.iter()
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .map(|(member, score)| (member.clone(), score))
+            .collect()
+    }
+
+    /// Remove up to `count` members with the lowest (or highest) scores.
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<(Bytes, f64)> {
+        let mut popped = Vec::with_capacity(count.min(self.len()));
+        while popped.len() < count {
+            let next = if max {
+                self.ordered.pop_last()
+            } else {
+                self.ordered.pop_first()
+            };
+            let Some((Score(score), member)) = next else {
+                break;
+            };
+            self.scores.remove(&member);
+            popped.push((member, score));
+        }
+        popped
+    }
+}
+
+// Entry with Bytes for zero-copy
+pub struct Entry {
+    pub value: Value,
+    pub expiry: Option<u64>,
+    pub last_accessed: AtomicU32, // Seconds since server start (for LRU)
+}
+
+impl Entry {
+    pub fn new(value: Value, expiry: Option<u64>) -> Self {
+        Self {
+            value,
+            expiry,
+            last_accessed: AtomicU32::new(get_uptime_seconds()),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry.is_some_and(|expiry| now >= expiry)
+    }
+
+    // String accessor used by every string command to enforce WRONGTYPE uniformly
+    #[inline(always)]
+    pub fn as_string(&self) -> Result<Bytes, WrongTypeError> {
+        match &self.value {
+            Value::String(s) => Ok(s.clone()),
+            Value::Int(n) => Ok(integer_to_bytes(*n)),
+            _ => Err(WrongTypeError),
+        }
+    }
+
+    /// DEBUG OBJECT line. Reports `expired:1` for keys past their TTL that
+    /// nothing has removed yet, so tests can tell lazy from active expiry.
+    pub fn debug_object(&self, now: u64, uptime: u32) -> String {
+        let ttl = match self.expiry {
+            Some(expiry) => expiry.saturating_sub(now) as i64,
+            None => -1,
+        };
+        format!(
+            "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru_seconds_idle:{} ttl:{} expired:{}",
+            self.value.encoding(),
+            self.value.mem_size(),
+            uptime.saturating_sub(self.last_accessed.load(Ordering::Relaxed)),
+            ttl,
+            self.is_expired(now) as u8,
+        )
+    }
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            expiry: self.expiry,
+            last_accessed: AtomicU32::new(self.last_accessed.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// Per-shard index of keys by expiry time (memory.expiry_index).
+// Entries are hints: deleting a key or changing its TTL leaves the old entry
+// behind, and the sweeper discards it once it comes due.
+#[derive(Default)]
+pub struct ExpiryIndex {
+    by_time: Mutex<BTreeMap<u64, HashSet<Bytes>>>,
+}
+
+impl ExpiryIndex {
+    pub fn insert(&self, expiry: u64, key: Bytes) {
+        self.by_time.lock().entry(expiry).or_default().insert(key);
+    }
+
+    // Remove and return up to `limit` keys whose expiry is at or before `now`
+    pub fn pop_due(&self, now: u64, limit: usize) -> Vec<Bytes> {
+        let mut due = Vec::new();
+        let mut by_time = self.by_time.lock();
+
+        while due.len() < limit {
+            let Some(mut slot) = by_time.first_entry() else {
+                break;
+            };
+            if *slot.key() > now {
+                break;
+            }
+            let keys = slot.get_mut();
+            while due.len() < limit {
+                let Some(key) = keys.iter().next().cloned() else {
+                    break;
+                };
+                keys.remove(&key);
+                due.push(key);
+            }
+            if keys.is_empty() {
+                slot.remove();
+            }
+        }
+
+        due
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_time.lock().values().map(|keys| keys.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_time.lock().is_empty()
+    }
+}
+
+// Below this many keys a clear stays on the calling thread: spawning
+// workers would cost more than it saves
+pub const PARALLEL_CLEAR_MIN_KEYS: usize = 100_000;
+
+// Background thread that drops values a FLUSHALL/FLUSHDB ASYNC removed
+pub static RECLAIMER: Lazy<std::sync::mpsc::Sender<Vec<Value>>> = Lazy::new(|| {
+    let (sender, receiver) = std::sync::mpsc::channel::<Vec<Value>>();
+    std::thread::Builder::new()
+        .name("redistill-reclaim".into())
+        .spawn(move || receiver.into_iter().for_each(drop))
+        .expect("failed to spawn the reclaimer thread");
+    sender
+});
+
+// Drop values on the reclaimer thread (inline if it has gone away)
+pub fn reclaim_later(values: Vec<Value>) {
+    if let Err(std::sync::mpsc::SendError(values)) = RECLAIMER.send(values) {
+        drop(values);
+    }
+}
+
+// FLUSHALL/FLUSHDB option: Some(true) for ASYNC, Some(false) for SYNC or
+// no option, None for anything else
+pub fn flush_is_async(options: &[Bytes]) -> Option<bool> {
+    match options {
+        [] => Some(false),
+        [mode] if mode.eq_ignore_ascii_case(b"async") => Some(true),
+        [mode] if mode.eq_ignore_ascii_case(b"sync") => Some(false),
+        _ => None,
+    }
+}
+
+// Most entries a single collection may hold (0 = unlimited). Eviction
+// removes whole keys, so these are what stop one runaway LPUSH loop from
+// filling memory on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EntryLimits {
+    pub list: usize,
+    pub set: usize,
+    pub zset: usize,
+}
+
+impl EntryLimits {
+    // Whether a collection of `len` entries may grow by `adding`
+    #[inline(always)]
+    pub fn allows(limit: usize, len: usize, adding: usize) -> bool {
+        limit == 0 || len.saturating_add(adding) <= limit
+    }
+}
+
+// Sharded store with DashMap for lock-free reads
+pub struct ShardedStore {
+    pub shards: Vec<Arc<DashMap<Bytes, Entry>>>,
+    pub num_shards: usize,
+    pub expiry_index: Option<Arc<Vec<ExpiryIndex>>>,
+    pub rng: Option<Arc<Mutex<fastrand::Rng>>>, // None = fastrand's thread-local generator
+    pub entry_limits: EntryLimits,
+    // One per shard: MSET holds it exclusively while writing its pairs for
+    // that shard and MGET shared while reading its keys there
+    pub batch_locks: Arc<Vec<RwLock<()>>>,
+}
+
+impl ShardedStore {
+    pub fn new(num_shards: usize) -> Self {
+        let mut shards = Vec::with_capacity(num_shards);
+        for _ in 0..num_shards {
+            shards.push(Arc::new(DashMap::with_capacity(1000)));
+        }
+        Self {
+            shards,
+            num_shards,
+            expiry_index: None,
+            rng: None,
+            entry_limits: EntryLimits::default(),
+            batch_locks: Arc::new((0..num_shards).map(|_| RwLock::new(())).collect()),
+        }
+    }
+
+    /// Create a store for a secondary database. Shards start unallocated with
+    /// few inner locks, so idle databases cost little memory.
+    pub fn sparse(num_shards: usize) -> Self {
+        let shards = (0..num_shards)
+            .map(|_| Arc::new(DashMap::with_capacity_and_shard_amount(0, 4)))
+            .collect();
+        Self {
+            shards,
+            num_shards,
+            expiry_index: None,
+            rng: None,
+            entry_limits: EntryLimits::default(),
+            batch_locks: Arc::new((0..num_shards).map(|_| RwLock::new(())).collect()),
+        }
+    }
+
+    /// Create a store that also indexes keys by expiry time for the sweeper.
+    pub fn with_expiry_index(num_shards: usize) -> Self {
+        Self::new(num_shards).indexed()
+    }
+
+    /// Also index keys by expiry time for the sweeper.
+    pub fn indexed(self) -> Self {
+        let index = (0..self.num_shards).map(|_| ExpiryIndex::default()).collect();
+        Self {
+            expiry_index: Some(Arc::new(index)),
+            ..self
+        }
+    }
+
+    /// Draw eviction and sampling choices from a generator seeded with
+    /// `seed`, so the same operations pick the same keys every run.
+    pub fn seeded(self, seed: u64) -> Self {
+        Self {
+            rng: Some(Arc::new(Mutex::new(fastrand::Rng::with_seed(seed)))),
+            ..self
+        }
+    }
+
+    /// Refuse pushes and adds that would grow a collection past `limits`.
+    pub fn with_entry_limits(self, limits: EntryLimits) -> Self {
+        Self {
+            entry_limits: limits,
+            ..self
+        }
+    }
+
+    // Random number in 0..n
+    #[inline(always)]
+    pub fn random_below(&self, n: usize) -> usize {
+        match &self.rng {
+            Some(rng) => rng.lock().usize(..n),
+            None => fastrand::usize(..n),
+        }
+    }
+
+    /// Draw `count` keys uniformly from the whole store, as (shard index,
+    /// key) pairs. Each draw picks a position among all keys, so a shard is
+    /// chosen in proportion to its length and keys in crowded shards are as
+    /// likely to come up as keys in sparse ones. Costs a `len()` per shard,
+    /// then a walk through the chosen shard to the drawn position.
+    pub fn random_keys(&self, count: usize) -> Vec<(usize, Bytes)> {
+        let mut ends = Vec::with_capacity(self.num_shards);
+        let mut total = 0;
+        for shard in &self.shards {
+            total += shard.len();
+            ends.push(total);
+        }
+        if total == 0 {
+            return Vec::new();
+        }
+
+        let mut sample = Vec::with_capacity(count);
+        for _ in 0..count {
+            let pick = self.random_below(total);
+            let shard_idx = ends.partition_point(|&end| end <= pick);
+            let offset = pick - shard_idx.checked_sub(1).map_or(0, |prev| ends[prev]);
+            let shard = &self.shards[shard_idx];
+            let mut key = shard.iter().nth(offset).map(|entry| entry.key().clone());
+            if key.is_none() {
+                // The shard shrank since its length was read
+                key = shard.iter().next().map(|entry| entry.key().clone());
+            }
+            if let Some(key) = key {
+                sample.push((shard_idx, key));
+            }
+        }
+        sample
+    }
+
+    /// Remove up to `limit` due keys per shard using the expiry index.
+    /// Stale index entries are skipped. Returns the number of keys expired.
+    pub fn expire_indexed(&self, now: u64, limit: usize) -> usize {
+        let Some(index) = &self.expiry_index else {
+            return 0;
+        };
+        let mut expired_count = 0;
+
+        for (shard, shard_index) in self.shards.iter().zip(index.iter()) {
+            for key in shard_index.pop_due(now, limit) {
+                // Only remove if the key still holds an expired entry
+                if let Some((key, entry)) = shard.remove_if(&key, |_, e| e.is_expired(now)) {
+                    expired_count += 1;
+                    if max_memory() > 0 {
+                        let size = entry_size(key.len(), entry.value.mem_size());
+                        MEMORY_USED.fetch_sub(size as u64, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        expired_count
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.clone(),
+            num_shards: self.num_shards,
+            expiry_index: self.expiry_index.clone(),
+            rng: self.rng.clone(),
+            entry_limits: self.entry_limits,
+            batch_locks: self.batch_locks.clone(),
+        }
+    }
+
+    /// Record a key's expiry in the secondary index (no-op when disabled).
+    #[inline(always)]
+    pub fn index_expiry(&self, key: &Bytes, expiry: u64) {
+        if let Some(index) = &self.expiry_index {
+            index[self.hash(key)].insert(expiry, key.clone());
+        }
+    }
+
+    // Fast AHash with hardware acceleration (AES-NI). The whole key is hashed:
+    // `{...}` hash tags mean nothing here. The result depends on num_shards
+    // and on whether the CPU has AES-NI, so clients should ask DEBUG SHARD-OF
+    // rather than compute it themselves.
+    #[inline(always)]
+    pub fn hash(&self, key: &[u8]) -> usize {
+        let mut hasher = AHasher::default();
+        hasher.write(key);
+        hasher.finish() as usize % self.num_shards
+    }
+
+    /// Set a key-value pair. Returns the old entry's size if it existed (for memory tracking).
+    #[inline(always)]
+    pub fn set(&self, key: Bytes, value: Bytes, ttl: Option<u64>, now: u64) -> Option<usize> {
+        let expiry = ttl.map(|s| now + s);
+        let key_len = key.len();
+        let shard = &self.shards[self.hash(&key)];
+        if let Some(expiry) = expiry {
+            self.index_expiry(&key, expiry);
+        }
+        
+        // Stamp every write: a zero timestamp would make the newest keys
+        // look like the least recently used ones to the LRU sampler
+        // insert() returns the old value atomically - no race condition
+        let old_entry = shard.insert(
+            key,
+            Entry {
+                value: Value::string(value),
+                expiry,
+                last_accessed: AtomicU32::new(get_uptime_seconds()),
+            },
+        );
+        
+        // Return old entry size for memory tracking
+        old_entry.map(|e| entry_size(key_len, e.value.mem_size()))
+    }
+
+    /// Store `value` under `key` with no expiry, replacing whatever the key
+    /// held. Returns the replaced entry's size for memory tracking.
+    pub fn put(&self, key: Bytes, value: Value) -> Option<usize> {
+        let key_len = key.len();
+        let shard = &self.shards[self.hash(&key)];
+        let old_entry = shard.insert(key, Entry::new(value, None));
+        old_entry.map(|e| entry_size(key_len, e.value.mem_size()))
+    }
+
+    /// Give a live key the absolute expiry `expiry` (unix seconds) if
+    /// `condition` allows it, deleting the key when that time has already
+    /// come. Returns true if the key was changed.
+    pub fn expire_at(&self, key: &Bytes, expiry: u64, condition: ExpireCondition, now: u64) -> bool {
+        let shard = &self.shards[self.hash(key)];
+        let Some(mut entry) = shard.get_mut(key.as_ref()) else {
+            return false;
+        };
+        if entry.is_expired(now) {
+            drop(entry);
+            if let Some((key, entry)) = shard.remove_if(key.as_ref(), |_, e| e.is_expired(now)) {
+                let size = expired_entry_size(key.len(), entry.value.mem_size());
+                if max_memory() > 0 {
+                    MEMORY_USED.fetch_sub(size as u64, Ordering::Relaxed);
+                }
+            }
+            return false;
+        }
+        if !condition.allows(entry.expiry, expiry) {
+            return false;
+        }
+        if expiry > now {
+            entry.expiry = Some(expiry);
+            drop(entry);
+            self.index_expiry(key, expiry);
+            return true;
+        }
+
+        drop(entry);
+        let (count, bytes_freed) = self.delete(std::slice::from_ref(key));
+        if max_memory() > 0 && bytes_freed > 0 {
+            MEMORY_USED.fetch_sub(bytes_freed as u64, Ordering::Relaxed);
+        }
+        count > 0
+    }
+
+    /// Write several string values without TTLs, as MSET does, and return
+    /// the total size of the entries they replaced.
+    ///
+    /// Pairs are grouped by shard and each shard's group is written under
+    /// that shard's batch lock, taken once, in shard order. The sort is
+    /// stable, so a key given twice keeps the last value.
+    ///
+    /// Within one shard the batch is atomic for `get_many` (MGET): it sees
+    /// all of the shard's new values or none. Across shards it is not, so an
+    /// MGET may see one shard's part of an MSET and not another's. Plain
+    /// single-key reads do not take the lock and may see the keys change one
+    /// at a time, though never a torn value.
+    pub fn set_many(&self, pairs: Vec<(Bytes, Bytes)>) -> usize {
+        let mut grouped: Vec<(usize, Bytes, Bytes)> = pairs
+            .into_iter()
+            .map(|(key, value)| (self.hash(&key), key, value))
+            .collect();
+        grouped.sort_by_key(|(shard, _, _)| *shard);
+
+        let stamp = get_uptime_seconds();
+        let mut replaced = 0;
+        let mut pairs = grouped.into_iter().peekable();
+        while let Some(&(shard, _, _)) = pairs.peek() {
+            let _batch = self.batch_locks[shard].write();
+            while let Some((_, key, value)) = pairs.next_if(|(s, _, _)| *s == shard) {
+                let key_len = key.len();
+                let entry = Entry {
+                    value: Value::string(value),
+                    expiry: None,
+                    last_accessed: AtomicU32::new(stamp),
+                };
+                if let Some(old) = self.shards[shard].insert(key, entry) {
+                    replaced += entry_size(key_len, old.value.mem_size());
+                }
+            }
+        }
+        replaced
+    }
+
+    /// Read several string values, as MGET does; other types and missing
+    /// keys read as None. Keys of one shard are read under its batch lock,
+    /// so a `set_many` on that shard is seen whole or not at all.
+    pub fn get_many(&self, keys: &[Bytes], now: u64, touch: bool) -> Vec<Option<Bytes>> {
+        let mut order: Vec<(usize, usize)> =
+            keys.iter().enumerate().map(|(i, key)| (self.hash(key), i)).collect();
+        order.sort_unstable();
+
+        let mut values = vec![None; keys.len()];
+        let mut order = order.into_iter().peekable();
+        while let Some(&(shard, _)) = order.peek() {
+            let _batch = self.batch_locks[shard].read();
+            while let Some((_, i)) = order.next_if(|(s, _)| *s == shard) {
+                values[i] = self.lookup_string(&keys[i], now, touch).ok().flatten();
+            }
+        }
+        values
+    }
+
+    /// Get a string value. Keys holding other types read as missing (MGET semantics).
+    #[inline(always)]
+    pub fn get(&self, key: &[u8], now: u64) -> Option<Bytes> {
+        self.get_string(key, now).ok().flatten()
+    }
+
+    /// TYPE of a live key, or None if it is missing or expired.
+    pub fn value_type(&self, key: &[u8], now: u64) -> Option<&'static str> {
+        let shard = &self.shards[self.hash(key)];
+        let entry = shard.get(key)?;
+        (!entry.is_expired(now)).then(|| entry.value.type_name())
+    }
+
+    /// Get a string value, failing with WRONGTYPE if the key holds another type.
+    #[inline(always)]
+    pub fn get_string(&self, key: &[u8], now: u64) -> Result<Option<Bytes>, WrongTypeError> {
+        self.lookup_string(key, now, true)
+    }
+
+    /// Like `get_string`, but only counts as an access for LRU when `touch`
+    /// is set (CLIENT NO-TOUCH reads pass false).
+    #[inline(always)]
+    pub fn lookup_string(
+        &self,
+        key: &[u8],
+        now: u64,
+        touch: bool,
+    ) -> Result<Option<Bytes>, WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+
+        // Try read-only access first
+        if let Some(entry) = shard.get(key) {
+            if entry.is_expired(now) {
+                // Expired - need to remove
+                let key_len = key.len();
+                let value_len = entry.value.mem_size();
+                drop(entry);
+
+                // Only decrement memory if we actually removed the key
+                // This prevents double-decrement race with eviction
+                if shard.remove(key).is_some() {
+                    let size = expired_entry_size(key_len, value_len);
+                    if max_memory() > 0 {
+                        MEMORY_USED.fetch_sub(size as u64, Ordering::Relaxed);
+                    }
+                }
+                KEYSPACE_MISSES.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+
+            // Update access time approximately (90% skip for performance)
+            if touch {
+                maybe_update_access_time(self, &entry);
+            }
+
+            KEYSPACE_HITS.fetch_add(1, Ordering::Relaxed);
+            return entry.as_string().map(Some);
+        }
+        KEYSPACE_MISSES.fetch_add(1, Ordering::Relaxed);
+        Ok(None)
+    }
+
+    /// Replace the integer at `key` with `update(current)`, where a missing or
+    /// expired key counts as 0 and keeps no TTL, and a live key keeps its
+    /// own. An int-encoded value is changed in place without allocating; a
+    /// string that parses as an integer is stored int-encoded from then on.
+    /// Returns the new value.
+    pub fn update_integer(
+        &self,
+        key: &Bytes,
+        now: u64,
+        update: impl FnOnce(i64) -> Option<i64>,
+    ) -> Result<i64, IntegerError> {
+        let shard = &self.shards[self.hash(key)];
+        let mut created = false;
+        let mut entry = shard.entry(key.clone()).or_insert_with(|| {
+            created = true;
+            Entry::new(Value::Int(0), None)
+        });
+
+        let mut freed = 0;
+        if entry.is_expired(now) {
+            freed = expired_entry_size(key.len(), entry.value.mem_size());
+            *entry = Entry::new(Value::Int(0), None);
+            created = true;
+        }
+
+        let current = match &entry.value {
+            Value::Int(n) => *n,
+            Value::String(s) => parse_i64(s).ok_or(IntegerError::NotInteger)?,
+            _ => return Err(IntegerError::WrongType),
+        };
+        let Some(new) = update(current) else {
+            // Leave no placeholder behind for a key that did not exist
+            drop(entry);
+            if created {
+                shard.remove_if(key.as_ref(), |_, e| e.value == Value::Int(0) && e.expiry.is_none());
+            }
+            if max_memory() > 0 {
+                MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+            }
+            return Err(IntegerError::Overflow);
+        };
+
+        let old_size = entry.value.mem_size();
+        entry.value = Value::Int(new);
+        entry.last_accessed.store(get_uptime_seconds(), Ordering::Relaxed);
+
+        if max_memory() > 0 {
+            let added = if created {
+                entry_size(key.len(), decimal_len(new))
+            } else {
+                decimal_len(new)
+            };
+            let removed = freed + if created { 0 } else { old_size };
+            MEMORY_USED.fetch_sub(removed as u64, Ordering::Relaxed);
+            add_memory_used(added as u64);
+        }
+
+        Ok(new)
+    }
+
+    /// Run BITFIELD `ops` against the string at `key` while holding its
+    /// entry, so concurrent INCRBYs on the same field all count. A missing or
+    /// expired key starts empty; a live key keeps its TTL. Nothing is stored
+    /// when no write went through (all GETs, or OVERFLOW FAIL skipped them).
+    /// Returns one result per op and whether the value was written.
+    pub fn bitfield(
+        &self,
+        key: &Bytes,
+        ops: &[BitfieldOp],
+        max_value_size: u64,
+        now: u64,
+    ) -> Result<(Vec<Option<i64>>, bool), BitfieldError> {
+        let shard = &self.shards[self.hash(key)];
+        let mut created = false;
+        let mut entry = shard.entry(key.clone()).or_insert_with(|| {
+            created = true;
+            Entry::new(Value::String(Bytes::new()), None)
+        });
+
+        let mut freed = 0;
+        if entry.is_expired(now) {
+            freed = expired_entry_size(key.len(), entry.value.mem_size());
+            *entry = Entry::new(Value::String(Bytes::new()), None);
+            created = true;
+        }
+
+        // The results, and the new value if anything was written
+        let outcome = match entry.as_string() {
+            Ok(current) => {
+                let mut value = BytesMut::from(current.as_ref());
+                let results = bitfield_apply(&mut value, ops);
+                let written = ops
+                    .iter()
+                    .zip(&results)
+                    .any(|(op, result)| !matches!(op, BitfieldOp::Get(..)) && result.is_some());
+                if !written {
+                    Ok((results, None))
+                } else if value_size_allowed(value.len(), max_value_size) {
+                    Ok((results, Some(value)))
+                } else {
+                    Err(BitfieldError::TooLarge)
+                }
+            }
+            Err(_) => Err(BitfieldError::WrongType),
+        };
+
+        let (results, value) = match outcome {
+            Ok((results, Some(value))) => (results, value),
+            other => {
+                // Leave no placeholder behind for a key that did not exist
+                drop(entry);
+                if created {
+                    shard.remove_if(key.as_ref(), |_, e| {
+                        e.value == Value::String(Bytes::new()) && e.expiry.is_none()
+                    });
+                }
+                if max_memory() > 0 {
+                    MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+                }
+                return other.map(|(results, _)| (results, false));
+            }
+        };
+
+        let old_size = entry.value.mem_size();
+        let new_size = value.len();
+        entry.value = Value::String(value.freeze());
+        entry.last_accessed.store(get_uptime_seconds(), Ordering::Relaxed);
+
+        if max_memory() > 0 {
+            let added = if created { entry_size(key.len(), new_size) } else { new_size };
+            let removed = freed + if created { 0 } else { old_size };
+            MEMORY_USED.fetch_sub(removed as u64, Ordering::Relaxed);
+            add_memory_used(added as u64);
+        }
+
+        Ok((results, true))
+    }
+
+    /// Push values onto the head (or tail) of a list, creating it if missing.
+    /// Returns the new list length.
+    pub fn push(
+        &self,
+        key: &Bytes,
+        values: &[Bytes],
+        front: bool,
+        now: u64,
+    ) -> Result<usize, CollectionError> {
+        let shard = &self.shards[self.hash(key)];
+        let mut created = false;
+        let mut entry = shard.entry(key.clone()).or_insert_with(|| {
+            created = true;
+            Entry::new(Value::List(ListValue::default()), None)
+        });
+
+        // An expired key is replaced by a fresh list
+        let mut freed = 0;
+        if entry.is_expired(now) {
+            freed = expired_entry_size(key.len(), entry.value.mem_size());
+            *entry = Entry::new(Value::List(ListValue::default()), None);
+            created = true;
+        }
+
+        let list = match &mut entry.value {
+            Value::List(list) => list,
+            _ => return Err(CollectionError::WrongType),
+        };
+        if !EntryLimits::allows(self.entry_limits.list, list.len(), values.len()) {
+            drop(entry);
+            self.discard_created(key, created, freed);
+            return Err(CollectionError::TooManyEntries("list"));
+        }
+        let mut added = 0;
+        for value in values {
+            added += value.len();
+            list.push(value.clone(), front);
+        }
+        let len = list.len();
+
+        if max_memory() > 0 {
+            let size = if created {
+                entry_size(key.len(), added)
+            } else {
+                added
+            };
+            MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+            add_memory_used(size as u64);
+        }
+
+        Ok(len)
+    }
+
+    /// Return list elements between start and stop (inclusive, negative = from the end).
+    pub fn range(
+        &self,
+        key: &[u8],
+        start: i64,
+        stop: i64,
+        now: u64,
+    ) -> Result<Vec<Bytes>, WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+        let entry = match shard.get(key) {
+            Some(entry) if !entry.is_expired(now) => entry,
+            _ => return Ok(Vec::new()),
+        };
+        let list = match &entry.value {
+            Value::List(list) => list,
+            _ => return Err(WrongTypeError),
+        };
+
+        let len = list.len() as i64;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+        if start > stop || start >= len {
+            return Ok(Vec::new());
+        }
+        Ok(list.range(start as usize, stop as usize))
+    }
+
+    /// Pop up to `count` elements from the head (or tail) of a list.
+    pub fn pop(
+        &self,
+        key: &[u8],
+        count: usize,
+        front: bool,
+        now: u64,
+    ) -> Result<Vec<Bytes>, WrongTypeError> {
+        self.pop_with(key, now, |value| match value {
+            Value::List(list) => Ok(list.pop(count, front)),
+            _ => Err(WrongTypeError),
+        }, |item| item.len())
+    }
+
+    /// Pop up to `count` members with the lowest (or highest) scores.
+    pub fn zpop(
+        &self,
+        key: &[u8],
+        count: usize,
+        max: bool,
+        now: u64,
+    ) -> Result<Vec<(Bytes, f64)>, WrongTypeError> {
+        self.pop_with(key, now, |value| match value {
+            Value::ZSet(zset) => Ok(zset.pop(count, max)),
+            _ => Err(WrongTypeError),
+        }, |(member, _)| SortedSet::member_size(member))
+    }
+
+    /// Add members to a set, creating it if missing. Returns the number of
+    /// members that were new.
+    pub fn sadd(&self, key: &Bytes, members: &[Bytes], now: u64) -> Result<usize, CollectionError> {
+        let shard = &self.shards[self.hash(key)];
+        let mut created = false;
+        let mut entry = shard.entry(key.clone()).or_insert_with(|| {
+            created = true;
+            Entry::new(Value::Set(SetValue::default()), None)
+        });
+
+        // An expired key is replaced by a fresh set
+        let mut freed = 0;
+        if entry.is_expired(now) {
+            freed = expired_entry_size(key.len(), entry.value.mem_size());
+            *entry = Entry::new(Value::Set(SetValue::default()), None);
+            created = true;
+        }
+
+        let set = match &mut entry.value {
+            Value::Set(set) => set,
+            _ => return Err(CollectionError::WrongType),
+        };
+        let limit = self.entry_limits.set;
+        if !EntryLimits::allows(limit, set.len(), members.len()) {
+            let new: HashSet<&[u8]> = members
+                .iter()
+                .map(|m| m.as_ref())
+                .filter(|m| !set.contains(m))
+                .collect();
+            if !EntryLimits::allows(limit, set.len(), new.len()) {
+                drop(entry);
+                self.discard_created(key, created, freed);
+                return Err(CollectionError::TooManyEntries("set"));
+            }
+        }
+        let mut new_members = 0;
+        let mut added = 0;
+        for member in members {
+            if set.insert(member.clone()) {
+                new_members += 1;
+                added += member.len();
+            }
+        }
+
+        if max_memory() > 0 {
+            let size = if created {
+                entry_size(key.len(), added)
+            } else {
+                added
+            };
+            MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+            add_memory_used(size as u64);
+        }
+
+        Ok(new_members)
+    }
+
+    /// Remove members from a set. Returns the number actually removed.
+    pub fn srem(&self, key: &[u8], members: &[Bytes], now: u64) -> Result<usize, WrongTypeError> {
+        let removed = self.pop_with(key, now, |value| match value {
+            Value::Set(set) => Ok(members.iter().filter(|m| set.remove(m.as_ref())).collect()),
+            _ => Err(WrongTypeError),
+        }, |member: &&Bytes| member.len())?;
+        Ok(removed.len())
+    }
+
+    // Run a pop against a live collection, then delete the key if the pop
+    // left it empty (Redis never keeps empty collections around). Memory is
+    // accounted from the removed items, so a pop never walks the collection.
+    pub fn pop_with<T>(
+        &self,
+        key: &[u8],
+        now: u64,
+        pop: impl FnOnce(&mut Value) -> Result<Vec<T>, WrongTypeError>,
+        item_size: impl Fn(&T) -> usize,
+    ) -> Result<Vec<T>, WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+        let (popped, emptied) = {
+            let mut entry = match shard.get_mut(key) {
+                Some(entry) if !entry.is_expired(now) => entry,
+                _ => return Ok(Vec::new()),
+            };
+            let popped = pop(&mut entry.value)?;
+            (popped, entry.value.is_empty_collection())
+        };
+        let mut freed: usize = popped.iter().map(item_size).sum();
+
+        if emptied
+            && let Some((k, _)) = shard.remove_if(key, |_, entry| entry.value.is_empty_collection())
+        {
+            freed += entry_size(k.len(), 0);
+        }
+        if max_memory() > 0 {
+            MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+        }
+        Ok(popped)
+    }
+
+    /// Size of the intersection of the sets at `keys` (sorted sets too when
+    /// `allow_zset`), counting no further than `limit` (0 = no limit). The
+    /// intersection itself is never built: members of the smallest set are
+    /// filtered through the others, and the last pass stops at the limit.
+    pub fn inter_card(
+        &self,
+        keys: &[Bytes],
+        allow_zset: bool,
+        limit: usize,
+        now: u64,
+    ) -> Result<usize, WrongTypeError> {
+        // Type errors win over missing keys, as in Redis
+        let mut sizes = Vec::with_capacity(keys.len());
+        for key in keys {
+            let len = self
+                .read_value(key, now, |value| value.member_count(allow_zset).ok_or(WrongTypeError))
+                .transpose()?
+                .unwrap_or(0);
+            sizes.push((len, key));
+        }
+        if sizes.iter().any(|(len, _)| *len == 0) {
+            return Ok(0);
+        }
+        let limit = if limit == 0 { usize::MAX } else { limit };
+
+        // Smallest first, so the candidate list shrinks as fast as possible.
+        // A key that vanishes between passes empties the intersection.
+        sizes.sort_by_key(|(len, _)| *len);
+        let Some(mut candidates) = self.read_value(sizes[0].1, now, Value::members) else {
+            return Ok(0);
+        };
+        let Some(((_, last), middle)) = sizes[1..].split_last() else {
+            return Ok(candidates.len().min(limit));
+        };
+        for (_, key) in middle {
+            let filtered = self.read_value(key, now, |value| {
+                candidates.retain(|m| value.has_member(m));
+            });
+            if filtered.is_none() {
+                return Ok(0);
+            }
+        }
+        Ok(self
+            .read_value(last, now, |value| {
+                candidates
+                    .iter()
+                    .filter(|m| value.has_member(m))
+                    .take(limit)
+                    .count()
+            })
+            .unwrap_or(0))
+    }
+
+    // Run `read` against the live value at `key`, if there is one
+    pub fn read_value<T>(&self, key: &[u8], now: u64, read: impl FnOnce(&Value) -> T) -> Option<T> {
+        match self.shards[self.hash(key)].get(key) {
+            Some(entry) if !entry.is_expired(now) => Some(read(&entry.value)),
+            _ => None,
+        }
+    }
+
+    /// Add members to a sorted set, creating it if missing. Returns the
+    /// number of members that were new.
+    pub fn zadd(&self, key: &Bytes, members: &[(f64, Bytes)], now: u64) -> Result<usize, CollectionError> {
+        let shard = &self.shards[self.hash(key)];
+        let mut created = false;
+        let mut entry = shard.entry(key.clone()).or_insert_with(|| {
+            created = true;
+            Entry::new(Value::ZSet(SortedSet::default()), None)
+        });
+
+        // An expired key is replaced by a fresh sorted set
+        let mut freed = 0;
+        if entry.is_expired(now) {
+            freed = expired_entry_size(key.len(), entry.value.mem_size());
+            *entry = Entry::new(Value::ZSet(SortedSet::default()), None);
+            created = true;
+        }
+
+        let zset = match &mut entry.value {
+            Value::ZSet(zset) => zset,
+            _ => return Err(CollectionError::WrongType),
+        };
+        let limit = self.entry_limits.zset;
+        if !EntryLimits::allows(limit, zset.len(), members.len()) {
+            let new: HashSet<&[u8]> = members
+                .iter()
+                .map(|(_, m)| m.as_ref())
+                .filter(|m| zset.score(m).is_none())
+                .collect();
+            if !EntryLimits::allows(limit, zset.len(), new.len()) {
+                drop(entry);
+                self.discard_created(key, created, freed);
+                return Err(CollectionError::TooManyEntries("zset"));
+            }
+        }
+        let mut new_members = 0;
+        let mut added = 0;
+        for (score, member) in members {
+            if zset.insert(member.clone(), *score) {
+                new_members += 1;
+                added += SortedSet::member_size(member);
+            }
+        }
+
+        if max_memory() > 0 {
+            let size = if created {
+                entry_size(key.len(), added)
+            } else {
+                added
+            };
+            MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+            add_memory_used(size as u64);
+        }
+
+        Ok(new_members)
+    }
+
+    // A refused push or add leaves behind the empty collection it created
+    // for a missing or expired key; remove it, and count the expired value
+    // it replaced as freed
+    fn discard_created(&self, key: &Bytes, created: bool, freed: usize) {
+        if !created {
+            return;
+        }
+        let shard = &self.shards[self.hash(key)];
+        shard.remove_if(key, |_, entry| entry.value.is_empty_collection());
+        if max_memory() > 0 {
+            MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Run `read` against the sorted set at `key` (None if the key is missing).
+    pub fn with_zset<T>(
+        &self,
+        key: &[u8],
+        now: u64,
+        read: impl FnOnce(&SortedSet) -> T,
+    ) -> Result<Option<T>, WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+        let entry = match shard.get(key) {
+            Some(entry) if !entry.is_expired(now) => entry,
+            _ => return Ok(None),
+        };
+        match &entry.value {
+            Value::ZSet(zset) => Ok(Some(read(zset))),
+            _ => Err(WrongTypeError),
+        }
+    }
+
+    /// Delete keys. Returns (count_deleted, bytes_freed) for memory tracking.
+    #[inline(always)]
+    pub fn delete(&self, keys: &[Bytes]) -> (usize, usize) {
+        // Group by shard for efficiency
+        let mut shard_keys: Vec<Vec<&Bytes>> = vec![Vec::new(); self.num_shards];
+        for key in keys {
+            shard_keys[self.hash(key)].push(key);
+        }
+
+        let mut count = 0;
+        let mut bytes_freed = 0;
+        for (idx, keys_in_shard) in shard_keys.iter().enumerate() {
+            if !keys_in_shard.is_empty() {
+                let shard = &self.shards[idx];
+                for key in keys_in_shard {
+                    if let Some((k, entry)) = shard.remove(*key) {
+                        count += 1;
+                        bytes_freed += entry_size(k.len(), entry.value.mem_size());
+                    }
+                }
+            }
+        }
+        (count, bytes_freed)
+    }
+
+    /// Delete a string key only if it holds `expected` (lock release).
+    /// Returns the bytes freed when the key was deleted.
+    pub fn compare_and_delete(
+        &self,
+        key: &[u8],
+        expected: &[u8],
+        now: u64,
+    ) -> Result<Option<usize>, WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+        let mut wrong_type = false;
+        // remove_if runs the check under the shard's write lock, so no other
+        // client can swap the value between the compare and the delete
+        let removed = shard.remove_if(key, |_, entry| {
+            if entry.is_expired(now) {
+                return false;
+            }
+            match entry.as_string() {
+                Ok(value) => value.as_ref() == expected,
+                Err(_) => {
+                    wrong_type = true;
+                    false
+                }
+            }
+        });
+        if wrong_type {
+            return Err(WrongTypeError);
+        }
+        Ok(removed.map(|(k, entry)| entry_size(k.len(), entry.value.mem_size())))
+    }
+
+    /// GETDEL: remove a string key, returning its value and the bytes freed.
+    /// An expired entry is removed too but reads as missing. The expiry
+    /// check and the removal run under the shard's write lock, so GETDEL and
+    /// the sweeper cannot both act on one expired entry.
+    pub fn get_del(&self, key: &Bytes, now: u64) -> Result<(Option<Bytes>, usize), WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+        let dashmap::Entry::Occupied(occupied) = shard.entry(key.clone()) else {
+            return Ok((None, 0));
+        };
+        if occupied.get().is_expired(now) {
+            let (key, entry) = occupied.remove_entry();
+            return Ok((None, expired_entry_size(key.len(), entry.value.mem_size())));
+        }
+        let value = occupied.get().as_string()?;
+        let (key, entry) = occupied.remove_entry();
+        Ok((Some(value), entry_size(key.len(), entry.value.mem_size())))
+    }
+
+    /// GETEX: return a string key's value and optionally change its expiry
+    /// (`Some(None)` persists it). An expiry at or before `now` deletes the
+    /// key after reading it, and an expired entry is removed and reads as
+    /// missing, all under the shard's write lock. Returns the value and the
+    /// bytes freed.
+    pub fn get_ex(
+        &self,
+        key: &Bytes,
+        expiry: Option<Option<u64>>,
+        now: u64,
+    ) -> Result<(Option<Bytes>, usize), WrongTypeError> {
+        let shard = &self.shards[self.hash(key)];
+        let dashmap::Entry::Occupied(mut occupied) = shard.entry(key.clone()) else {
+            return Ok((None, 0));
+        };
+        if occupied.get().is_expired(now) {
+            let (key, entry) = occupied.remove_entry();
+            return Ok((None, expired_entry_size(key.len(), entry.value.mem_size())));
+        }
+        let value = Some(occupied.get().as_string()?);
+        match expiry {
+            Some(Some(at)) if at <= now => {}
+            Some(at) => {
+                occupied.get_mut().expiry = at;
+                drop(occupied);
+                if let Some(at) = at {
+                    self.index_expiry(key, at);
+                }
+                return Ok((value, 0));
+            }
+            None => return Ok((value, 0)),
+        }
+        let (key, entry) = occupied.remove_entry();
+        Ok((value, entry_size(key.len(), entry.value.mem_size())))
+    }
+
+    /// One SCAN step. The cursor is the next shard to visit (0 = done). Whole
+    /// shards are returned until at least `count` entries have been examined,
+    /// so a key present for the whole iteration is always returned.
+    ///
+    /// That holds under concurrent writes because a cursor never points into
+    /// a shard. Keys never move between shards (the shard comes from the key
+    /// hash), and DashMap iterates each of its inner maps under that map's
+    /// read lock. A resize needs the write lock, so it cannot reorder entries
+    /// while they are being visited. Keys added or removed during the scan
+    /// may or may not be returned.
+    pub fn scan(
+        &self,
+        cursor: usize,
+        count: usize,
+        pattern: Option<&[u8]>,
+        type_name: Option<&[u8]>,
+        now: u64,
+        budget: &mut CommandBudget,
+    ) -> Result<(usize, Vec<Bytes>), TimedOut> {
+        let mut shard = cursor;
+        let mut examined = 0;
+        let mut keys = Vec::new();
+
+        while shard < self.num_shards && examined < count {
+            for entry in self.shards[shard].iter() {
+                budget.tick()?;
+                examined += 1;
+                if entry.is_expired(now)
+                    || pattern.is_some_and(|p| !glob_match(p, entry.key()))
+                    || type_name
+                        .is_some_and(|t| !entry.value.type_name().as_bytes().eq_ignore_ascii_case(t))
+                {
+                    continue;
+                }
+                keys.push(entry.key().clone());
+            }
+            shard += 1;
+        }
+
+        let next = if shard >= self.num_shards { 0 } else { shard };
+        Ok((next, keys))
+    }
+
+    #[inline(always)]
+    pub fn exists(&self, keys: &[Bytes], now: u64) -> usize {
+        let mut count = 0;
+        for key in keys {
+            let shard = &self.shards[self.hash(key)];
+            if let Some(entry) = shard.get(key.as_ref())
+                && !entry.is_expired(now)
+            {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn keys(&self, now: u64, budget: &mut CommandBudget) -> Result<Vec<Bytes>, TimedOut> {
+        let mut result = Vec::new();
+        for shard in &self.shards {
+            for entry in shard.iter() {
+                budget.tick()?;
+                let (key, val) = entry.pair();
+                if val.expiry.is_none() || val.expiry.unwrap() > now {
+                    result.push(key.clone());
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Number of live keys, without collecting them.
+    pub fn count_keys(&self, now: u64, budget: &mut CommandBudget) -> Result<usize, TimedOut> {
+        let mut count = 0;
+        for shard in &self.shards {
+            for entry in shard.iter() {
+                budget.tick()?;
+                count += usize::from(!entry.is_expired(now));
+            }
+        }
+        Ok(count)
+    }
+
+    /// Live keys of a single shard (used to stream large KEYS replies shard by shard).
+    pub fn shard_keys(&self, shard: usize, now: u64) -> Vec<Bytes> {
+        self.shards[shard]
+            .iter()
+            .filter(|e| !e.value().is_expired(now))
+            .map(|e| e.key().clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.is_empty())
+    }
+
+    /// Remove every key. Returns the bytes freed, for memory accounting.
+    pub fn clear(&self) -> usize {
+        self.clear_with(None)
+    }
+
+    /// Remove every key. With `discard`, values are moved out of the map and
+    /// passed to it rather than dropped in place, so the caller can free them
+    /// elsewhere (FLUSHALL ASYNC). Stores of PARALLEL_CLEAR_MIN_KEYS keys or
+    /// more are cleared on one thread per core, each taking the next uncleared
+    /// shard and calling `discard` once when none are left. Returns the bytes
+    /// freed.
+    pub fn clear_with(&self, discard: Option<&(dyn Fn(Vec<Value>) + Sync)>) -> usize {
+        let workers = if self.len() < PARALLEL_CLEAR_MIN_KEYS {
+            1
+        } else {
+            num_cpus::get().min(self.num_shards)
+        };
+        let next_shard = AtomicUsize::new(0);
+        let freed = AtomicUsize::new(0);
+        let work = || {
+            let mut local = 0;
+            let mut values = Vec::new();
+            while let Some(shard) = self.shards.get(next_shard.fetch_add(1, Ordering::Relaxed)) {
+                shard.retain(|key, entry| {
+                    local += entry_size(key.len(), entry.value.mem_size());
+                    if discard.is_some() {
+                        values.push(std::mem::replace(&mut entry.value, Value::String(Bytes::new())));
+                    }
+                    false
+                });
+            }
+            freed.fetch_add(local, Ordering::Relaxed);
+            if let Some(discard) = discard {
+                discard(values);
+            }
+        };
+
+        if workers > 1 {
+            std::thread::scope(|scope| {
+                for _ in 1..workers {
+                    scope.spawn(work);
+                }
+                work();
+            });
+        } else {
+            work();
+        }
+        freed.into_inner()
+    }
+}
+
+// Why a connection stopped: reading a command, writing replies, or the
+// server closing it
+#[derive(Debug)]
+pub enum RedistillError {
+    Io(io::Error),           // The socket failed
+    Protocol(&'static [u8]), // The client sent something we refuse to parse
+    Timeout,                 // Idle past server.connection_timeout
+    TooLarge,                // An unfinished command outgrew MAX_BUFFER_SIZE
+    ConnectionClosed,        // The client hung up
+    Evicted,                 // Closed to stay within memory.max_memory_clients
+}
+
+impl RedistillError {
+    // Error line sent to the client before closing, if it gets one
+    pub fn reply(&self) -> Option<&'static [u8]> {
+        match self {
+            RedistillError::Protocol(msg) => Some(msg),
+            RedistillError::TooLarge => Some(b"Protocol error: command too large"),
+            _ => None,
+        }
+    }
+
+    // Ordinary ends of a connection, only worth logging at debug level
+    pub fn is_routine(&self) -> bool {
+        matches!(self, RedistillError::ConnectionClosed | RedistillError::Timeout)
+    }
+}
+
+impl std::fmt::Display for RedistillError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedistillError::Io(e) => write!(f, "I/O error: {}", e),
+            RedistillError::Protocol(msg) => f.write_str(&String::from_utf8_lossy(msg)),
+            RedistillError::Timeout => f.write_str("idle timeout"),
+            RedistillError::TooLarge => write!(f, "command buffer over {} bytes", MAX_BUFFER_SIZE),
+            RedistillError::ConnectionClosed => f.write_str("closed by client"),
+            RedistillError::Evicted => f.write_str("evicted (max_memory_clients)"),
+        }
+    }
+}
+
+impl std::error::Error for RedistillError {}
+
+impl From<io::Error> for RedistillError {
+    fn from(e: io::Error) -> Self {
+        RedistillError::Io(e)
+    }
+}
+
+// Log levels from least to most verbose, as logging.level names them
+pub const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+// Position of a level name in LOG_LEVELS (unknown names count as info)
+pub fn log_rank(level: &str) -> usize {
+    LOG_LEVELS.iter().position(|l| l.eq_ignore_ascii_case(level)).unwrap_or(2)
+}
+
+// Whether messages at `level` pass logging.level
+pub fn log_enabled(level: &str) -> bool {
+    log_rank(level) <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+// A length prefix written the one way the spec allows: at least one digit
+// and no leading zeros ("0" and "12", not "" or "007")
+#[inline(always)]
+pub fn minimal_digits(digits: &[u8]) -> bool {
+    !matches!(digits, [] | [b'0', _, ..])
+}
+
+// Optimized RESP parser with zero-copy
+pub struct RespParser {
+    pub buffer: BytesMut,
+    pub max_args: usize,
+    pub max_bytes: usize,
+    pub max_inline: usize, // server.max_inline_len
+    pub strict: bool, // server.strict_protocol
+    pub sizer: BufferSizer,
+}
+
+impl Default for RespParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RespParser {
+    #[inline]
+    pub fn new() -> Self {
+        let sizer = BufferSizer::from_config();
+        Self {
+            buffer: BytesMut::with_capacity(sizer.target),
+            max_args: CONFIG.server.max_command_args,
+            max_bytes: CONFIG.server.max_command_bytes,
+            max_inline: CONFIG.server.max_inline_len,
+            strict: CONFIG.server.strict_protocol,
+            sizer,
+        }
+    }
+
+    // Drop back to the smallest buffer while the client is quiet. Only
+    // called between commands, so nothing is pending.
+    pub fn release_idle(&mut self) {
+        if self.buffer.is_empty() {
+            self.sizer.reset();
+            self.buffer = BytesMut::with_capacity(self.sizer.target);
+        }
+    }
+
+    #[inline(always)]
+    pub fn has_buffered_data(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    pub async fn parse_command<S>(&mut self, stream: &mut S) -> Result<Vec<Bytes>, RedistillError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        loop {
+            match self.try_parse() {
+                Ok(Some(cmd)) => return Ok(cmd),
+                Ok(None) => {
+                    // DoS protection: reject connections with excessively large buffers
+                    if self.buffer.len() > MAX_BUFFER_SIZE {
+                        return Err(RedistillError::TooLarge);
+                    }
+                    if self.buffer.is_empty() {
+                        // Size the next read to what this connection has
+                        // been using, giving back anything a burst left behind
+                        if self.buffer.capacity() > self.sizer.target {
+                            self.buffer = BytesMut::new();
+                        }
+                        self.buffer.reserve(self.sizer.target);
+                    }
+                    if stream.read_buf(&mut self.buffer).await? == 0 {
+                        return Err(RedistillError::ConnectionClosed);
+                    }
+                    self.sizer.observe(self.buffer.len());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn try_parse(&mut self) -> Result<Option<Vec<Bytes>>, RedistillError> {
+        loop {
+            // Anything but a RESP array is an inline command
+            let cmd = if self.buffer.first().is_some_and(|&byte| byte != b'*') {
+                self.try_parse_inline()?
+            } else {
+                self.try_parse_multibulk()?
+            };
+            match cmd {
+                // A blank line or an empty multibulk (*0) does nothing, as in Redis
+                Some(cmd) if cmd.is_empty() => continue,
+                cmd => return Ok(cmd),
+            }
+        }
+    }
+
+    fn try_parse_multibulk(&mut self) -> Result<Option<Vec<Bytes>>, RedistillError> {
+        let mut cursor = 1;
+        let len = self.buffer.len();
+
+        // Fast integer parsing
+        let digits_start = cursor;
+        let mut array_len = 0usize;
+        loop {
+            if cursor >= len {
+                return Ok(None);
+            }
+            let byte = self.buffer[cursor];
+            if byte == b'\r' || byte == b'\n' {
+                break;
+            }
+            if !byte.is_ascii_digit() {
+                return Err(RedistillError::Protocol(b"Protocol error: invalid multibulk length"));
+            }
+            array_len = array_len * 10 + (byte - b'0') as usize;
+            
+            // Security: Prevent DoS via massive array allocation, and keep a
+            // single giant DEL/MSET from monopolizing a shard
+            if array_len > self.max_args {
+                return Err(RedistillError::Protocol(b"Protocol error: too many arguments"));
+            }
+            
+            cursor += 1;
+        }
+
+        let invalid = RedistillError::Protocol(b"Protocol error: invalid multibulk length");
+        if self.strict && !minimal_digits(&self.buffer[digits_start..cursor]) {
+            return Err(invalid);
+        }
+        match self.line_end(cursor) {
+            Some(Some(end)) => cursor += end,
+            Some(None) => return Ok(None),
+            None => return Err(invalid),
+        }
+
+        let mut result = Vec::with_capacity(array_len);
+        let mut total_bytes = 0usize;
+
+        for _ in 0..array_len {
+            if cursor >= len {
+                return Ok(None);
+            }
+            if self.buffer[cursor] != b'$' {
+                return Err(RedistillError::Protocol(b"Protocol error: expected '$'"));
+            }
+            cursor += 1;
+
+            let digits_start = cursor;
+            let mut str_len = 0usize;
+            loop {
+                if cursor >= len {
+                    return Ok(None);
+                }
+                let byte = self.buffer[cursor];
+                if byte == b'\r' || byte == b'\n' {
+                    break;
+                }
+                if !byte.is_ascii_digit() {
+                    return Err(RedistillError::Protocol(b"Protocol error: invalid bulk length"));
+                }
+                str_len = str_len * 10 + (byte - b'0') as usize;
+                
+                // Security: Prevent DoS via massive string allocation
+                if str_len > MAX_STRING_LEN {
+                    return Err(RedistillError::Protocol(b"Protocol error: invalid bulk length"));
+                }
+                
+                cursor += 1;
+            }
+
+            let invalid = RedistillError::Protocol(b"Protocol error: invalid bulk length");
+            if self.strict && !minimal_digits(&self.buffer[digits_start..cursor]) {
+                return Err(invalid);
+            }
+            match self.line_end(cursor) {
+                Some(Some(end)) => cursor += end,
+                Some(None) => return Ok(None),
+                None => return Err(invalid),
+            }
+
+            // Checked against declared lengths so oversized commands are
+            // rejected before their payload is buffered
+            total_bytes += str_len;
+            if total_bytes > self.max_bytes {
+                return Err(RedistillError::Protocol(b"Protocol error: command too large"));
+            }
+
+            // Store as reference for now - we'll convert after parsing
+            let start = cursor;
+            let end = cursor + str_len;
+            if end >= len {
+                return Ok(None);
+            }
+            match self.line_end(end) {
+                Some(Some(terminator)) => cursor = end + terminator,
+                Some(None) => return Ok(None),
+                None => {
+                    return Err(RedistillError::Protocol(
+                        b"Protocol error: expected CRLF after bulk string",
+                    ));
+                }
+            }
+            result.push(Bytes::copy_from_slice(&self.buffer[start..end]));
+        }
+
+        self.buffer.advance(cursor);
+        Ok(Some(result))
+    }
+
+    // An inline command, as typed into telnet: arguments separated by spaces
+    // or tabs on one line. Like Redis, the line may end in a bare \n (unless
+    // strict mode is on). A blank line parses as an empty command.
+    fn try_parse_inline(&mut self) -> Result<Option<Vec<Bytes>>, RedistillError> {
+        let too_big = RedistillError::Protocol(b"Protocol error: too big inline request");
+        let Some(newline) = self.buffer.iter().position(|&byte| byte == b'\n') else {
+            if self.buffer.len() > self.max_inline {
+                return Err(too_big);
+            }
+            return Ok(None);
+        };
+        if newline > self.max_inline {
+            return Err(too_big);
+        }
+
+        let mut line = &self.buffer[..newline];
+        if let [rest @ .., b'\r'] = line {
+            line = rest;
+        } else if self.strict {
+            return Err(RedistillError::Protocol(b"Protocol error: expected CRLF after inline command"));
+        }
+        if line.len() > self.max_bytes {
+            return Err(RedistillError::Protocol(b"Protocol error: command too large"));
+        }
+        let cmd: Vec<Bytes> = line
+            .split(|&byte| byte == b' ' || byte == b'\t')
+            .filter(|arg| !arg.is_empty())
+            .map(Bytes::copy_from_slice)
+            .collect();
+        if cmd.len() > self.max_args {
+            return Err(RedistillError::Protocol(b"Protocol error: too many arguments"));
+        }
+
+        self.buffer.advance(newline + 1);
+        Ok(Some(cmd))
+    }
+
+    // Length of the line ending at `at`: 2 for \r\n, 1 for a bare \n outside
+    // strict mode. Some(None) while the ending is still arriving, None when
+    // the bytes there cannot end a line.
+    #[inline(always)]
+    fn line_end(&self, at: usize) -> Option<Option<usize>> {
+        match self.buffer.get(at..) {
+            Some([b'\r', b'\n', ..]) => Some(Some(2)),
+            Some([b'\n', ..]) if !self.strict => Some(Some(1)),
+            Some([b'\r'] | []) | None => Some(None),
+            _ => None,
+        }
+    }
+}
+
+// Optimized RESP writer with pooled buffers
+pub struct RespWriter {
+    pub buffer: Vec<u8>,
+    pub flush_threshold: usize,
+    pub sizer: BufferSizer,
+}
+
+impl Default for RespWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RespWriter {
+    pub fn new() -> Self {
+        Self {
+            buffer: get_buffer(),
+            flush_threshold: CONFIG.server.reply_flush_threshold.min(MAX_PENDING_REPLY_BYTES),
+            sizer: BufferSizer::from_config(),
+        }
+    }
+
+    // Drop back to the smallest buffer while the client is quiet
+    pub fn release_idle(&mut self) {
+        if self.buffer.is_empty() {
+            self.sizer.reset();
+            self.buffer.shrink_to(self.sizer.target);
+        }
+    }
+
+    #[inline(always)]
+    pub fn write_u64(&mut self, mut n: u64) {
+        if n == 0 {
+            self.buffer.push(b'0');
+            return;
+        }
+
+        let mut buf = [0u8; 20];
+        let mut i = 20;
+
+        while n > 0 {
+            i -= 1;
+            buf[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+        }
+
+        self.buffer.extend_from_slice(&buf[i..]);
+    }
+
+    #[inline(always)]
+    pub fn write_simple_string(&mut self, s: &[u8]) {
+        self.buffer.push(b'+');
+        self.buffer.extend_from_slice(s);
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    #[inline(always)]
+    pub fn write_bulk_string(&mut self, s: &[u8]) {
+        self.buffer.push(b'$');
+        self.write_u64(s.len() as u64);
+        self.buffer.extend_from_slice(b"\r\n");
+        self.buffer.extend_from_slice(s);
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    #[inline(always)]
+    pub fn write_null(&mut self) {
+        self.buffer.extend_from_slice(b"$-1\r\n");
+    }
+
+    #[inline(always)]
+    pub fn write_signed_integer(&mut self, i: i64) {
+        self.buffer.push(b':');
+        if i < 0 {
+            self.buffer.push(b'-');
+            self.write_u64(i.unsigned_abs());
+        } else {
+            self.write_u64(i as u64);
+        }
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    #[inline(always)]
+    pub fn write_error(&mut self, s: &[u8]) {
+        self.buffer.extend_from_slice(b"-ERR ");
+        push_error_text(&mut self.buffer, s);
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    #[inline(always)]
+    pub fn write_array_header(&mut self, len: usize) {
+        self.buffer.push(b'*');
+        self.write_u64(len as u64);
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
+    #[inline(always)]
+    pub fn write_null_array(&mut self) {
+        self.buffer.extend_from_slice(b"*-1\r\n");
+    }
+
+    pub fn write_reply(&mut self, reply: &Reply) {
+        match reply {
+            Reply::SimpleString(s) => self.write_simple_string(s),
+            Reply::BulkString(s) => self.write_bulk_string(s),
+            Reply::Null => self.write_null(),
+            Reply::NullArray => self.write_null_array(),
+            Reply::Integer(i) => self.write_signed_integer(*i),
+            Reply::Error(line) => {
+                self.buffer.push(b'-');
+                self.buffer.extend_from_slice(line);
+                self.buffer.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                self.write_array_header(items.len());
+                for item in items {
+                    self.write_reply(item);
+                }
+            }
+            Reply::Deferred => {}
+        }
+    }
+
+    #[inline(always)]
+    pub fn should_flush(&self) -> bool {
+        self.buffer.len() >= self.flush_threshold
+    }
+
+    #[inline(always)]
+    pub async fn flush<S>(&mut self, stream: &mut S) -> Result<(), RedistillError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        if !self.buffer.is_empty() {
+            stream.write_all(&self.buffer).await?;
+            self.sizer.observe(self.buffer.len());
+            self.buffer.clear();
+            // A large reply can grow the buffer far past the target; keep
+            // at most the target between flushes
+            if self.buffer.capacity() > self.sizer.target {
+                self.buffer.shrink_to(self.sizer.target);
+            }
+        }
+        Ok(())
+    }
+}
+
+// A command's reply, built by `execute` and serialized by
+// `RespWriter::write_reply`. Bulk strings hold `Bytes`, so a value read from
+// the store is copied once, straight into the output buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    SimpleString(Bytes),
+    BulkString(Bytes),
+    Null,
+    NullArray,
+    Integer(i64),
+    Error(Bytes), // The whole error line minus the '-', e.g. "ERR syntax error"
+    Array(Vec<Reply>),
+    Deferred, // Written later by the connection loop (see ConnectionState::deferred)
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::SimpleString(Bytes::from_static(b"OK"))
+    }
+
+    pub fn bulk(s: &[u8]) -> Self {
+        Reply::BulkString(Bytes::copy_from_slice(s))
+    }
+
+    pub fn bulk_array(items: &[Bytes]) -> Self {
+        Reply::Array(items.iter().cloned().map(Reply::BulkString).collect())
+    }
+
+    // An ERR reply; the text is escaped and capped like any error line
+    pub fn error(text: &[u8]) -> Self {
+        let mut line = b"ERR ".to_vec();
+        push_error_text(&mut line, text);
+        Reply::Error(line.into())
+    }
+
+    // WRONGTYPE carries its own error code instead of ERR
+    pub fn wrongtype() -> Self {
+        Reply::Error(Bytes::from_static(WrongTypeError::MESSAGE))
+    }
+}
+
+impl Drop for RespWriter {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.buffer);
+        return_buffer(buf);
+    }
+}
+
+// Seconds since the epoch, or 0 if the clock reads earlier than that (seen
+// on VMs that boot before their clock is synced) instead of panicking
+#[inline(always)]
+pub fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+// Seconds since the process started; 0 if the clock has been set back since
+pub fn server_uptime() -> u64 {
+    START_TIME.elapsed().unwrap_or_default().as_secs()
+}
+
+// Get uptime in seconds for LRU tracking
+#[inline(always)]
+pub fn get_uptime_seconds() -> u32 {
+    let start = SERVER_START_TIME.load(Ordering::Relaxed);
+    if start == 0 {
+        return 0;
+    }
+    get_timestamp().saturating_sub(start as u64) as u32
+}
+
+// Approximate access tracking: only update 10% of the time
+#[inline(always)]
+pub fn maybe_update_access_time(store: &ShardedStore, entry: &Entry) {
+    // Skip entirely if memory limits disabled (zero-cost)
+    if max_memory() == 0 {
+        return;
+    }
+
+    // Fast path: skip 90% of updates for performance
+    if store.random_below(100) < 90 {
+        return;
+    }
+    // Slow path: update access time
+    entry
+        .last_accessed
+        .store(get_uptime_seconds(), Ordering::Relaxed);
+}
+
+// Whether a string value fits under memory.max_value_size (0 = unlimited)
+#[inline(always)]
+pub fn value_size_allowed(len: usize, max_value_size: u64) -> bool {
+    max_value_size == 0 || len as u64 <= max_value_size
+}
+
+// Calculate approximate size of an entry
+#[inline(always)]
+pub fn entry_size(key_len: usize, value_len: usize) -> usize {
+    key_len + value_len + 64 // ~64 bytes overhead for Arc, Entry struct, etc.
+}
+
+// Size of an entry removed because its TTL passed, counting it in
+// EXPIRED_KEYS. Every lazy-expiry path goes through here.
+#[inline(always)]
+pub fn expired_entry_size(key_len: usize, value_len: usize) -> usize {
+    EXPIRED_KEYS.fetch_add(1, Ordering::Relaxed);
+    entry_size(key_len, value_len)
+}
+
+// Check connection rate limit (TOCTOU race fixed with compare_exchange)
+#[inline]
+pub fn check_rate_limit() -> bool {
+    let rate_limit = CONNECTION_RATE_LIMIT.load(Ordering::Relaxed);
+
+    // No rate limit
+    if rate_limit == 0 {
+        return true;
+    }
+
+    let now = get_timestamp();
+    let last_check = LAST_CONNECTION_CHECK.load(Ordering::Acquire);
+
+    // New second - atomically reset counter
+    if now > last_check {
+        // Try to atomically update the timestamp
+        match LAST_CONNECTION_CHECK.compare_exchange(
+            last_check,
+            now,
+            Ordering::AcqRel,
+            Ordering::Acquire
+        ) {
+            Ok(_) => {
+                // We won the race - reset counter
+                CONNECTIONS_THIS_SECOND.store(1, Ordering::Release);
+                return true;
+            }
+            Err(_) => {
+                // Someone else reset it - fall through to increment
+            }
+        }
+    }
+
+    // Same second - check limit
+    let count = CONNECTIONS_THIS_SECOND.fetch_add(1, Ordering::AcqRel);
+    count < rate_limit
+}
+
+// Token bucket pacing the accept loop: up to `burst` connections go through
+// immediately, after that one every 1/rate seconds. Only the accept loop
+// touches it, so no synchronization is needed.
+pub struct AcceptThrottle {
+    pub rate: f64,
+    pub burst: f64,
+    pub tokens: f64,
+    pub last: Instant,
+}
+
+impl AcceptThrottle {
+    pub fn new(rate: u64, burst: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: now,
+        }
+    }
+
+    /// Take a token for one connection, returning how long to wait before
+    /// handling it. The bucket may go negative; the debt is paid back by the
+    /// refill that happens while the caller sleeps.
+    pub fn acquire(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+// How long the accept loop waits for a connection slot before turning the
+// client away
+pub const CONNECTION_PERMIT_WAIT: Duration = Duration::from_millis(100);
+
+// max_connections as a semaphore, so the limit holds before a handler task
+// is spawned rather than being checked against a counter the handlers
+// update later
+pub fn connection_permits(max_connections: usize) -> Option<Arc<tokio::sync::Semaphore>> {
+    (max_connections > 0).then(|| {
+        Arc::new(tokio::sync::Semaphore::new(
+            max_connections.min(tokio::sync::Semaphore::MAX_PERMITS),
+        ))
+    })
+}
+
+/// Take a connection slot, waiting up to `wait` for one to free up. The
+/// handler holds the permit until it returns. None means the server is
+/// full and the connection should be refused.
+pub async fn acquire_connection_permit(
+    permits: &Arc<tokio::sync::Semaphore>,
+    wait: Duration,
+) -> Option<tokio::sync::OwnedSemaphorePermit> {
+    tokio::time::timeout(wait, permits.clone().acquire_owned()).await.ok()?.ok()
+}
+
+// Listen address for a bind host and port; IPv6 literals need brackets
+pub fn listen_addr(bind: &str, port: u16) -> String {
+    if bind.contains(':') && !bind.starts_with('[') {
+        format!("[{}]:{}", bind, port)
+    } else {
+        format!("{}:{}", bind, port)
+    }
+}
+
+// Format bytes as human-readable string
+pub fn format_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    const GB: u64 = 1024 * MB;
+
+    if bytes >= GB {
+        format!("{:.2}GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.2}MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.2}KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{}B", bytes)
+    }
+}
+
+// Fast byte comparison helpers
+#[inline(always)]
+pub fn eq_ignore_case_3(a: &[u8], b: &[u8; 3]) -> bool {
+    a.len() == 3 && (a[0] | 0x20) == b[0] && (a[1] | 0x20) == b[1] && (a[2] | 0x20) == b[2]
+}
+
+// Parse bytes as u64 with overflow protection
+#[inline(always)]
+pub fn parse_u64(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let mut val = 0u64;
+    for &b in bytes.iter() {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        val = val.checked_mul(10)?.checked_add((b - b'0') as u64)?;
+    }
+    Some(val)
+}
+
+// EXPIRE's NX | XX | GT | LT option
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpireCondition {
+    Always,
+    Nx, // Only if the key has no expiry
+    Xx, // Only if the key has an expiry
+    Gt, // Only if the new expiry is later
+    Lt, // Only if the new expiry is sooner
+}
+
+impl ExpireCondition {
+    pub fn parse(options: &[Bytes]) -> Result<Self, Vec<u8>> {
+        let mut condition = ExpireCondition::Always;
+        for option in options {
+            let parsed = match option.to_ascii_uppercase().as_slice() {
+                b"NX" => ExpireCondition::Nx,
+                b"XX" => ExpireCondition::Xx,
+                b"GT" => ExpireCondition::Gt,
+                b"LT" => ExpireCondition::Lt,
+                _ => {
+                    let mut message = b"Unsupported option ".to_vec();
+                    message.extend_from_slice(option);
+                    return Err(message);
+                }
+            };
+            condition = match (condition, parsed) {
+                (ExpireCondition::Always, _) => parsed,
+                (current, parsed) if current == parsed => current,
+                // XX combines with GT or LT; the narrower condition implies it
+                (ExpireCondition::Xx, ExpireCondition::Gt | ExpireCondition::Lt) => parsed,
+                (ExpireCondition::Gt | ExpireCondition::Lt, ExpireCondition::Xx) => condition,
+                (ExpireCondition::Nx, _) | (_, ExpireCondition::Nx) => {
+                    return Err(b"NX and XX, GT or LT options at the same time are not compatible".to_vec());
+                }
+                _ => return Err(b"GT and LT options at the same time are not compatible".to_vec()),
+            };
+        }
+        Ok(condition)
+    }
+
+    /// Whether an expiry of `new` may replace `current`. A key without an
+    /// expiry counts as expiring never, so GT fails on it and LT succeeds.
+    pub fn allows(self, current: Option<u64>, new: u64) -> bool {
+        match (self, current) {
+            (ExpireCondition::Always, _) => true,
+            (ExpireCondition::Nx, current) => current.is_none(),
+            (ExpireCondition::Xx, current) => current.is_some(),
+            (ExpireCondition::Gt, Some(current)) => new > current,
+            (ExpireCondition::Gt, None) => false,
+            (ExpireCondition::Lt, Some(current)) => new < current,
+            (ExpireCondition::Lt, None) => true,
+        }
+    }
+}
+
+// Parse bytes as i64 with overflow protection
+#[inline(always)]
+pub fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let (negative, start) = if bytes[0] == b'-' {
+        (true, 1)
+    } else {
+        (false, 0)
+    };
+    if start >= bytes.len() {
+        return None;
+    }
+    let mut val = 0i64;
+    for &b in bytes[start..].iter() {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        val = val.checked_mul(10)?.checked_add((b - b'0') as i64)?;
+    }
+    if negative {
+        Some(-val)
+    } else {
+        Some(val)
+    }
+}
+
+// Parse a sorted-set score: any float including inf/-inf, but never NaN
+pub fn parse_score(bytes: &[u8]) -> Option<f64> {
+    let score: f64 = std::str::from_utf8(bytes).ok()?.parse().ok()?;
+    (!score.is_nan()).then_some(score)
+}
+
+// LMPOP/ZMPOP arguments after the command name:
+//   numkeys key [key ...] <end> [COUNT count]
+// `ends` holds the two accepted end names. Returns the keys, whether the
+// first end was named, and the count (default 1).
+pub fn parse_mpop_args<'a>(
+    args: &'a [Bytes],
+    ends: [&[u8]; 2],
+) -> Result<(&'a [Bytes], bool, usize), &'static [u8]> {
+    if args.len() < 3 {
+        return Err(b"wrong number of arguments");
+    }
+    let numkeys = match parse_i64(&args[0]) {
+        Some(n) if n > 0 => n as usize,
+        Some(_) => return Err(b"numkeys should be greater than 0"),
+        None => return Err(b"value is not an integer or out of range"),
+    };
+    if numkeys > args.len() - 2 {
+        return Err(b"syntax error");
+    }
+
+    let end = &args[numkeys + 1];
+    let first = if end.eq_ignore_ascii_case(ends[0]) {
+        true
+    } else if end.eq_ignore_ascii_case(ends[1]) {
+        false
+    } else {
+        return Err(b"syntax error");
+    };
+    let count = match &args[numkeys + 2..] {
+        [] => 1,
+        [option, n] if option.eq_ignore_ascii_case(b"count") => match parse_i64(n) {
+            Some(n) if n > 0 => n as usize,
+            _ => return Err(b"count should be greater than 0"),
+        },
+        _ => return Err(b"syntax error"),
+    };
+    Ok((&args[1..=numkeys], first, count))
+}
+
+// SINTERCARD/ZINTERCARD arguments after the command name:
+//   numkeys key [key ...] [LIMIT limit]
+// Returns the keys and the limit (0 = no limit).
+pub fn parse_intercard_args(args: &[Bytes]) -> Result<(&[Bytes], usize), &'static [u8]> {
+    if args.len() < 2 {
+        return Err(b"wrong number of arguments");
+    }
+    let numkeys = match parse_i64(&args[0]) {
+        Some(n) if n > 0 => n as usize,
+        _ => return Err(b"numkeys should be greater than 0"),
+    };
+    if numkeys > args.len() - 1 {
+        return Err(b"Number of keys can't be greater than number of args");
+    }
+
+    let limit = match &args[numkeys + 1..] {
+        [] => 0,
+        [option, n] if option.eq_ignore_ascii_case(b"limit") => match parse_i64(n) {
+            Some(n) if n >= 0 => n as usize,
+            _ => return Err(b"LIMIT can't be negative"),
+        },
+        _ => return Err(b"syntax error"),
+    };
+    Ok((&args[1..=numkeys], limit))
+}
+
+// Sorted-set members as [member, score] pairs (ZMPOP reply)
+pub fn scored_pairs_reply(members: &[(Bytes, f64)]) -> Reply {
+    let pairs = members.iter().map(|(member, score)| {
+        Reply::Array(vec![
+            Reply::BulkString(member.clone()),
+            Reply::BulkString(score.to_string().into()),
+        ])
+    });
+    Reply::Array(pairs.collect())
+}
+
+// Highest bit offset + width a bit command may touch (a 512MB string)
+pub const MAX_BIT_OFFSET: u64 = 512 * 1024 * 1024 * 8;
+
+/// First bit set to `bit` within bytes `start..=end` (negative = from the end),
+/// as a bit position from the start of the value, or -1. Looking for a clear
+/// bit without an explicit end finds the first bit past the value.
+pub fn bitpos(value: &[u8], bit: bool, start: i64, end: Option<i64>) -> i64 {
+    let len = value.len() as i64;
+    if len == 0 {
+        return if bit { -1 } else { 0 };
+    }
+    let normalize = |i: i64| if i < 0 { (len + i).max(0) } else { i };
+    let start = normalize(start);
+    let last = normalize(end.unwrap_or(-1)).min(len - 1);
+    if start > last {
+        return -1;
+    }
+
+    for i in start..=last {
+        let byte = if bit { value[i as usize] } else { !value[i as usize] };
+        if byte != 0 {
+            return i * 8 + byte.leading_zeros() as i64;
+        }
+    }
+    if !bit && end.is_none() { (last + 1) * 8 } else { -1 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitfieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// Integer field type such as `u8` or `i16` (u1..u63, i1..i64).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitfieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl BitfieldType {
+    pub fn parse(spec: &[u8]) -> Option<Self> {
+        let (&kind, digits) = spec.split_first()?;
+        let signed = match kind.to_ascii_lowercase() {
+            b'i' => true,
+            b'u' => false,
+            _ => return None,
+        };
+        let bits: u32 = std::str::from_utf8(digits).ok()?.parse().ok()?;
+        let max = if signed { 64 } else { 63 };
+        (1..=max).contains(&bits).then_some(Self { signed, bits })
+    }
+
+    pub fn range(&self) -> (i128, i128) {
+        if self.signed {
+            let half = 1i128 << (self.bits - 1);
+            (-half, half - 1)
+        } else {
+            (0, (1i128 << self.bits) - 1)
+        }
+    }
+
+    // Fit `value` into the field, or None when FAIL rejects it
+    pub fn fit(&self, value: i128, overflow: BitfieldOverflow) -> Option<i64> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            BitfieldOverflow::Wrap => {
+                let wrapped = value.rem_euclid(1i128 << self.bits);
+                Some(if wrapped > max { wrapped - (1i128 << self.bits) } else { wrapped } as i64)
+            }
+            BitfieldOverflow::Sat => Some(value.clamp(min, max) as i64),
+            BitfieldOverflow::Fail => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitfieldOp {
+    Get(BitfieldType, u64),
+    Set(BitfieldType, u64, i64, BitfieldOverflow),
+    IncrBy(BitfieldType, u64, i64, BitfieldOverflow),
+}
+
+/// Parse BITFIELD subcommands (everything after the key).
+pub fn parse_bitfield_ops(args: &[Bytes]) -> Result<Vec<BitfieldOp>, &'static [u8]> {
+    let parse_i64 = |arg: &Bytes| -> Result<i64, &'static [u8]> {
+        std::str::from_utf8(arg)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(b"value is not an integer or out of range".as_slice())
+    };
+    let field = |ty: &Bytes, offset: &Bytes| -> Result<(BitfieldType, u64), &'static [u8]> {
+        let ty = BitfieldType::parse(ty).ok_or(
+            b"Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                .as_slice(),
+        )?;
+        // "#N" addresses the Nth field of this width
+        let (scaled, digits) = match offset.strip_prefix(b"#") {
+            Some(rest) => (true, rest),
+            None => (false, offset.as_ref()),
+        };
+        let offset = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .and_then(|n| if scaled { n.checked_mul(ty.bits as u64) } else { Some(n) })
+            .filter(|&n| n.checked_add(ty.bits as u64).is_some_and(|end| end <= MAX_BIT_OFFSET))
+            .ok_or(b"bit offset is not an integer or out of range".as_slice())?;
+        Ok((ty, offset))
+    };
+
+    let mut ops = Vec::new();
+    let mut overflow = BitfieldOverflow::Wrap;
+    let mut i = 0;
+    while i < args.len() {
+        let op = &args[i];
+        if op.eq_ignore_ascii_case(b"get") && i + 2 < args.len() {
+            let (ty, offset) = field(&args[i + 1], &args[i + 2])?;
+            ops.push(BitfieldOp::Get(ty, offset));
+            i += 3;
+        } else if (op.eq_ignore_ascii_case(b"set") || op.eq_ignore_ascii_case(b"incrby"))
+            && i + 3 < args.len()
+        {
+            let (ty, offset) = field(&args[i + 1], &args[i + 2])?;
+            let value = parse_i64(&args[i + 3])?;
+            ops.push(if op.eq_ignore_ascii_case(b"set") {
+                BitfieldOp::Set(ty, offset, value, overflow)
+            } else {
+                BitfieldOp::IncrBy(ty, offset, value, overflow)
+            });
+            i += 4;
+        } else if op.eq_ignore_ascii_case(b"overflow") && i + 1 < args.len() {
+            let mode = &args[i + 1];
+            overflow = if mode.eq_ignore_ascii_case(b"wrap") {
+                BitfieldOverflow::Wrap
+            } else if mode.eq_ignore_ascii_case(b"sat") {
+                BitfieldOverflow::Sat
+            } else if mode.eq_ignore_ascii_case(b"fail") {
+                BitfieldOverflow::Fail
+            } else {
+                return Err(b"Invalid OVERFLOW type specified");
+            };
+            i += 2;
+        } else {
+            return Err(b"syntax error");
+        }
+    }
+    Ok(ops)
+}
+
+// Bits are numbered from the most significant bit of the first byte;
+// bits past the end of the value read as zero
+pub fn read_bits(value: &[u8], offset: u64, ty: BitfieldType) -> i64 {
+    let mut raw = 0u64;
+    for pos in offset..offset + ty.bits as u64 {
+        let byte = value.get((pos / 8) as usize).copied().unwrap_or(0);
+        raw = (raw << 1) | ((byte >> (7 - pos % 8)) & 1) as u64;
+    }
+    if ty.signed && ty.bits < 64 && raw >> (ty.bits - 1) == 1 {
+        (raw as i64) - (1i64 << ty.bits)
+    } else {
+        raw as i64
+    }
+}
+
+pub fn write_bits(value: &mut BytesMut, offset: u64, ty: BitfieldType, field: i64) {
+    let needed = (offset + ty.bits as u64).div_ceil(8) as usize;
+    if value.len() < needed {
+        value.resize(needed, 0);
+    }
+    for i in 0..ty.bits as u64 {
+        let pos = offset + i;
+        let mask = 1u8 << (7 - pos % 8);
+        let byte = &mut value[(pos / 8) as usize];
+        if (field >> (ty.bits as u64 - 1 - i)) & 1 == 1 {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+/// Run BITFIELD operations against `value`, growing it as fields are written.
+/// Returns one reply per operation: the field for GET, the old value for SET,
+/// the new value for INCRBY, or None when OVERFLOW FAIL skipped the write.
+pub fn bitfield_apply(value: &mut BytesMut, ops: &[BitfieldOp]) -> Vec<Option<i64>> {
+    ops.iter()
+        .map(|&op| match op {
+            BitfieldOp::Get(ty, offset) => Some(read_bits(value, offset, ty)),
+            BitfieldOp::Set(ty, offset, new, overflow) => {
+                let old = read_bits(value, offset, ty);
+                let new = ty.fit(new as i128, overflow)?;
+                write_bits(value, offset, ty, new);
+                Some(old)
+            }
+            BitfieldOp::IncrBy(ty, offset, increment, overflow) => {
+                let old = read_bits(value, offset, ty);
+                let new = ty.fit(old as i128 + increment as i128, overflow)?;
+                write_bits(value, offset, ty, new);
+                Some(new)
+            }
+        })
+        .collect()
+}
+
+#[inline(always)]
+pub fn eq_ignore_case_6(a: &[u8], b: &[u8; 6]) -> bool {
+    a.len() == 6
+        && (a[0] | 0x20) == b[0]
+        && (a[1] | 0x20) == b[1]
+        && (a[2] | 0x20) == b[2]
+        && (a[3] | 0x20) == b[3]
+        && (a[4] | 0x20) == b[4]
+        && (a[5] | 0x20) == b[5]
+}
+
+// Redis-style glob matching (as used by KEYS/SCAN MATCH): `*`, `?`, `[abc]`,
+// `[^abc]`, `[a-z]` and `\` escapes. Case-sensitive, binary-safe.
+//
+// Stars backtrack iteratively: on a mismatch only the last star seen takes
+// one more byte, since anything an earlier star could take instead the last
+// one can too. That keeps the work at O(pattern * string), where retrying
+// every star recursively is exponential in the number of stars (the Redis
+// CVE-2022-36021 pattern).
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+    // Pattern position after the last star, and the string position it
+    // took bytes up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+            continue;
+        }
+        if p < pattern.len()
+            && let Some(next) = glob_match_one(pattern, p, string[s])
+        {
+            p = next;
+            s += 1;
+            continue;
+        }
+        let Some((star_p, star_s)) = star else {
+            return false;
+        };
+        p = star_p;
+        s = star_s + 1;
+        star = Some((star_p, s));
+    }
+
+    // Trailing stars match the empty remainder
+    while pattern.get(p) == Some(&b'*') {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+// Match the single-byte pattern element at `p` (anything but `*`) against
+// `c`. Returns the position after the element, or None on a mismatch.
+fn glob_match_one(pattern: &[u8], mut p: usize, c: u8) -> Option<usize> {
+    match pattern[p] {
+        b'?' => Some(p + 1),
+        b'[' => {
+            p += 1;
+            let negate = pattern.get(p) == Some(&b'^');
+            if negate {
+                p += 1;
+            }
+            let mut matched = false;
+            loop {
+                match pattern.get(p) {
+                    // Unterminated class: treat the end of the pattern as `]`
+                    None => break,
+                    Some(b'\\') if p + 1 < pattern.len() => {
+                        p += 1;
+                        matched |= pattern[p] == c;
+                    }
+                    Some(b']') => break,
+                    Some(&start) if p + 2 < pattern.len() && pattern[p + 1] == b'-' => {
+                        let end = pattern[p + 2];
+                        let (lo, hi) = if start <= end { (start, end) } else { (end, start) };
+                        matched |= (lo..=hi).contains(&c);
+                        p += 2;
+                    }
+                    Some(&x) => matched |= x == c,
+                }
+                p += 1;
+            }
+            (matched != negate).then_some((p + 1).min(pattern.len()))
+        }
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        x => (x == c).then_some(p + 1),
+    }
+}
+
+// Shared small integers (0..SHARED_INTEGERS), like Redis' shared integer objects.
+// Reading an int-encoded 42 hands out one refcounted Bytes instead of
+// formatting a new one each time.
+pub const SHARED_INTEGERS: usize = 10000;
+// Refcount Redis reports for shared objects (OBJ_SHARED_REFCOUNT)
+pub const SHARED_REFCOUNT: usize = i32::MAX as usize;
+
+pub static SHARED_INTEGER_VALUES: Lazy<Vec<Bytes>> = Lazy::new(|| {
+    (0..SHARED_INTEGERS)
+        .map(|i| Bytes::from(i.to_string()))
+        .collect()
+});
+
+// Index into the shared integer table for canonical short numeric strings
+#[inline(always)]
+pub fn shared_integer_index(value: &[u8]) -> Option<usize> {
+    // Reject leading zeros ("007") so GET returns exactly what was stored
+    if value.is_empty() || value.len() > 4 || (value.len() > 1 && value[0] == b'0') {
+        return None;
+    }
+    let n = parse_u64(value)? as usize;
+    (n < SHARED_INTEGERS).then_some(n)
+}
+
+// Return the shared Bytes for a small integer value, or a copy of the input otherwise
+#[inline(always)]
+pub fn shared_or_owned(value: &Bytes) -> Bytes {
+    match shared_integer_index(value) {
+        Some(n) => SHARED_INTEGER_VALUES[n].clone(),
+        None => value.clone(),
+    }
+}
+
+// Format an integer result (INCR family), reusing the shared table when possible
+#[inline(always)]
+pub fn integer_to_bytes(n: i64) -> Bytes {
+    if (0..SHARED_INTEGERS as i64).contains(&n) {
+        SHARED_INTEGER_VALUES[n as usize].clone()
+    } else {
+        Bytes::from(n.to_string())
+    }
+}
+
+// True if the value points at the shared integer table
+#[inline(always)]
+pub fn is_shared_integer(value: &Bytes) -> bool {
+    shared_integer_index(value)
+        .is_some_and(|n| SHARED_INTEGER_VALUES[n].as_ptr() == value.as_ptr())
+}
+
+// ==================== Command Table ====================
+
+// Static command metadata in the shape Redis reports from COMMAND: arity
+// (negative = at least |arity| arguments, name included), flags, the key
+// positions (first key, last key with negatives counting from the end, step)
+// and the ACL categories the command belongs to, without the '@'.
+//
+// value_types are the TYPEs a command's existing keys may have; `execute`
+// replies WRONGTYPE before the handler runs if any key holds another type.
+// It is opt-in: keyspace commands (EXISTS, DEL, TYPE, TTL, EXPIRE, ...) and
+// overwriting writes like SET leave it empty and work on keys of any type.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i32,
+    pub flags: &'static [&'static str],
+    pub first_key: i32,
+    pub last_key: i32,
+    pub step: i32,
+    pub categories: &'static [&'static str],
+    pub value_types: &'static [&'static str],
+}
+
+pub const fn spec(
+    name: &'static str,
+    arity: i32,
+    flags: &'static [&'static str],
+    first_key: i32,
+    last_key: i32,
+    step: i32,
+    categories: &'static [&'static str],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        categories,
+        value_types: &[],
+    }
+}
+
+pub const WRITE: &[&str] = &["write", "denyoom"];
+pub const WRITE_FAST: &[&str] = &["write", "denyoom", "fast"];
+pub const READ: &[&str] = &["readonly"];
+pub const READ_FAST: &[&str] = &["readonly", "fast"];
+pub const FAST: &[&str] = &["fast"];
+pub const ADMIN: &[&str] = &["admin"];
+
+pub const READ_STRING_FAST: &[&str] = &["read", "string", "fast"];
+pub const WRITE_STRING_FAST: &[&str] = &["write", "string", "fast"];
+pub const ADMIN_DANGEROUS: &[&str] = &["admin", "slow", "dangerous"];
+pub const SORT_CATEGORIES: &[&str] = &["write", "set", "sortedset", "list", "slow", "dangerous"];
+
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    spec("get", 2, READ_FAST, 1, 1, 1, READ_STRING_FAST).typed(&["string"]),
+    spec("set", -3, WRITE, 1, 1, 1, &["write", "string", "slow"]),
+    spec("del", -2, &["write"], 1, -1, 1, &["keyspace", "write", "slow"]),
+    spec("unlock", 3, &["write", "fast"], 1, 1, 1, &["write", "string", "fast"]).typed(&["string"]),
+    spec("getdel", 2, &["write", "fast"], 1, 1, 1, &["write", "string", "fast"]).typed(&["string"]),
+    spec("getex", -2, &["write", "fast"], 1, 1, 1, &["write", "string", "fast"]).typed(&["string"]),
+    spec("exists", -2, READ_FAST, 1, -1, 1, &["keyspace", "read", "fast"]),
+    spec("mget", -2, READ_FAST, 1, -1, 1, READ_STRING_FAST),
+    spec("mset", -3, WRITE, 1, -1, 2, &["write", "string", "slow"]),
+    spec("incr", 2, WRITE_FAST, 1, 1, 1, WRITE_STRING_FAST).typed(&["string"]),
+    spec("decr", 2, WRITE_FAST, 1, 1, 1, WRITE_STRING_FAST).typed(&["string"]),
+    spec("incrby", 3, WRITE_FAST, 1, 1, 1, WRITE_STRING_FAST).typed(&["string"]),
+    spec("bitpos", -3, READ, 1, 1, 1, &["read", "bitmap", "slow"]).typed(&["string"]),
+    spec("bitfield", -2, WRITE, 1, 1, 1, &["write", "bitmap", "slow"]).typed(&["string"]),
+    spec("decrby", 3, WRITE_FAST, 1, 1, 1, WRITE_STRING_FAST).typed(&["string"]),
+    spec("expire", -3, &["write", "fast"], 1, 1, 1, &["keyspace", "write", "fast"]),
+    spec("expireat", -3, &["write", "fast"], 1, 1, 1, &["keyspace", "write", "fast"]),
+    spec("pexpireat", -3, &["write", "fast"], 1, 1, 1, &["keyspace", "write", "fast"]),
+    spec("persist", 2, &["write", "fast"], 1, 1, 1, &["keyspace", "write", "fast"]),
+    spec("ttl", 2, READ_FAST, 1, 1, 1, &["keyspace", "read", "fast"]),
+    spec("pttl", 2, READ_FAST, 1, 1, 1, &["keyspace", "read", "fast"]),
+    spec("type", 2, READ_FAST, 1, 1, 1, &["keyspace", "read", "fast"]),
+    spec("keys", 2, READ, 0, 0, 0, &["keyspace", "read", "slow", "dangerous"]),
+    spec("scan", -2, READ, 0, 0, 0, &["keyspace", "read", "slow"]),
+    spec("lpush", -3, WRITE_FAST, 1, 1, 1, &["write", "list", "fast"]).typed(&["list"]),
+    spec("rpush", -3, WRITE_FAST, 1, 1, 1, &["write", "list", "fast"]).typed(&["list"]),
+    spec("lrange", 4, READ, 1, 1, 1, &["read", "list", "slow"]).typed(&["list"]),
+    spec("llen", 2, READ_FAST, 1, 1, 1, &["read", "list", "fast"]).typed(&["list"]),
+    spec("lpop", -2, WRITE_FAST, 1, 1, 1, &["write", "list", "fast"]).typed(&["list"]),
+    spec("rpop", -2, WRITE_FAST, 1, 1, 1, &["write", "list", "fast"]).typed(&["list"]),
+    spec("lmpop", -4, &["write", "movablekeys"], 0, 0, 0, &["write", "list", "slow"])
+        .typed(&["list"]),
+    spec("sadd", -3, WRITE_FAST, 1, 1, 1, &["write", "set", "fast"]).typed(&["set"]),
+    spec("srem", -3, &["write", "fast"], 1, 1, 1, &["write", "set", "fast"]).typed(&["set"]),
+    spec("scard", 2, READ_FAST, 1, 1, 1, &["read", "set", "fast"]).typed(&["set"]),
+    spec("sismember", 3, READ_FAST, 1, 1, 1, &["read", "set", "fast"]).typed(&["set"]),
+    spec("smembers", 2, READ, 1, 1, 1, &["read", "set", "slow"]).typed(&["set"]),
+    spec("sintercard", -3, &["readonly", "movablekeys"], 0, 0, 0, &["read", "set", "slow"])
+        .typed(&["set"]),
+    spec("zadd", -4, WRITE_FAST, 1, 1, 1, &["write", "sortedset", "fast"]).typed(&["zset"]),
+    spec("zcard", 2, READ_FAST, 1, 1, 1, &["read", "sortedset", "fast"]).typed(&["zset"]),
+    spec("zscore", 3, READ_FAST, 1, 1, 1, &["read", "sortedset", "fast"]).typed(&["zset"]),
+    spec("zrange", -4, READ, 1, 1, 1, &["read", "sortedset", "slow"]).typed(&["zset"]),
+    spec("zmpop", -4, &["write", "movablekeys"], 0, 0, 0, &["write", "sortedset", "slow"])
+        .typed(&["zset"]),
+    spec("zintercard", -3, &["readonly", "movablekeys"], 0, 0, 0, &["read", "sortedset", "slow"])
+        .typed(&["zset", "set"]),
+    spec("sort", -2, &["write", "denyoom", "movablekeys"], 1, 1, 1, SORT_CATEGORIES)
+        .typed(&["list", "set", "zset"]),
+    spec("object", -2, READ, 2, 2, 1, &["keyspace", "read", "slow"]),
+    spec("dbsize", 1, READ_FAST, 0, 0, 0, &["keyspace", "read", "fast"]),
+    spec("randomkey", 1, READ, 0, 0, 0, &["keyspace", "read", "slow"]),
+    spec("flushdb", -1, &["write"], 0, 0, 0, &["keyspace", "write", "slow", "dangerous"]),
+    spec("flushall", -1, &["write"], 0, 0, 0, &["keyspace", "write", "slow", "dangerous"]),
+    spec("select", 2, &["loading", "stale", "fast"], 0, 0, 0, &["fast", "connection"]),
+    spec("ping", -1, FAST, 0, 0, 0, &["fast", "connection"]),
+    spec("auth", -2, &["noscript", "loading", "stale", "fast"], 0, 0, 0, &["fast", "connection"]),
+    spec("info", -1, &["loading", "stale"], 0, 0, 0, &["slow", "dangerous"]),
+    spec("config", -2, ADMIN, 0, 0, 0, ADMIN_DANGEROUS),
+    spec("command", -1, &["loading", "stale"], 0, 0, 0, &["slow", "connection"]),
+    spec(
+        "client", -2, &["admin", "noscript", "loading", "stale"], 0, 0, 0,
+        &["admin", "slow", "dangerous", "connection"],
+    ),
+    spec("memory", -2, READ, 0, 0, 0, &["read", "slow"]),
+    spec("save", 1, &["admin", "noscript"], 0, 0, 0, ADMIN_DANGEROUS),
+    spec("bgsave", -1, &["admin", "noscript"], 0, 0, 0, ADMIN_DANGEROUS),
+    spec("debug", -2, &["admin", "noscript", "loading", "stale"], 0, 0, 0, ADMIN_DANGEROUS),
+    spec("latency", -2, &["admin", "noscript", "loading", "stale"], 0, 0, 0, ADMIN_DANGEROUS),
+    spec("replconf", -1, &["admin", "noscript", "loading", "stale"], 0, 0, 0, ADMIN_DANGEROUS),
+    spec("lolwut", -1, READ_FAST, 0, 0, 0, &["read", "fast"]),
+    spec("acl", -2, &["noscript", "loading", "stale"], 0, 0, 0, &["slow"]),
+];
+
+pub fn lookup_command(name: &[u8]) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    pub const fn typed(mut self, value_types: &'static [&'static str]) -> Self {
+        self.value_types = value_types;
+        self
+    }
+
+    /// Keys whose existing values must have one of `value_types`: every key
+    /// but SORT's STORE destination, which is overwritten whatever it holds.
+    pub fn typed_keys<'a>(&self, command: &'a [Bytes]) -> Vec<&'a Bytes> {
+        if self.value_types.is_empty() {
+            return Vec::new();
+        }
+        if self.name == "sort" {
+            return command.get(1).into_iter().collect();
+        }
+        self.keys(command)
+    }
+
+    #[inline]
+    pub fn arity_ok(&self, argc: usize) -> bool {
+        if self.arity >= 0 {
+            argc == self.arity as usize
+        } else {
+            argc >= self.arity.unsigned_abs() as usize
+        }
+    }
+
+    /// Key arguments of a full command (name included) according to the key spec.
+    pub fn keys<'a>(&self, command: &'a [Bytes]) -> Vec<&'a Bytes> {
+        // SORT key [BY pattern] [LIMIT offset count] [GET pattern ...]
+        // [ASC|DESC] [ALPHA] [STORE destination]: the key, plus the
+        // destination found by skipping each option's arguments
+        if self.name == "sort" {
+            let mut keys: Vec<&Bytes> = command.get(1).into_iter().collect();
+            let mut i = 2;
+            while i < command.len() {
+                let option = &command[i];
+                if option.eq_ignore_ascii_case(b"store") {
+                    keys.extend(command.get(i + 1));
+                    i += 2;
+                } else if option.eq_ignore_ascii_case(b"limit") {
+                    i += 3;
+                } else if option.eq_ignore_ascii_case(b"by") || option.eq_ignore_ascii_case(b"get") {
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+            }
+            return keys;
+        }
+        // LMPOP/ZMPOP/*INTERCARD style: numkeys, then that many keys
+        if self.flags.contains(&"movablekeys") {
+            let numkeys = command
+                .get(1)
+                .and_then(|n| std::str::from_utf8(n).ok()?.parse::<usize>().ok())
+                .unwrap_or(0);
+            return command.iter().skip(2).take(numkeys).collect();
+        }
+        if self.first_key <= 0 || self.step <= 0 {
+            return Vec::new();
+        }
+        let last = if self.last_key < 0 {
+            command.len() as i32 + self.last_key
+        } else {
+            self.last_key
+        };
+        (self.first_key..=last.min(command.len() as i32 - 1))
+            .step_by(self.step as usize)
+            .map(|i| &command[i as usize])
+            .collect()
+    }
+}
+
+// ==================== ACL Rules ====================
+
+// Every category some command in COMMAND_TABLE belongs to, as ACL CAT lists them
+pub const ACL_CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "string",
+    "bitmap",
+    "admin",
+    "fast",
+    "slow",
+    "dangerous",
+    "connection",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclTarget {
+    All,
+    Category(&'static str),
+    Command(&'static str),
+}
+
+impl AclTarget {
+    pub fn matches(&self, spec: &CommandSpec) -> bool {
+        match self {
+            AclTarget::All => true,
+            AclTarget::Category(category) => spec.categories.contains(category),
+            AclTarget::Command(name) => spec.name == *name,
+        }
+    }
+}
+
+/// Command permissions built from Redis-style rules such as
+/// `+@read -@dangerous +del`. Rules apply left to right, so the last rule
+/// that matches a command decides; a command no rule matches is denied.
+#[derive(Debug, Clone)]
+pub struct AclRules {
+    pub rules: Vec<(bool, AclTarget)>,
+    pub allows_all: bool, // Precomputed so the default setup skips the table lookup
+}
+
+impl AclRules {
+    pub fn all() -> Self {
+        Self {
+            rules: vec![(true, AclTarget::All)],
+            allows_all: true,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for token in text.split_whitespace() {
+            let lower = token.to_ascii_lowercase();
+            let (allow, name) = match lower.as_str() {
+                "allcommands" => (true, "@all"),
+                "nocommands" => (false, "@all"),
+                _ => match lower.split_at_checked(1) {
+                    Some(("+", name)) => (true, name),
+                    Some(("-", name)) => (false, name),
+                    _ => return Err(format!("Error in ACL rule '{}': Syntax error", token)),
+                },
+            };
+            let target = match name.strip_prefix('@') {
+                Some("all") => AclTarget::All,
+                Some(category) => ACL_CATEGORIES
+                    .iter()
+                    .find(|known| **known == category)
+                    .map(|known| AclTarget::Category(known))
+                    .ok_or_else(|| format!("Error in ACL rule '{}': Unknown category", token))?,
+                None => lookup_command(name.as_bytes())
+                    .map(|spec| AclTarget::Command(spec.name))
+                    .ok_or_else(|| format!("Error in ACL rule '{}': Unknown command", token))?,
+            };
+            rules.push((allow, target));
+        }
+        let mut acl = Self {
+            rules,
+            allows_all: false,
+        };
+        acl.allows_all = COMMAND_TABLE.iter().all(|spec| acl.allows(spec));
+        Ok(acl)
+    }
+
+    pub fn allows(&self, spec: &CommandSpec) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(_, target)| target.matches(spec))
+            .is_some_and(|(allow, _)| *allow)
+    }
+
+    // Rules in the form ACL GETUSER reports them
+    pub fn describe(&self) -> String {
+        let rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|(allow, target)| {
+                let sign = if *allow { '+' } else { '-' };
+                match target {
+                    AclTarget::All => format!("{}@all", sign),
+                    AclTarget::Category(category) => format!("{}@{}", sign, category),
+                    AclTarget::Command(name) => format!("{}{}", sign, name),
+                }
+            })
+            .collect();
+        rules.join(" ")
+    }
+}
+
+// Rules every connection starts with (security.acl_rules; empty allows everything)
+pub static DEFAULT_ACL: Lazy<Arc<AclRules>> = Lazy::new(|| {
+    if CONFIG.security.acl_rules.is_empty() {
+        Arc::new(AclRules::all())
+    } else {
+        // Config::validate has already parsed these once
+        Arc::new(AclRules::parse(&CONFIG.security.acl_rules).expect("invalid acl_rules"))
+    }
+});
+
+// Longest error text written to a client, in input bytes
+pub const MAX_ERROR_LEN: usize = 1024;
+
+// Error replies are a single RESP line, so control bytes (CR/LF above all)
+// are hex-escaped: echoed user input must never end the line early and
+// smuggle in a forged reply. The text is also capped at MAX_ERROR_LEN.
+pub fn push_error_text(out: &mut Vec<u8>, text: &[u8]) {
+    let text = &text[..text.len().min(MAX_ERROR_LEN)];
+    // Nearly every error is a static message with nothing to escape
+    if !text.iter().any(|b| b.is_ascii_control()) {
+        out.extend_from_slice(text);
+        return;
+    }
+    for &b in text {
+        if b.is_ascii_control() {
+            out.extend_from_slice(format!("\\x{:02x}", b).as_bytes());
+        } else {
+            out.push(b);
+        }
+    }
+}
+
+// Append bytes for an error message, escaping anything non-printable as \xHH
+pub fn push_escaped(out: &mut String, bytes: &[u8]) {
+    for &b in bytes {
+        if (0x20..0x7f).contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+}
+
+/// Redis-style unknown command message: echoes the (escaped) name and a
+/// preview of the first arguments, capped at roughly 128 bytes.
+pub fn unknown_command_message(command: &[Bytes]) -> String {
+    const PREVIEW: usize = 128;
+
+    let mut name = String::new();
+    push_escaped(&mut name, &command[0][..command[0].len().min(PREVIEW)]);
+
+    let mut args = String::new();
+    for arg in &command[1..] {
+        if args.len() >= PREVIEW {
+            break;
+        }
+        let room = PREVIEW - args.len();
+        args.push('\'');
+        push_escaped(&mut args, &arg[..arg.len().min(room)]);
+        args.push_str("' ");
+    }
+
+    format!("unknown command '{}', with args beginning with: {}", name, args)
+}
+
+// COMMAND reply entry: [name, arity, [flags], first key, last key, step, [categories]]
+pub fn command_spec_reply(spec: &CommandSpec) -> Reply {
+    let flags = spec.flags.iter().map(|flag| Reply::SimpleString(Bytes::from_static(flag.as_bytes())));
+    let categories = spec
+        .categories
+        .iter()
+        .map(|category| Reply::SimpleString(format!("@{}", category).into()));
+    Reply::Array(vec![
+        Reply::BulkString(Bytes::from_static(spec.name.as_bytes())),
+        Reply::Integer(spec.arity as i64),
+        Reply::Array(flags.collect()),
+        Reply::Integer(spec.first_key as i64),
+        Reply::Integer(spec.last_key as i64),
+        Reply::Integer(spec.step as i64),
+        Reply::Array(categories.collect()),
+    ])
+}
+
+// Per-connection details shared with the client registry (CLIENT LIST/INFO).
+// Counters are atomics so other connections can read them without locking.
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: String,
+    pub connected_at: u64,
+    pub last_active: AtomicU64,
+    pub tot_cmds: AtomicU64,
+    pub last_cmd: Mutex<Bytes>,
+    pub output_bytes: AtomicU64, // Replies buffered and not yet sent
+    pub no_evict: AtomicBool,    // CLIENT NO-EVICT: never closed for max_memory_clients
+    pub evicted: AtomicBool,
+    pub evict: tokio::sync::Notify, // Wakes the connection to close it
+}
+
+impl ClientInfo {
+    pub fn new(id: u64, addr: String, now: u64) -> Self {
+        Self {
+            id,
+            addr,
+            connected_at: now,
+            last_active: AtomicU64::new(now),
+            tot_cmds: AtomicU64::new(0),
+            last_cmd: Mutex::new(Bytes::new()),
+            output_bytes: AtomicU64::new(0),
+            no_evict: AtomicBool::new(false),
+            evicted: AtomicBool::new(false),
+            evict: tokio::sync::Notify::new(),
+        }
+    }
+
+    // Record how many reply bytes this client has waiting, keeping
+    // CLIENT_OUTPUT_BYTES in step
+    pub fn set_output_bytes(&self, bytes: u64) {
+        let old = self.output_bytes.swap(bytes, Ordering::Relaxed);
+        if bytes > old {
+            CLIENT_OUTPUT_BYTES.fetch_add(bytes - old, Ordering::Relaxed);
+        } else {
+            CLIENT_OUTPUT_BYTES.fetch_sub(old - bytes, Ordering::Relaxed);
+        }
+    }
+
+    #[inline(always)]
+    pub fn record_command(&self, name: &Bytes, now: u64) {
+        self.tot_cmds.fetch_add(1, Ordering::Relaxed);
+        self.last_active.store(now, Ordering::Relaxed);
+        *self.last_cmd.lock() = name.clone();
+    }
+
+    // One CLIENT LIST line (without the trailing newline)
+    pub fn info_line(&self, now: u64) -> String {
+        let last_cmd = self.last_cmd.lock();
+        let cmd = if last_cmd.is_empty() {
+            "NULL".to_string()
+        } else {
+            String::from_utf8_lossy(&last_cmd).to_ascii_lowercase()
+        };
+        format!(
+            "id={} addr={} age={} idle={} cmd={} tot-cmds={}",
+            self.id,
+            self.addr,
+            now.saturating_sub(self.connected_at),
+            now.saturating_sub(self.last_active.load(Ordering::Relaxed)),
+            cmd,
+            self.tot_cmds.load(Ordering::Relaxed)
+        )
+    }
+}
+
+// Connected clients by id
+pub static CLIENTS: Lazy<DashMap<u64, Arc<ClientInfo>>> = Lazy::new(DashMap::new);
+pub static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Close the client with the most unsent reply bytes, skipping NO-EVICT ones
+// and any already on their way out. Returns its id.
+pub fn evict_largest_client() -> Option<u64> {
+    let largest = CLIENTS
+        .iter()
+        .filter(|c| !c.no_evict.load(Ordering::Relaxed) && !c.evicted.load(Ordering::Relaxed))
+        .max_by_key(|c| c.output_bytes.load(Ordering::Relaxed))
+        .filter(|c| c.output_bytes.load(Ordering::Relaxed) > 0)
+        .map(|c| c.value().clone())?;
+    // Two writers over budget at once may pick the same client; count it once
+    if largest.evicted.swap(true, Ordering::AcqRel) {
+        return None;
+    }
+    EVICTED_CLIENTS.fetch_add(1, Ordering::Relaxed);
+    largest.evict.notify_one();
+    Some(largest.id)
+}
+
+// Work execute_command leaves to the connection loop because it has to await
+pub enum DeferredReply {
+    // Reply too large to build in memory: written straight to the socket,
+    // flushing as it goes
+    Keys { count: usize, now: u64 },
+    // Failed AUTH: the error is already buffered, and is held back for
+    // security.auth_failure_delay_ms so a wrong password is not answered
+    // measurably faster than a right one
+    AuthFailure,
+    // DEBUG SLEEP-BEFORE-REPLY: the reply is held back this long, delaying
+    // only this connection
+    Sleep(Duration),
+}
+
+// Connection state for authentication
+pub struct ConnectionState {
+    pub authenticated: bool,
+    pub db: usize, // Database selected with SELECT
+    pub client: Arc<ClientInfo>,
+    pub deferred: Option<DeferredReply>,
+    pub no_touch: bool, // CLIENT NO-TOUCH: reads leave LRU times alone
+    pub acl: Arc<AclRules>, // Commands this connection may run
+}
+
+impl ConnectionState {
+    pub fn new(addr: String) -> Self {
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        let client = Arc::new(ClientInfo::new(id, addr, get_timestamp()));
+        CLIENTS.insert(id, client.clone());
+        Self {
+            // If no password is set, authentication is not required
+            authenticated: PASSWORD.read().is_empty(),
+            db: 0,
+            client,
+            deferred: None,
+            no_touch: false,
+            acl: DEFAULT_ACL.clone(),
+        }
+    }
+}
+
+impl Drop for ConnectionState {
+    fn drop(&mut self) {
+        self.client.set_output_bytes(0);
+        CLIENTS.remove(&self.client.id);
+    }
+}
+
+// Eviction: ensure memory is available. Starts with the database being
+// written to and moves on to the others once it has nothing left to evict.
+#[inline(always)]
+pub fn evict_if_needed(dbs: &[ShardedStore], db: usize, needed_size: usize) -> bool {
+    let max_memory = max_memory();
+
+    // Fast path: unlimited memory (zero-cost)
+    if max_memory == 0 {
+        return true;
+    }
+
+    // Check if eviction needed (relaxed for performance)
+    let current = MEMORY_USED.load(Ordering::Relaxed);
+    if current + needed_size as u64 <= max_memory {
+        return true;
+    }
+
+    // Get eviction policy
+    let policy = eviction_policy();
+
+    // No eviction policy - reject new keys
+    if policy == EvictionPolicy::NoEviction {
+        return false;
+    }
+
+    // Free down to the low watermark in one bulk pass so a steady write load
+    // doesn't evict on every command. Only one thread runs the bulk pass at a
+    // time; concurrent writers just free what they need.
+    let bulk = !EVICTING.swap(true, Ordering::AcqRel);
+    let (goal, max_attempts) = if bulk {
+        let goal = eviction_goal(
+            current,
+            needed_size,
+            max_memory,
+            CONFIG.memory.max_memory_low_pct,
+        );
+        (goal, MAX_BULK_EVICTIONS)
+    } else {
+        (needed_size, 100) // Prevent infinite loop
+    };
+
+    let mut freed = 0;
+    let mut attempts = 0;
+    let mut db = db;
+    let mut exhausted = 0;
+
+    while freed < goal && attempts < max_attempts {
+        attempts += 1;
+
+        let store = &dbs[db];
+        let evicted = match policy {
+            EvictionPolicy::AllKeysLru => evict_lru(store),
+            EvictionPolicy::AllKeysRandom => evict_random(store),
+            EvictionPolicy::NoEviction => break,
+        };
+
+        if evicted == 0 {
+            exhausted += 1;
+            if exhausted == dbs.len() {
+                break; // No more keys to evict
+            }
+            db = (db + 1) % dbs.len();
+            continue;
+        }
+
+        exhausted = 0;
+        freed += evicted;
+    }
+
+    if bulk {
+        EVICTING.store(false, Ordering::Release);
+    }
+
+    freed >= needed_size
+}
+
+// Upper bound on keys evicted by one bulk pass (bounds write latency)
+pub const MAX_BULK_EVICTIONS: usize = 1000;
+
+// Bytes a bulk eviction pass should free: enough to bring usage (plus the
+// pending write) down to low_pct of max_memory, and never less than needed.
+#[inline]
+pub fn eviction_goal(current: u64, needed: usize, max_memory: u64, low_pct: u64) -> usize {
+    let low_watermark = max_memory / 100 * low_pct.min(100);
+    let over = (current + needed as u64).saturating_sub(low_watermark) as usize;
+    over.max(needed)
+}
+
+// Evict using LRU policy
+#[inline]
+pub fn evict_lru(store: &ShardedStore) -> usize {
+    let sample_size = CONFIG.memory.eviction_sample_size;
+
+    // Sample keys uniformly across shards, then evict the oldest
+    let sample = store.random_keys(sample_size);
+    if let Some((oldest_shard_idx, key)) = oldest_in_sample(store, sample.into_iter()) {
+        let key_len = key.len();
+        let shard = &store.shards[oldest_shard_idx];
+        if let Some((_, entry)) = shard.remove(&key) {
+            let size = entry_size(key_len, entry.value.mem_size());
+            MEMORY_USED.fetch_sub(size as u64, Ordering::Relaxed);
+            EVICTED_KEYS.fetch_add(1, Ordering::Relaxed);
+            return size;
+        }
+    }
+
+    0
+}
+
+// Least recently used key among sampled (shard index, key) pairs
+pub fn oldest_in_sample(
+    store: &ShardedStore,
+    sample: impl Iterator<Item = (usize, Bytes)>,
+) -> Option<(usize, Bytes)> {
+    let mut oldest: Option<(u32, usize, Bytes)> = None;
+    for (shard_idx, key) in sample {
+        let Some(entry) = store.shards[shard_idx].get(&key) else {
+            continue;
+        };
+        let last_accessed = entry.last_accessed.load(Ordering::Relaxed);
+        drop(entry);
+        if oldest.as_ref().is_none_or(|(time, _, _)| last_accessed < *time) {
+            oldest = Some((last_accessed, shard_idx, key));
+        }
+    }
+    oldest.map(|(_, shard_idx, key)| (shard_idx, key))
+}
+
+// Draws RANDOMKEY makes before giving up on a store full of expired keys
+pub const RANDOMKEY_ATTEMPTS: usize = 100;
+
+// Evict using random policy
+#[inline]
+pub fn evict_random(store: &ShardedStore) -> usize {
+    // One key drawn uniformly from the whole store
+    if let Some((shard_idx, key)) = store.random_keys(1).pop()
+        && let Some((key, entry)) = store.shards[shard_idx].remove(&key)
+    {
+        let size = entry_size(key.len(), entry.value.mem_size());
+        MEMORY_USED.fetch_sub(size as u64, Ordering::Relaxed);
+        EVICTED_KEYS.fetch_add(1, Ordering::Relaxed);
+        return size;
+    }
+
+    0
+}
+
+// ==================== Memory Doctor ====================
+
+// Key statistics gathered from a bounded scan of the store
+#[derive(Debug, Default)]
+pub struct KeySample {
+    pub keys: usize,
+    pub with_expiry: usize,
+    pub value_bytes: usize,
+}
+
+// Scan up to `limit` entries, starting from a random shard
+pub fn sample_keys(store: &ShardedStore, limit: usize) -> KeySample {
+    let mut sample = KeySample::default();
+    let start = store.random_below(store.num_shards);
+
+    for i in 0..store.num_shards {
+        let shard = &store.shards[(start + i) % store.num_shards];
+        for entry in shard.iter() {
+            if sample.keys >= limit {
+                return sample;
+            }
+            sample.keys += 1;
+            if entry.value().expiry.is_some() {
+                sample.with_expiry += 1;
+            }
+            sample.value_bytes += entry.value().value.mem_size();
+        }
+    }
+
+    sample
+}
+
+// Human-readable MEMORY DOCTOR report from the memory counters and a key sample
+pub fn memory_doctor_report(
+    used: u64,
+    max_memory: u64,
+    policy: &str,
+    total_keys: usize,
+    sample: &KeySample,
+) -> String {
+    let mut report = String::new();
+    let mut warnings = Vec::new();
+
+    if let Some(pct) = (used * 100).checked_div(max_memory) {
+        report.push_str(&format!(
+            "Memory usage: {} of {} max_memory ({}%)\n",
+            format_bytes(used),
+            format_bytes(max_memory),
+            pct
+        ));
+        if pct >= 90 {
+            warnings.push(format!(
+                "Used memory is at {}% of max_memory; writes are about to trigger eviction",
+                pct
+            ));
+        }
+        if policy == "noeviction" {
+            warnings.push(
+                "noeviction is set with a max_memory limit: writes fail with OOM errors once the limit is reached"
+                    .to_string(),
+            );
+        }
+        if max_memory < 1024 * 1024 {
+            warnings.push(format!(
+                "max_memory is very small ({}); most datasets will not fit",
+                format_bytes(max_memory)
+            ));
+        }
+    } else {
+        report.push_str("Memory usage: not tracked (no max_memory limit set)\n");
+    }
+    report.push_str(&format!("Eviction policy: {}\n", policy));
+
+    report.push_str(&format!("Keys: {}\n", total_keys));
+    if let Some(expiry_pct) = (sample.with_expiry * 100).checked_div(sample.keys) {
+        report.push_str(&format!(
+            "Keys with an expiry: ~{}% (~{} keys, sampled {})\n",
+            expiry_pct,
+            total_keys * sample.with_expiry / sample.keys,
+            sample.keys
+        ));
+        report.push_str(&format!(
+            "Average value size: {} (sampled)\n",
+            format_bytes((sample.value_bytes / sample.keys) as u64)
+        ));
+    }
+
+    if warnings.is_empty() {
+        report.push_str("No issues detected.\n");
+    } else {
+        for warning in warnings {
+            report.push_str("WARNING: ");
+            report.push_str(&warning);
+            report.push('\n');
+        }
+    }
+
+    report
+}
+
+// ==================== Replication IDs ====================
+
+// Random 40-hex-char replication ID, as Redis generates for master_replid
+pub fn new_repl_id() -> String {
+    (0..40)
+        .map(|_| char::from_digit(fastrand::u32(..16), 16).unwrap())
+        .collect()
+}
+
+// Size of a command on the replication stream: its RESP multibulk encoding
+pub fn resp_encoded_len(command: &[Bytes]) -> usize {
+    let digits = |n: usize| n.checked_ilog10().unwrap_or(0) as usize + 1;
+    let header = 1 + digits(command.len()) + 2;
+    header
+        + command
+            .iter()
+            .map(|arg| 1 + digits(arg.len()) + 2 + arg.len() + 2)
+            .sum::<usize>()
+}
+
+/// Called with every write as the command that repeats it, and the index of
+/// the database it changed. Commands whose effect depends on when they ran
+/// arrive rewritten with absolute times (`SET key value PXAT ms`,
+/// `PEXPIREAT key ms`), so an AOF or a replica replaying them later gets
+/// the same expiries. Hooks run on the connection's thread before the reply
+/// is written and must not block.
+pub type PropagationHook = Box<dyn Fn(usize, &[Bytes]) + Send + Sync>;
+
+pub static PROPAGATION_HOOKS: Lazy<RwLock<Vec<PropagationHook>>> = Lazy::new(|| RwLock::new(Vec::new()));
+// Lets writes skip the lock until the first hook is added
+pub static PROPAGATION_HOOKED: AtomicBool = AtomicBool::new(false);
+
+pub fn add_propagation_hook(hook: PropagationHook) {
+    PROPAGATION_HOOKS.write().push(hook);
+    PROPAGATION_HOOKED.store(true, Ordering::Release);
+}
+
+// Account a successful write on the replication stream and hand it to the hooks
+#[inline]
+pub fn propagate(db: usize, command: &[Bytes]) {
+    REPL_OFFSET.fetch_add(resp_encoded_len(command) as u64, Ordering::Relaxed);
+    DIRTY.fetch_add(1, Ordering::Relaxed);
+    if PROPAGATION_HOOKED.load(Ordering::Acquire) {
+        for hook in PROPAGATION_HOOKS.read().iter() {
+            hook(db, command);
+        }
+    }
+}
+
+// How a key's new expiry (unix seconds) is propagated: PEXPIREAT while it
+// lies ahead, DEL once it has passed and the key is gone
+pub fn propagate_expiry(db: usize, key: &Bytes, expiry: u64, now: u64) {
+    if expiry > now {
+        let millis = Bytes::from(expiry.saturating_mul(1000).to_string());
+        propagate(db, &[Bytes::from_static(b"PEXPIREAT"), key.clone(), millis]);
+    } else {
+        propagate(db, &[Bytes::from_static(b"DEL"), key.clone()]);
+    }
+}
+
+// ==================== Snapshots ====================
+
+// Snapshot layout (little-endian): magic, then one record per key as
+// [type u8][expiry u64, 0 = none][key], then SNAPSHOT_EOF. Keys belong to
+// database 0 until a SNAPSHOT_SELECT_DB record [0xFE][index u32] switches it. Each key or string
+// is [len u32][bytes]; a list value is [count u32] followed by its elements,
+// a set is [count u32] followed by its members, and a sorted set is
+// [count u32] followed by [member][score f64] pairs.
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"RDSTL001";
+pub const SNAPSHOT_STRING: u8 = 0;
+pub const SNAPSHOT_LIST: u8 = 1;
+pub const SNAPSHOT_ZSET: u8 = 2;
+pub const SNAPSHOT_SET: u8 = 3;
+pub const SNAPSHOT_SELECT_DB: u8 = 0xFE;
+pub const SNAPSHOT_EOF: u8 = 0xFF;
+
+pub fn write_blob<W: io::Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(data)
+}
+
+pub fn read_u32<R: io::Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_blob<R: io::Read>(input: &mut R) -> io::Result<Bytes> {
+    let len = read_u32(input)? as usize;
+    let mut data = vec![0u8; len];
+    input.read_exact(&mut data)?;
+    Ok(Bytes::from(data))
+}
+
+/// Write every live key of every database to `out`. Returns the number of keys written.
+pub fn write_snapshot<W: io::Write>(dbs: &[ShardedStore], out: &mut W, now: u64) -> io::Result<usize> {
+    write_snapshot_paced(dbs, out, now, Duration::ZERO)
+}
+
+/// `write_snapshot`, sleeping `key_delay` after each key so tests can catch
+/// a save part way through (DEBUG SET-SAVE-DELAY)
+pub fn write_snapshot_paced<W: io::Write>(
+    dbs: &[ShardedStore],
+    out: &mut W,
+    now: u64,
+    key_delay: Duration,
+) -> io::Result<usize> {
+    out.write_all(SNAPSHOT_MAGIC)?;
+    let mut count = 0;
+
+    for (index, store) in dbs.iter().enumerate() {
+        if index > 0 {
+            if store.is_empty() {
+                continue;
+            }
+            out.write_all(&[SNAPSHOT_SELECT_DB])?;
+            out.write_all(&(index as u32).to_le_bytes())?;
+        }
+        count += write_snapshot_db(store, out, now, key_delay)?;
+    }
+
+    out.write_all(&[SNAPSHOT_EOF])?;
+    Ok(count)
+}
+
+/// Copy a shard as it is at one moment. All of the map's inner locks are
+/// held for reading while their tables are cloned, so a write lands either
+/// wholly before the copy or after it; writers to this shard wait only for
+/// the copy, never for what the caller then does with it. Until it is dropped
+/// the copy is a second instance of the shard: string values are `Bytes` and
+/// share their data, but lists, sets and sorted sets are duplicated.
+///
+/// The locks are taken one after another, which is safe because no caller
+/// holds one key's entry in a shard while locking another key of it.
+pub fn freeze_shard(shard: &DashMap<Bytes, Entry>) -> DashMap<Bytes, Entry> {
+    let tables: Vec<_> = shard.shards().iter().map(|table| table.read()).collect();
+    let mut copy = DashMap::with_hasher_and_shard_amount(shard.hasher().clone(), tables.len());
+    for (target, table) in copy.shards_mut().iter_mut().zip(&tables) {
+        *target.get_mut() = (**table).clone();
+    }
+    copy
+}
+
+// Shards are frozen one at a time, so the file holds each shard as of one
+// moment (a key and anything else in its shard agree) while only one extra
+// shard is in memory at once. Keys in different shards are copied at
+// different moments.
+pub fn write_snapshot_db<W: io::Write>(
+    store: &ShardedStore,
+    out: &mut W,
+    now: u64,
+    key_delay: Duration,
+) -> io::Result<usize> {
+    let mut count = 0;
+
+    for shard in &store.shards {
+        let frozen = freeze_shard(shard);
+        for entry in frozen.iter() {
+            if entry.is_expired(now) {
+                continue;
+            }
+            let kind = match entry.value {
+                Value::String(_) | Value::Int(_) => SNAPSHOT_STRING,
+                Value::List(_) => SNAPSHOT_LIST,
+                Value::Set(_) => SNAPSHOT_SET,
+                Value::ZSet(_) => SNAPSHOT_ZSET,
+            };
+            out.write_all(&[kind])?;
+            out.write_all(&entry.expiry.unwrap_or(0).to_le_bytes())?;
+            write_blob(out, entry.key())?;
+            match &entry.value {
+                Value::String(value) => write_blob(out, value)?,
+                Value::Int(n) => write_blob(out, n.to_string().as_bytes())?,
+                Value::List(list) => {
+                    out.write_all(&(list.len() as u32).to_le_bytes())?;
+                    for item in list.iter() {
+                        write_blob(out, &item)?;
+                    }
+                }
+                Value::Set(set) => {
+                    out.write_all(&(set.len() as u32).to_le_bytes())?;
+                    for member in set.members() {
+                        write_blob(out, &member)?;
+                    }
+                }
+                Value::ZSet(zset) => {
+                    out.write_all(&(zset.len() as u32).to_le_bytes())?;
+                    for (member, score) in zset.iter() {
+                        write_blob(out, member)?;
+                        out.write_all(&score.to_le_bytes())?;
+                    }
+                }
+            }
+            count += 1;
+            if !key_delay.is_zero() {
+                std::thread::sleep(key_delay);
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+/// Load a snapshot written by `write_snapshot`, skipping keys that have
+/// expired since. Returns the number of keys loaded.
+pub fn read_snapshot<R: io::Read>(dbs: &[ShardedStore], input: &mut R, now: u64) -> io::Result<usize> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != SNAPSHOT_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a snapshot file"));
+    }
+
+    let mut store = &dbs[0];
+    let mut count = 0;
+    loop {
+        let mut kind = [0u8; 1];
+        input.read_exact(&mut kind)?;
+        if kind[0] == SNAPSHOT_EOF {
+            break;
+        }
+        if kind[0] == SNAPSHOT_SELECT_DB {
+            let index = read_u32(input)? as usize;
+            store = dbs.get(index).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "database index out of range")
+            })?;
+            continue;
+        }
+        let mut expiry = [0u8; 8];
+        input.read_exact(&mut expiry)?;
+        let expiry = Some(u64::from_le_bytes(expiry)).filter(|&e| e != 0);
+        let key = read_blob(input)?;
+        let value = match kind[0] {
+            SNAPSHOT_STRING => Value::string(read_blob(input)?),
+            SNAPSHOT_LIST => {
+                let len = read_u32(input)?;
+                let mut list = ListValue::default();
+                for _ in 0..len {
+                    list.push(read_blob(input)?, false);
+                }
+                Value::List(list)
+            }
+            SNAPSHOT_ZSET => {
+                let len = read_u32(input)?;
+                let mut zset = SortedSet::default();
+                for _ in 0..len {
+                    let member = read_blob(input)?;
+                    let mut score = [0u8; 8];
+                    input.read_exact(&mut score)?;
+                    zset.insert(member, f64::from_le_bytes(score));
+                }
+                Value::ZSet(zset)
+            }
+            SNAPSHOT_SET => {
+                let len = read_u32(input)?;
+                // Re-encoded as it loads, under the current intset limit
+                let mut set = SetValue::default();
+                for _ in 0..len {
+                    set.insert(read_blob(input)?);
+                }
+                Value::Set(set)
+            }
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown record type"));
+            }
+        };
+
+        let entry = Entry::new(value, expiry);
+        if entry.is_expired(now) {
+            continue;
+        }
+        if max_memory() > 0 {
+            let size = entry_size(key.len(), entry.value.mem_size());
+            add_memory_used(size as u64);
+        }
+        store.shards[store.hash(&key)].insert(key, entry);
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Save to `path`, and on success take the writes it covers off DIRTY.
+// Writes made while it ran stay counted for the next save.
+pub fn save_snapshot(dbs: &[ShardedStore], path: &str) -> io::Result<usize> {
+    let dirty = DIRTY.load(Ordering::Relaxed);
+    let result = write_snapshot_file(dbs, path);
+    if result.is_ok() {
+        DIRTY.fetch_sub(dirty, Ordering::Relaxed);
+        LAST_SAVE.store(get_timestamp(), Ordering::Relaxed);
+    }
+    LAST_SAVE_OK.store(result.is_ok(), Ordering::Relaxed);
+    result
+}
+
+// Save atomically: write a temp file next to the target, then rename over it
+pub fn write_snapshot_file(dbs: &[ShardedStore], path: &str) -> io::Result<usize> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut out = io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+    let key_delay = Duration::from_micros(SAVE_KEY_DELAY_US.load(Ordering::Relaxed));
+    let count = write_snapshot_paced(dbs, &mut out, get_timestamp(), key_delay)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(count)
+}
+
+// Snapshot on a blocking thread while commands keep running. False if a
+// save is already in progress.
+pub fn start_bgsave(dbs: &[ShardedStore]) -> bool {
+    if BGSAVE_IN_PROGRESS.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let dbs: Vec<ShardedStore> = dbs.iter().map(ShardedStore::clone).collect();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = save_snapshot(&dbs, &CONFIG.persistence.snapshot_path) {
+            eprintln!("❌ Background save failed: {}", e);
+        }
+        BGSAVE_IN_PROGRESS.store(false, Ordering::Release);
+    });
+    true
+}
+
+// Seconds to wait after a failed autosave before trying again
+pub const AUTOSAVE_RETRY_DELAY: u64 = 5;
+
+// BGSAVE whenever one of the `save` points is met, until `stopped` fires
+pub async fn autosave_task(
+    dbs: Arc<Vec<ShardedStore>>,
+    points: Vec<SavePoint>,
+    mut stopped: tokio::sync::watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut last_attempt = 0;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop_requested(&mut stopped) => break,
+        }
+        let now = get_timestamp();
+        if !LAST_SAVE_OK.load(Ordering::Relaxed) && now < last_attempt + AUTOSAVE_RETRY_DELAY {
+            continue;
+        }
+        let elapsed = now.saturating_sub(LAST_SAVE.load(Ordering::Relaxed));
+        if save_due(&points, DIRTY.load(Ordering::Relaxed), elapsed) && start_bgsave(&dbs) {
+            last_attempt = now;
+        }
+    }
+}
+
+pub fn load_snapshot(dbs: &[ShardedStore], path: &str) -> io::Result<usize> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(0);
+    }
+    let mut input = io::BufReader::new(std::fs::File::open(path)?);
+    read_snapshot(dbs, &mut input, get_timestamp())
+}
+
+// DEBUG RELOAD: save, empty every database and load the file back, which
+// shows whether the snapshot format keeps everything that was in memory
+pub fn reload_snapshot(dbs: &[ShardedStore], path: &str) -> io::Result<usize> {
+    save_snapshot(dbs, path)?;
+    let freed: usize = dbs.iter().map(ShardedStore::clear).sum();
+    if max_memory() > 0 {
+        MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+    }
+    load_snapshot(dbs, path)
+}
+
+// Passive key expiration: scan random keys and remove expired ones
+// This runs in a background task to clean up keys that are never accessed
+pub fn expire_random_keys(store: &ShardedStore, sample_size: usize) -> usize {
+    let now = get_timestamp();
+    let mut expired_count = 0;
+    
+    for _ in 0..sample_size {
+        // Pick a random shard
+        let shard_idx = store.random_below(store.num_shards);
+        let shard = &store.shards[shard_idx];
+        
+        // Check first entry in the shard, releasing the iterator before removing
+        let expired = shard
+            .iter()
+            .next()
+            .filter(|entry| entry.value().is_expired(now))
+            .map(|entry| (entry.key().clone(), entry.value().value.mem_size()));
+        if let Some((key, value_len)) = expired {
+            let key_len = key.len();
+
+            // Remove expired key
+            if shard.remove(&key).is_some() {
+                expired_count += 1;
+                if max_memory() > 0 {
+                    let size = entry_size(key_len, value_len);
+                    MEMORY_USED.fetch_sub(size as u64, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+    
+    expired_count
+}
+
+// Background task for passive key expiration, until `stopped` fires
+pub async fn expiration_task(dbs: Arc<Vec<ShardedStore>>, mut stopped: tokio::sync::watch::Receiver<bool>) {
+    // Run every 100ms, check 20 random keys per iteration
+    // This is similar to Redis's passive expiration strategy
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop_requested(&mut stopped) => break,
+        }
+        if !ACTIVE_EXPIRE.load(Ordering::Relaxed) {
+            continue;
+        }
+        for store in dbs.iter() {
+            let expired = match &store.expiry_index {
+                // With the index, up to 200 due keys per shard per tick
+                Some(_) => store.expire_indexed(get_timestamp(), 200),
+                None => expire_random_keys(store, 20),
+            };
+            EXPIRED_KEYS.fetch_add(expired as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+// GETEX key [EX seconds | PX milliseconds | EXAT unix-time | PXAT unix-time-ms | PERSIST]
+pub fn getex_command(dbs: &[ShardedStore], db: usize, command: &[Bytes], now: u64) -> Reply {
+    // None leaves the TTL alone, Some(None) removes it
+    let expiry = match &command[2..] {
+        [] => None,
+        [opt] if opt.eq_ignore_ascii_case(b"persist") => Some(None),
+        [opt, time] => {
+            let Some(time) = parse_u64(time) else {
+                return Reply::error(b"value is not an integer or out of range");
+            };
+            if time == 0 {
+                return Reply::error(b"invalid expire time in 'getex' command");
+            }
+            // Milliseconds round up to whole seconds, as for SET PX
+            let at = if opt.eq_ignore_ascii_case(b"ex") {
+                now.saturating_add(time)
+            } else if opt.eq_ignore_ascii_case(b"px") {
+                now.saturating_add(time.div_ceil(1000))
+            } else if opt.eq_ignore_ascii_case(b"exat") {
+                time
+            } else if opt.eq_ignore_ascii_case(b"pxat") {
+                time.div_ceil(1000)
+            } else {
+                return Reply::error(b"syntax error");
+            };
+            Some(Some(at))
+        }
+        _ => return Reply::error(b"syntax error"),
+    };
+
+    match dbs[db].get_ex(&command[1], expiry, now) {
+        Ok((value, freed)) => {
+            if max_memory() > 0 && freed > 0 {
+                MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+            }
+            match expiry {
+                _ if value.is_none() => {}
+                Some(Some(at)) => propagate_expiry(db, &command[1], at, now),
+                Some(None) => propagate(db, &[Bytes::from_static(b"PERSIST"), command[1].clone()]),
+                None => {}
+            }
+            value.map_or(Reply::Null, Reply::BulkString)
+        }
+        Err(_) => Reply::wrongtype(),
+    }
+}
+
+// EXPIRE, EXPIREAT and PEXPIREAT: `expiry` turns the time argument into an
+// absolute expiry in unix seconds, and a time already past deletes the key
+pub fn expire_command(
+    dbs: &[ShardedStore],
+    db: usize,
+    command: &[Bytes],
+    now: u64,
+    expiry: impl FnOnce(i64) -> u64,
+) -> Reply {
+    if command.len() < 3 {
+        return Reply::error(b"wrong number of arguments");
+    }
+    let Some(time) = parse_i64(&command[2]) else {
+        return Reply::error(b"value is not an integer or out of range");
+    };
+    let condition = match ExpireCondition::parse(&command[3..]) {
+        Ok(condition) => condition,
+        Err(message) => return Reply::error(&message),
+    };
+    let expiry = expiry(time);
+    let applied = dbs[db].expire_at(&command[1], expiry, condition, now);
+    if applied {
+        propagate_expiry(db, &command[1], expiry, now);
+    }
+    Reply::Integer(applied as i64)
+}
+
+// SORT key [LIMIT offset count] [ASC|DESC] [ALPHA] [STORE destination]
+// over a list, set or sorted set's elements. Elements compare as numbers
+// unless ALPHA is given, with equal numbers ordered by their bytes; BY and
+// GET patterns are not supported.
+pub fn sort_command(dbs: &[ShardedStore], db: usize, command: &[Bytes], now: u64) -> Reply {
+    let store = &dbs[db];
+    let mut alpha = false;
+    let mut desc = false;
+    let mut limit = None;
+    let mut dest = None;
+    let mut i = 2;
+    while i < command.len() {
+        let option = &command[i];
+        if option.eq_ignore_ascii_case(b"alpha") {
+            alpha = true;
+        } else if option.eq_ignore_ascii_case(b"asc") {
+            desc = false;
+        } else if option.eq_ignore_ascii_case(b"desc") {
+            desc = true;
+        } else if option.eq_ignore_ascii_case(b"limit") && i + 2 < command.len() {
+            match (parse_i64(&command[i + 1]), parse_i64(&command[i + 2])) {
+                (Some(offset), Some(count)) => limit = Some((offset, count)),
+                _ => return Reply::error(b"value is not an integer or out of range"),
+            }
+            i += 2;
+        } else if option.eq_ignore_ascii_case(b"store") && i + 1 < command.len() {
+            dest = Some(&command[i + 1]);
+            i += 1;
+        } else {
+            return Reply::error(b"syntax error");
+        }
+        i += 1;
+    }
+
+    let mut budget = CommandBudget::start();
+    let elements = match store.read_value(&command[1], now, |value| match value {
+        Value::String(_) | Value::Int(_) => None,
+        _ => Some(budget.collect(value.elements())),
+    }) {
+        Some(Some(Ok(elements))) => elements,
+        Some(Some(Err(_))) => return Reply::error(TimedOut::MESSAGE),
+        Some(None) => return Reply::wrongtype(),
+        None => Vec::new(),
+    };
+
+    let mut sorted: Vec<Bytes> = if alpha {
+        let mut elements = elements;
+        elements.sort_unstable();
+        elements
+    } else {
+        let mut scored = Vec::with_capacity(elements.len());
+        for element in elements {
+            match std::str::from_utf8(&element).ok().and_then(|s| s.parse::<f64>().ok()) {
+                Some(score) if !score.is_nan() => scored.push((score, element)),
+                _ => return Reply::error(b"One or more scores can't be converted into double"),
+            }
+        }
+        scored.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        scored.into_iter().map(|(_, element)| element).collect()
+    };
+    if desc {
+        sorted.reverse();
+    }
+    if let Some((offset, count)) = limit {
+        let start = (offset.max(0) as usize).min(sorted.len());
+        let end = if count < 0 {
+            sorted.len()
+        } else {
+            start.saturating_add(count as usize).min(sorted.len())
+        };
+        sorted.truncate(end);
+        sorted.drain(..start);
+    }
+
+    let Some(dest) = dest else {
+        return Reply::bulk_array(&sorted);
+    };
+    let len = sorted.len();
+    if !EntryLimits::allows(store.entry_limits.list, 0, len) {
+        return CollectionError::TooManyEntries("list").reply();
+    }
+    let freed = if sorted.is_empty() {
+        store.delete(std::slice::from_ref(dest)).1
+    } else {
+        let size = entry_size(dest.len(), sorted.iter().map(|e| e.len()).sum());
+        if !evict_if_needed(dbs, db, size) {
+            return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+        }
+        if max_memory() > 0 {
+            add_memory_used(size as u64);
+        }
+        store
+            .put(dest.clone(), Value::List(ListValue::from_elements(sorted)))
+            .unwrap_or(0)
+    };
+    if max_memory() > 0 && freed > 0 {
+        MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+    }
+    propagate(db, command);
+    Reply::Integer(len as i64)
+}
+
+// INCR, DECR, INCRBY and DECRBY: `update` is the command's arithmetic and
+// `overflow` the error it reports when that fails
+pub fn incr_command(
+    dbs: &[ShardedStore],
+    db: usize,
+    command: &[Bytes],
+    now: u64,
+    overflow: &[u8],
+    update: impl FnOnce(i64) -> Option<i64>,
+) -> Reply {
+    let key = &command[1];
+    // Room for the longest result, since the current value is not known yet
+    if !evict_if_needed(dbs, db, entry_size(key.len(), I64_MAX_DECIMAL_LEN)) {
+        return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+    }
+    match dbs[db].update_integer(key, now, update) {
+        Ok(n) => {
+            propagate(db, command);
+            Reply::Integer(n)
+        }
+        Err(IntegerError::WrongType) => Reply::wrongtype(),
+        Err(IntegerError::NotInteger) => Reply::error(b"value is not an integer or out of range"),
+        Err(IntegerError::Overflow) => Reply::error(overflow),
+    }
+}
+
+// Execute command - fully inlined and optimized
+#[inline(always)]
+pub fn execute_command(
+    dbs: &[ShardedStore],
+    command: &[Bytes],
+    writer: &mut RespWriter,
+    state: &mut ConnectionState,
+    now: u64,
+) {
+    // A bug that panics in one command costs only that command's reply.
+    // DashMap and parking_lot guards are released as the stack unwinds and
+    // never poison, so this and every other connection keep working.
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let threshold = CONFIG.server.latency_monitor_threshold;
+        if CONFIG.server.command_latency_metrics || threshold > 0 {
+            let start = Instant::now();
+            let reply = execute(dbs, command, state, now);
+            let elapsed = start.elapsed();
+            if let Some(name) = command.first() {
+                if CONFIG.server.command_latency_metrics {
+                    COMMAND_LATENCY[command_metric_index(name)].record(elapsed);
+                }
+                let millis = elapsed.as_millis() as u64;
+                if threshold > 0 && millis >= threshold {
+                    LATENCY_MONITOR.lock().record(latency_event(name), now, millis);
+                }
+            }
+            reply
+        } else {
+            execute(dbs, command, state, now)
+        }
+    }));
+    let reply = result.unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        let name = command.first().map(|name| String::from_utf8_lossy(name)).unwrap_or_default();
+        eprintln!("Command '{}' from {} panicked: {}", name, state.client.addr, message);
+        state.deferred = None;
+        Reply::error(b"internal error")
+    });
+    writer.write_reply(&reply);
+    if CONFIG.server.track_hot_keys && fastrand::u32(..HOT_KEY_SAMPLE_RATE) == 0 {
+        record_hot_keys(command);
+    }
+}
+
+// The default hook prints a backtrace only when RUST_BACKTRACE is set. A
+// panic caught in execute_command is a bug to report, so always capture one.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("{}\n{}", info, std::backtrace::Backtrace::force_capture());
+    }));
+}
+
+// Run one command against the selected database and return its reply.
+// Nothing is written here, so command semantics can be tested on the Reply.
+#[inline(always)]
+pub fn execute(
+    dbs: &[ShardedStore],
+    command: &[Bytes],
+    state: &mut ConnectionState,
+    now: u64,
+) -> Reply {
+    let store = &dbs[state.db];
+
+    // Batch counter updates to reduce atomic operation overhead
+    // Update global counter every 256 operations instead of every operation
+    thread_local! {
+        static LOCAL_CMD_COUNT: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+    }
+    
+    LOCAL_CMD_COUNT.with(|count| {
+        let new_count = count.get() + 1;
+        if new_count >= 256 {
+            TOTAL_COMMANDS.fetch_add(256, Ordering::Relaxed);
+            count.set(0);
+        } else {
+            count.set(new_count);
+        }
+    });
+    
+    if command.is_empty() {
+        return Reply::error(b"empty command");
+    }
+
+    let cmd = &command[0];
+    state.client.record_command(cmd, now);
+
+    // AUTH, PING and LOLWUT don't require authentication
+    let requires_auth = !matches!(cmd.len(), 4 if eq_ignore_case_3(&cmd[..3], b"aut") && (cmd[3] | 0x20) == b'h')
+        && !matches!(cmd.len(), 4 if eq_ignore_case_3(&cmd[..3], b"pin") && (cmd[3] | 0x20) == b'g')
+        && !eq_ignore_case_6(cmd, b"lolwut");
+
+    if requires_auth && !state.authenticated {
+        return Reply::error(b"NOAUTH Authentication required");
+    }
+
+    // The same commands skip the ACL check as well. Commands missing from the
+    // table have no categories and fall through to the unknown command error.
+    let spec = lookup_command(cmd);
+    if requires_auth
+        && !state.acl.allows_all
+        && let Some(spec) = spec
+        && !state.acl.allows(spec)
+    {
+        return Reply::Error(
+            format!("NOPERM User default has no permissions to run the '{}' command", spec.name).into(),
+        );
+    }
+
+    // WRONGTYPE for every typed command, from its table entry, so handlers
+    // can take the type as given. Checked only once the arity is right, so
+    // a short command still gets its arity error.
+    if let Some(spec) = spec
+        && !spec.value_types.is_empty()
+        && spec.arity_ok(command.len())
+        && spec.typed_keys(command).into_iter().any(|key| {
+            store.value_type(key, now).is_some_and(|found| !spec.value_types.contains(&found))
+        })
+    {
+        return Reply::wrongtype();
+    }
+
+    // Optimized command matching
+    match cmd.len() {
+        3 => {
+            if eq_ignore_case_3(cmd, b"set") {
+                return if command.len() >= 3 {
+                    let key = &command[1];
+                    let value = &command[2];
+
+                    // Refuse oversized values before anything is copied or evicted
+                    if !value_size_allowed(value.len(), CONFIG.memory.max_value_size) {
+                        return Reply::error(b"value exceeds maximum allowed size");
+                    }
+
+                    // Check memory limit before setting
+                    let size = entry_size(key.len(), value.len());
+                    if !evict_if_needed(dbs, state.db, size) {
+                        return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                    }
+
+                    // Parse options: EX, PX, EXAT, PXAT, NX, XX, GET
+                    let mut ttl: Option<u64> = None;
+                    let mut nx = false;  // Only set if Not eXists
+                    let mut xx = false;  // Only set if eXists
+                    let mut get = false; // Return old value
+                    
+                    // Options are case-insensitive (eq_ignore_ascii_case, as elsewhere)
+                    let mut i = 3;
+                    while i < command.len() {
+                        let opt = &command[i];
+                        let millis = opt.eq_ignore_ascii_case(b"px") || opt.eq_ignore_ascii_case(b"pxat");
+                        let at = opt.eq_ignore_ascii_case(b"exat") || opt.eq_ignore_ascii_case(b"pxat");
+                        if millis || at || opt.eq_ignore_ascii_case(b"ex") {
+                            // EX seconds | PX milliseconds | EXAT unix-time | PXAT
+                            // unix-time-ms, milliseconds rounded up to whole seconds.
+                            // A time already past stores a key that has expired.
+                            if i + 1 >= command.len() {
+                                return Reply::error(b"syntax error");
+                            }
+                            i += 1;
+                            match parse_u64(&command[i]) {
+                                Some(v) if v > 0 => {
+                                    let seconds = if millis { v.div_ceil(1000) } else { v };
+                                    ttl = Some(if at { seconds.saturating_sub(now) } else { seconds });
+                                }
+                                _ => {
+                                    return Reply::error(b"value is not an integer or out of range");
+                                }
+                            }
+                        } else if opt.eq_ignore_ascii_case(b"nx") {
+                            nx = true;
+                        } else if opt.eq_ignore_ascii_case(b"xx") {
+                            xx = true;
+                        } else if opt.eq_ignore_ascii_case(b"get") {
+                            get = true;
+                        } else {
+                            return Reply::error(b"syntax error");
+                        }
+                        i += 1;
+                    }
+                    
+                    // NX and XX are mutually exclusive
+                    if nx && xx {
+                        return Reply::error(b"XX and NX options at the same time are not compatible");
+                    }
+                    
+                    // Check NX/XX conditions
+                    let shard = &store.shards[store.hash(key)];
+                    let mut key_exists = false;
+                    let mut old_value = None;
+                    if (nx || xx || get)
+                        && let Some(entry) = shard.get(key.as_ref())
+                        && !entry.is_expired(now)
+                    {
+                        key_exists = true;
+                        // GET only works against string values
+                        if get {
+                            match entry.as_string() {
+                                Ok(v) => old_value = Some(v),
+                                Err(_) => {
+                                    return Reply::wrongtype();
+                                }
+                            }
+                        }
+                    }
+                    
+                    // NX: only set if key doesn't exist
+                    if nx && key_exists {
+                        return match old_value {
+                            Some(v) if get => Reply::BulkString(v),
+                            _ => Reply::Null,
+                        };
+                    }
+                    
+                    // XX: only set if key exists
+                    if xx && !key_exists {
+                        return Reply::Null;
+                    }
+
+                    // Atomic set - returns old entry size if key existed
+                    let old_size = store.set(key.clone(), value.clone(), ttl, now);
+
+                    // Track memory usage (only if limits enabled)
+                    if max_memory() > 0 {
+                        if let Some(old) = old_size {
+                            MEMORY_USED.fetch_sub(old as u64, Ordering::Relaxed);
+                        }
+                        add_memory_used(size as u64);
+                    }
+
+                    // A relative TTL is replayed as the absolute time it ended up as
+                    match ttl {
+                        Some(ttl) => propagate(
+                            state.db,
+                            &[
+                                Bytes::from_static(b"SET"),
+                                key.clone(),
+                                value.clone(),
+                                Bytes::from_static(b"PXAT"),
+                                Bytes::from((now + ttl).saturating_mul(1000).to_string()),
+                            ],
+                        ),
+                        None => propagate(state.db, command),
+                    }
+                    if get {
+                        match old_value {
+                            Some(v) => Reply::BulkString(v),
+                            None => Reply::Null,
+                        }
+                    } else {
+                        Reply::ok()
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(cmd, b"ttl") {
+                // TTL key - returns remaining time in seconds
+                return if command.len() >= 2 {
+                    let key = &command[1];
+                    let shard = &store.shards[store.hash(key)];
+                    
+                    match shard.get(key.as_ref()) {
+                        Some(entry) => {
+                            match entry.expiry {
+                                Some(expiry) => {
+                                    if now >= expiry {
+                                        // Key expired
+                                        drop(entry);
+                                        shard.remove(key.as_ref());
+                                        Reply::Integer(-2)
+                                    } else {
+                                        Reply::Integer((expiry - now) as i64)
+                                    }
+                                }
+                                None => {
+                                    // Key exists but has no TTL
+                                    Reply::Integer(-1)
+                                }
+                            }
+                        }
+                        None => {
+                            // Key doesn't exist
+                            Reply::Integer(-2)
+                        }
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(cmd, b"get") {
+                return if command.len() >= 2 {
+                    match store.lookup_string(&command[1], now, !state.no_touch) {
+                        Ok(Some(value)) => Reply::BulkString(value),
+                        Ok(None) => Reply::Null,
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(cmd, b"acl") {
+                // ACL CAT [category] | GETUSER default | WHOAMI (a single default user)
+                let sub = &command[1..];
+                return match sub.first() {
+                    Some(name) if name.eq_ignore_ascii_case(b"cat") && sub.len() == 1 => {
+                        let names: Vec<Bytes> =
+                            ACL_CATEGORIES.iter().map(|c| Bytes::from_static(c.as_bytes())).collect();
+                        Reply::bulk_array(&names)
+                    }
+                    Some(name) if name.eq_ignore_ascii_case(b"cat") && sub.len() == 2 => {
+                        let category = String::from_utf8_lossy(&sub[1]).to_ascii_lowercase();
+                        if !ACL_CATEGORIES.contains(&category.as_str()) {
+                            let mut message = b"Unknown category '".to_vec();
+                            message.extend_from_slice(&sub[1]);
+                            message.push(b'\'');
+                            return Reply::error(&message);
+                        }
+                        let names: Vec<Bytes> = COMMAND_TABLE
+                            .iter()
+                            .filter(|spec| spec.categories.contains(&category.as_str()))
+                            .map(|spec| Bytes::from_static(spec.name.as_bytes()))
+                            .collect();
+                        Reply::bulk_array(&names)
+                    }
+                    Some(name) if name.eq_ignore_ascii_case(b"getuser") && sub.len() == 2 => {
+                        if sub[1].as_ref() != b"default" {
+                            return Reply::Null;
+                        }
+                        Reply::Array(vec![
+                            Reply::bulk(b"flags"),
+                            Reply::Array(vec![Reply::bulk(b"on")]),
+                            Reply::bulk(b"commands"),
+                            Reply::BulkString(state.acl.describe().into()),
+                        ])
+                    }
+                    Some(name) if name.eq_ignore_ascii_case(b"whoami") && sub.len() == 1 => {
+                        Reply::bulk(b"default")
+                    }
+                    _ => Reply::error(b"unknown subcommand or wrong number of arguments for 'acl' command"),
+                };
+            }
+            if eq_ignore_case_3(cmd, b"del") {
+                return if command.len() >= 2 {
+                    let (count, bytes_freed) = store.delete(&command[1..]);
+                    // Track memory freed (only if limits enabled)
+                    if max_memory() > 0 && bytes_freed > 0 {
+                        MEMORY_USED.fetch_sub(bytes_freed as u64, Ordering::Relaxed);
+                    }
+                    if count > 0 {
+                        propagate(state.db, command);
+                    }
+                    Reply::Integer(count as i64)
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+        }
+        4 => {
+            if eq_ignore_case_3(&cmd[..3], b"pin") && (cmd[3] | 0x20) == b'g' {
+                return Reply::SimpleString(Bytes::from_static(b"PONG"));
+            }
+            if eq_ignore_case_3(&cmd[..3], b"sor") && (cmd[3] | 0x20) == b't' {
+                return if command.len() >= 2 {
+                    sort_command(dbs, state.db, command, now)
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"key") && (cmd[3] | 0x20) == b's' {
+                // Large results are streamed by the connection loop instead of
+                // being collected into one Vec and one giant reply buffer
+                // Once counted, a streamed reply yields between shards, so
+                // only the count is held to command_timeout
+                let mut budget = CommandBudget::start();
+                let Ok(count) = store.count_keys(now, &mut budget) else {
+                    return Reply::error(TimedOut::MESSAGE);
+                };
+                if count > CONFIG.server.keys_stream_threshold {
+                    state.deferred = Some(DeferredReply::Keys { count, now });
+                    return Reply::Deferred;
+                }
+                return match store.keys(now, &mut budget) {
+                    Ok(keys) => Reply::bulk_array(&keys),
+                    Err(_) => Reply::error(TimedOut::MESSAGE),
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"inc") && (cmd[3] | 0x20) == b'r' {
+                // INCR key
+                return if command.len() >= 2 {
+                    incr_command(dbs, state.db, command, now, b"increment would produce overflow", |n| {
+                        n.checked_add(1)
+                    })
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"dec") && (cmd[3] | 0x20) == b'r' {
+                // DECR key
+                return if command.len() >= 2 {
+                    incr_command(dbs, state.db, command, now, b"decrement would produce overflow", |n| {
+                        n.checked_sub(1)
+                    })
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"ptt") && (cmd[3] | 0x20) == b'l' {
+                // PTTL key - returns remaining time in milliseconds
+                return if command.len() >= 2 {
+                    let key = &command[1];
+                    let shard = &store.shards[store.hash(key)];
+                    
+                    match shard.get(key.as_ref()) {
+                        Some(entry) => {
+                            match entry.expiry {
+                                Some(expiry) => {
+                                    if now >= expiry {
+                                        drop(entry);
+                                        shard.remove(key.as_ref());
+                                        Reply::Integer(-2)
+                                    } else {
+                                        // Convert seconds to milliseconds
+                                        Reply::Integer(((expiry - now) * 1000) as i64)
+                                    }
+                                }
+                                None => {
+                                    Reply::Integer(-1)
+                                }
+                            }
+                        }
+                        None => {
+                            Reply::Integer(-2)
+                        }
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"mge") && (cmd[3] | 0x20) == b't' {
+                // MGET key [key ...]
+                return if command.len() >= 2 {
+                    let values = store.get_many(&command[1..], now, !state.no_touch);
+                    Reply::Array(values.into_iter().map(|value| value.map_or(Reply::Null, Reply::BulkString)).collect())
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"mse") && (cmd[3] | 0x20) == b't' {
+                // MSET key value [key value ...]
+                return if command.len() >= 3 && (command.len() - 1).is_multiple_of(2) {
+                    let pairs = (command.len() - 1) / 2;
+
+                    // All or nothing: one oversized value rejects the whole MSET
+                    let max_value_size = CONFIG.memory.max_value_size;
+                    if command[2..].iter().step_by(2).any(|v| !value_size_allowed(v.len(), max_value_size)) {
+                        return Reply::error(b"value exceeds maximum allowed size");
+                    }
+                    
+                    // Check memory for all pairs first
+                    let mut total_size = 0;
+                    for i in 0..pairs {
+                        let key = &command[1 + i * 2];
+                        let value = &command[2 + i * 2];
+                        total_size += entry_size(key.len(), value.len());
+                    }
+                    
+                    if !evict_if_needed(dbs, state.db, total_size) {
+                        return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                    }
+                    
+                    // Set all pairs, one shard at a time (see ShardedStore::set_many)
+                    let replaced = store.set_many(
+                        command[1..]
+                            .chunks_exact(2)
+                            .map(|pair| (pair[0].clone(), pair[1].clone()))
+                            .collect(),
+                    );
+                    if max_memory() > 0 {
+                        MEMORY_USED.fetch_sub(replaced as u64, Ordering::Relaxed);
+                        add_memory_used(total_size as u64);
+                    }
+                    
+                    propagate(state.db, command);
+                    Reply::ok()
+                } else {
+                    Reply::error(b"wrong number of arguments for MSET")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"aut") && (cmd[3] | 0x20) == b'h' {
+                // AUTH command
+                let password = PASSWORD.read();
+                return if !password.is_empty() {
+                    if command.len() >= 2 {
+                        // Use constant-time comparison to prevent timing attacks
+                        let provided = command[1].as_ref();
+                        let expected = password.as_bytes();
+                        let is_valid = provided.ct_eq(expected).into();
+                        if is_valid {
+                            state.authenticated = true;
+                            Reply::ok()
+                        } else {
+                            if CONFIG.security.auth_failure_delay_ms > 0 {
+                                state.deferred = Some(DeferredReply::AuthFailure);
+                            }
+                            Reply::error(b"ERR invalid password")
+                        }
+                    } else {
+                        Reply::error(b"ERR wrong number of arguments for 'auth' command")
+                    }
+                } else {
+                    Reply::error(b"ERR Client sent AUTH, but no password is set")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"inf") && (cmd[3] | 0x20) == b'o' {
+                // INFO command - return server stats
+                let uptime = server_uptime();
+                let total_commands = TOTAL_COMMANDS.load(Ordering::Relaxed);
+                let total_connections = TOTAL_CONNECTIONS.load(Ordering::Relaxed);
+                let active_connections = ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
+                let keyspace: String = dbs
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, db)| *index == 0 || !db.is_empty())
+                    .map(|(index, db)| {
+                        format!("db{}:keys={},expires=0,avg_ttl=0\r\n", index, db.len())
+                    })
+                    .collect();
+                let memory_used = MEMORY_USED.load(Ordering::Relaxed);
+                let memory_peak = MEMORY_PEAK.load(Ordering::Relaxed);
+                let evicted_keys = EVICTED_KEYS.load(Ordering::Relaxed);
+                let max_memory = max_memory();
+                let eviction_policy = eviction_policy();
+                let rejected_connections = REJECTED_CONNECTIONS.load(Ordering::Relaxed);
+                let worker_threads = tokio::runtime::Handle::try_current()
+                    .map(|handle| handle.metrics().num_workers())
+                    .unwrap_or(0);
+
+                let info = format!(
+                    "# Server\r\n\
+                    redis_version:7.0.0\r\n\
+                    redistill_version:{}\r\n\
+                    redistill_git_sha1:{}\r\n\
+                    redis_mode:standalone\r\n\
+                    os:{} {}\r\n\
+                    arch_bits:{}\r\n\
+                    process_id:{}\r\n\
+                    tcp_port:{}\r\n\
+                    uptime_in_seconds:{}\r\n\
+                    num_shards:{}\r\n\
+                    io_threads_active:{}\r\n\
+                    \r\n\
+                    # Clients\r\n\
+                    connected_clients:{}\r\n\
+                    mem_clients_normal:{}\r\n\
+                    maxmemory_clients:{}\r\n\
+                    \r\n\
+                    # Memory\r\n\
+                    used_memory:{}\r\n\
+                    used_memory_human:{}\r\n\
+                    used_memory_peak:{}\r\n\
+                    used_memory_peak_human:{}\r\n\
+                    maxmemory:{}\r\n\
+                    maxmemory_human:{}\r\n\
+                    maxmemory_policy:{}\r\n\
+                    mem_allocator:{}\r\n\
+                    evicted_keys:{}\r\n\
+                    \r\n\
+                    # Persistence\r\n\
+                    loading:{}\r\n\
+                    rdb_changes_since_last_save:{}\r\n\
+                    rdb_bgsave_in_progress:{}\r\n\
+                    rdb_last_save_time:{}\r\n\
+                    rdb_last_bgsave_status:{}\r\n\
+                    \r\n\
+                    # Stats\r\n\
+                    total_connections_received:{}\r\n\
+                    total_commands_processed:{}\r\n\
+                    rejected_connections:{}\r\n\
+                    expired_keys:{}\r\n\
+                    evicted_clients:{}\r\n\
+                    keyspace_hits:{}\r\n\
+                    keyspace_misses:{}\r\n\
+                    \r\n\
+                    # Replication\r\n\
+                    role:master\r\n\
+                    connected_slaves:0\r\n\
+                    master_replid:{}\r\n\
+                    master_repl_offset:{}\r\n\
+                    \r\n\
+                    # Keyspace\r\n\
+                    {}",
+                    env!("CARGO_PKG_VERSION"),
+                    env!("REDISTILL_GIT_SHA"),
+                    std::env::consts::OS,
+                    std::env::consts::ARCH,
+                    usize::BITS,
+                    std::process::id(),
+                    server_port(),
+                    uptime,
+                    store.num_shards,
+                    worker_threads,
+                    active_connections,
+                    CLIENT_OUTPUT_BYTES.load(Ordering::Relaxed),
+                    CONFIG.memory.max_memory_clients,
+                    memory_used,
+                    format_bytes(memory_used),
+                    memory_peak,
+                    format_bytes(memory_peak),
+                    max_memory,
+                    if max_memory > 0 {
+                        format_bytes(max_memory)
+                    } else {
+                        "unlimited".to_string()
+                    },
+                    eviction_policy.as_str(),
+                    MEM_ALLOCATOR,
+                    evicted_keys,
+                    LOADING.load(Ordering::Relaxed) as u8,
+                    DIRTY.load(Ordering::Relaxed),
+                    BGSAVE_IN_PROGRESS.load(Ordering::Relaxed) as u8,
+                    LAST_SAVE.load(Ordering::Relaxed),
+                    if LAST_SAVE_OK.load(Ordering::Relaxed) { "ok" } else { "err" },
+                    total_connections,
+                    total_commands,
+                    rejected_connections,
+                    EXPIRED_KEYS.load(Ordering::Relaxed),
+                    EVICTED_CLIENTS.load(Ordering::Relaxed),
+                    KEYSPACE_HITS.load(Ordering::Relaxed),
+                    KEYSPACE_MISSES.load(Ordering::Relaxed),
+                    REPL_ID.lock(),
+                    REPL_OFFSET.load(Ordering::Relaxed),
+                    keyspace
+                );
+                return Reply::BulkString(info.into());
+            }
+            if eq_ignore_case_3(&cmd[..3], b"sca") && (cmd[3] | 0x20) == b'n' {
+                // SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+                if command.len() < 2 {
+                    return Reply::error(b"wrong number of arguments for 'scan' command");
+                }
+                let Some(cursor) = parse_u64(&command[1]) else {
+                    return Reply::error(b"invalid cursor");
+                };
+                let mut pattern = None;
+                let mut count = 10;
+                let mut type_name = None;
+                let mut i = 2;
+                while i < command.len() {
+                    let opt = &command[i];
+                    let Some(arg) = command.get(i + 1) else {
+                        return Reply::error(b"syntax error");
+                    };
+                    if opt.eq_ignore_ascii_case(b"match") {
+                        pattern = Some(arg.as_ref());
+                    } else if opt.eq_ignore_ascii_case(b"count") {
+                        match parse_u64(arg) {
+                            Some(n) if n > 0 => count = n as usize,
+                            _ => {
+                                return Reply::error(b"syntax error");
+                            }
+                        }
+                    } else if opt.eq_ignore_ascii_case(b"type") {
+                        type_name = Some(arg.as_ref());
+                    } else {
+                        return Reply::error(b"syntax error");
+                    }
+                    i += 2;
+                }
+
+                let mut budget = CommandBudget::start();
+                let Ok((next, keys)) =
+                    store.scan(cursor as usize, count, pattern, type_name, now, &mut budget)
+                else {
+                    return Reply::error(TimedOut::MESSAGE);
+                };
+                return Reply::Array(vec![
+                    Reply::BulkString(next.to_string().into()),
+                    Reply::bulk_array(&keys),
+                ]);
+            }
+            if eq_ignore_case_3(&cmd[..3], b"typ") && (cmd[3] | 0x20) == b'e' {
+                // TYPE key - works on any value type
+                return if command.len() >= 2 {
+                    let name = store.value_type(&command[1], now).unwrap_or("none");
+                    Reply::SimpleString(Bytes::from_static(name.as_bytes()))
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"sav") && (cmd[3] | 0x20) == b'e' {
+                // SAVE - synchronous snapshot
+                let path = &CONFIG.persistence.snapshot_path;
+                return if path.is_empty() {
+                    Reply::error(b"Save disabled: no snapshot path configured")
+                } else if BGSAVE_IN_PROGRESS.load(Ordering::Acquire) {
+                    Reply::error(b"Background save already in progress")
+                } else {
+                    match save_snapshot(dbs, path) {
+                        Ok(_) => Reply::ok(),
+                        Err(e) => Reply::error(format!("Snapshot failed: {}", e).as_bytes()),
+                    }
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"lle") && (cmd[3] | 0x20) == b'n' {
+                // LLEN key
+                return if command.len() >= 2 {
+                    let key = &command[1];
+                    let shard = &store.shards[store.hash(key)];
+                    match shard.get(key.as_ref()) {
+                        Some(entry) if !entry.is_expired(now) => match &entry.value {
+                            Value::List(list) => Reply::Integer(list.len() as i64),
+                            _ => Reply::wrongtype(),
+                        },
+                        _ => Reply::Integer(0),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if (eq_ignore_case_3(&cmd[..3], b"lpo") || eq_ignore_case_3(&cmd[..3], b"rpo"))
+                && (cmd[3] | 0x20) == b'p'
+            {
+                // LPOP/RPOP key [count]
+                return if command.len() == 2 || command.len() == 3 {
+                    let count = match command.get(2) {
+                        Some(arg) => match parse_i64(arg) {
+                            Some(n) if n >= 0 => Some(n as usize),
+                            _ => {
+                                return Reply::error(b"value is out of range, must be positive");
+                            }
+                        },
+                        None => None,
+                    };
+                    let front = (cmd[0] | 0x20) == b'l';
+                    let popped = store.pop(&command[1], count.unwrap_or(1), front, now);
+                    if popped.as_ref().is_ok_and(|items| !items.is_empty()) {
+                        propagate(state.db, command);
+                    }
+                    match popped {
+                        // Without a count the reply is a single element
+                        Ok(items) if count.is_none() => match items.first() {
+                            Some(item) => Reply::BulkString(item.clone()),
+                            None => Reply::Null,
+                        },
+                        Ok(items) if items.is_empty() => Reply::NullArray,
+                        Ok(items) => Reply::bulk_array(&items),
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"sad") && (cmd[3] | 0x20) == b'd' {
+                // SADD key member [member ...]
+                return if command.len() >= 3 {
+                    let key = &command[1];
+                    let members = &command[2..];
+
+                    let size = entry_size(key.len(), members.iter().map(|m| m.len()).sum());
+                    if !evict_if_needed(dbs, state.db, size) {
+                        return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                    }
+
+                    match store.sadd(key, members, now) {
+                        Ok(added) => {
+                            propagate(state.db, command);
+                            Reply::Integer(added as i64)
+                        }
+                        Err(e) => e.reply(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"sre") && (cmd[3] | 0x20) == b'm' {
+                // SREM key member [member ...]
+                return if command.len() >= 3 {
+                    match store.srem(&command[1], &command[2..], now) {
+                        Ok(removed) => {
+                            if removed > 0 {
+                                propagate(state.db, command);
+                            }
+                            Reply::Integer(removed as i64)
+                        }
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_3(&cmd[..3], b"zad") && (cmd[3] | 0x20) == b'd' {
+                // ZADD key score member [score member ...]
+                if command.len() < 4 {
+                    return Reply::error(b"wrong number of arguments");
+                }
+                if !command.len().is_multiple_of(2) {
+                    return Reply::error(b"syntax error");
+                }
+                let mut members = Vec::with_capacity((command.len() - 2) / 2);
+                for pair in command[2..].chunks_exact(2) {
+                    match parse_score(&pair[0]) {
+                        Some(score) => members.push((score, pair[1].clone())),
+                        None => {
+                            return Reply::error(b"value is not a valid float");
+                        }
+                    }
+                }
+
+                let key = &command[1];
+                let added = members.iter().map(|(_, m)| SortedSet::member_size(m)).sum();
+                if !evict_if_needed(dbs, state.db, entry_size(key.len(), added)) {
+                    return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                }
+                return match store.zadd(key, &members, now) {
+                    Ok(new_members) => {
+                        propagate(state.db, command);
+                        Reply::Integer(new_members as i64)
+                    }
+                    Err(e) => e.reply(),
+                };
+            }
+        }
+        5 => {
+            let lower = [
+                cmd[0] | 0x20,
+                cmd[1] | 0x20,
+                cmd[2] | 0x20,
+                cmd[3] | 0x20,
+                cmd[4] | 0x20,
+            ];
+            if &lower == b"getex" {
+                if command.len() < 2 {
+                    return Reply::error(b"wrong number of arguments for 'getex' command");
+                }
+                return getex_command(dbs, state.db, command, now);
+            }
+            if &lower == b"lpush" || &lower == b"rpush" {
+                // LPUSH/RPUSH key element [element ...]
+                return if command.len() >= 3 {
+                    let key = &command[1];
+                    let values = &command[2..];
+
+                    let size = entry_size(key.len(), values.iter().map(|v| v.len()).sum());
+                    if !evict_if_needed(dbs, state.db, size) {
+                        return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                    }
+
+                    match store.push(key, values, lower[0] == b'l', now) {
+                        Ok(len) => {
+                            propagate(state.db, command);
+                            Reply::Integer(len as i64)
+                        }
+                        Err(e) => e.reply(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if &lower == b"lmpop" || &lower == b"zmpop" {
+                // LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+                // ZMPOP numkeys key [key ...] MIN|MAX [COUNT count]
+                let list = lower[0] == b'l';
+                let ends: [&[u8]; 2] = if list { [b"left", b"right"] } else { [b"min", b"max"] };
+                let (keys, first_end, count) = match parse_mpop_args(&command[1..], ends) {
+                    Ok(parsed) => parsed,
+                    Err(msg) => {
+                        return Reply::error(msg);
+                    }
+                };
+
+                // Pop from the first key that has anything to give
+                for key in keys {
+                    return if list {
+                        match store.pop(key, count, first_end, now) {
+                            Ok(items) if items.is_empty() => continue,
+                            Ok(items) => {
+                                propagate(state.db, command);
+                                Reply::Array(vec![Reply::BulkString(key.clone()), Reply::bulk_array(&items)])
+                            }
+                            Err(_) => Reply::wrongtype(),
+                        }
+                    } else {
+                        match store.zpop(key, count, !first_end, now) {
+                            Ok(members) if members.is_empty() => continue,
+                            Ok(members) => {
+                                propagate(state.db, command);
+                                Reply::Array(vec![Reply::BulkString(key.clone()), scored_pairs_reply(&members)])
+                            }
+                            Err(_) => Reply::wrongtype(),
+                        }
+                    };
+                }
+                return Reply::NullArray;
+            }
+            if &lower == b"debug" {
+                // DEBUG CHANGE-REPL-ID | SET-ACTIVE-EXPIRE 0|1 | OBJECT key
+                //     | STRINGMATCH-LEN pattern string | SHARD-OF key
+                //     | POPULATE count [prefix] [size]
+                //     | SLEEP seconds | SLEEP-BEFORE-REPLY seconds | SET-SAVE-DELAY micros
+                //     | HOTKEYS [count] | RELOAD | PANIC [key] | JMAP
+                if !CONFIG.security.enable_debug_command {
+                    return Reply::error(
+                        b"DEBUG command not allowed. Set security.enable_debug_command in the configuration file and restart the server",
+                    );
+                }
+                let Some(sub) = command.get(1) else {
+                    return Reply::error(b"wrong number of arguments for 'debug' command");
+                };
+                let sub = sub.to_ascii_lowercase();
+                return match sub.as_slice() {
+                    b"change-repl-id" if command.len() == 2 => {
+                        *REPL_ID.lock() = new_repl_id();
+                        Reply::ok()
+                    }
+                    b"set-active-expire" if command.len() == 3 => {
+                        match command[2].as_ref() {
+                            b"0" => ACTIVE_EXPIRE.store(false, Ordering::Relaxed),
+                            b"1" => ACTIVE_EXPIRE.store(true, Ordering::Relaxed),
+                            _ => {
+                                return Reply::error(b"value is not an integer or out of range");
+                            }
+                        }
+                        Reply::ok()
+                    }
+                    b"object" if command.len() == 3 => {
+                        // Peek without the lazy-expiry removal a normal read does
+                        let key = &command[2];
+                        match store.shards[store.hash(key)].get(key.as_ref()) {
+                            Some(entry) => Reply::SimpleString(entry.debug_object(now, get_uptime_seconds()).into()),
+                            None => Reply::error(b"no such key"),
+                        }
+                    }
+                    b"stringmatch-len" if command.len() == 4 => {
+                        // Exposes glob_match so Redis' own pattern vectors can run over the wire
+                        Reply::Integer(glob_match(&command[2], &command[3]) as i64)
+                    }
+                    b"populate" if (3..=5).contains(&command.len()) => {
+                        // Keys prefix:0..count holding "value:N", zero-padded or cut to
+                        // `size` if given. Existing keys are left alone, as in Redis.
+                        let count = parse_u64(&command[2]);
+                        let size = command.get(4).map(|size| parse_u64(size));
+                        let (Some(count), None | Some(Some(_))) = (count, size) else {
+                            return Reply::error(b"value is not an integer or out of range");
+                        };
+                        if let Some(Some(size)) = size
+                            && (size > MAX_STRING_LEN as u64 || !value_size_allowed(size as usize, CONFIG.memory.max_value_size))
+                        {
+                            return Reply::error(b"value exceeds maximum allowed size");
+                        }
+                        let prefix = command.get(3).map_or(&b"key"[..], |prefix| prefix.as_ref());
+                        for i in 0..count {
+                            let mut key = prefix.to_vec();
+                            key.extend_from_slice(format!(":{}", i).as_bytes());
+                            if store.shards[store.hash(&key)].contains_key(key.as_slice()) {
+                                continue;
+                            }
+                            let mut value = format!("value:{}", i).into_bytes();
+                            if let Some(Some(size)) = size {
+                                value.resize(size as usize, 0);
+                            }
+                            let size = entry_size(key.len(), value.len());
+                            if !evict_if_needed(dbs, state.db, size) {
+                                return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                            }
+                            store.set(key.into(), value.into(), None, now);
+                            if max_memory() > 0 {
+                                add_memory_used(size as u64);
+                            }
+                        }
+                        Reply::ok()
+                    }
+                    b"shard-of" if command.len() == 3 => {
+                        Reply::Integer(store.hash(&command[2]) as i64)
+                    }
+                    b"sleep" | b"sleep-before-reply" if command.len() == 3 => {
+                        // The connection waits before answering; other clients are
+                        // not held up, unlike DEBUG SLEEP in Redis
+                        let delay = std::str::from_utf8(&command[2])
+                            .ok()
+                            .and_then(|s| s.parse::<f64>().ok())
+                            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+                        let Some(delay) = delay else {
+                            return Reply::error(b"value is not a valid float");
+                        };
+                        state.deferred = Some(DeferredReply::Sleep(delay));
+                        Reply::ok()
+                    }
+                    b"hotkeys" if command.len() <= 3 => {
+                        // Key, estimated accesses, key, ... hottest first. The
+                        // sampled counts are scaled back up by the sample rate.
+                        if !CONFIG.server.track_hot_keys {
+                            return Reply::error(b"hot key tracking is off; set server.track_hot_keys");
+                        }
+                        let count = match command.get(2).map(|count| parse_u64(count)) {
+                            None => 10,
+                            Some(Some(count)) => count as usize,
+                            Some(None) => return Reply::error(b"value is not an integer or out of range"),
+                        };
+                        let top = HOT_KEYS.lock().top(count);
+                        let mut pairs = Vec::with_capacity(top.len() * 2);
+                        for (key, hits) in top {
+                            pairs.push(Reply::BulkString(key));
+                            pairs.push(Reply::Integer((hits * HOT_KEY_SAMPLE_RATE as u64) as i64));
+                        }
+                        Reply::Array(pairs)
+                    }
+                    b"set-save-delay" if command.len() == 3 => {
+                        // Slows SAVE/BGSAVE by this many microseconds per key, so a
+                        // test can write and read while a save is still running
+                        let Some(micros) = parse_u64(&command[2]) else {
+                            return Reply::error(b"value is not an integer or out of range");
+                        };
+                        SAVE_KEY_DELAY_US.store(micros, Ordering::Relaxed);
+                        Reply::ok()
+                    }
+                    b"reload" if command.len() == 2 => {
+                        let path = &CONFIG.persistence.snapshot_path;
+                        if path.is_empty() {
+                            return Reply::error(b"Reload disabled: no snapshot path configured");
+                        }
+                        // Holding the BGSAVE flag keeps autosave off the file meanwhile
+                        if BGSAVE_IN_PROGRESS.swap(true, Ordering::AcqRel) {
+                            return Reply::error(b"Background save already in progress");
+                        }
+                        let reloaded = reload_snapshot(dbs, path);
+                        BGSAVE_IN_PROGRESS.store(false, Ordering::Release);
+                        match reloaded {
+                            Ok(_) => Reply::ok(),
+                            Err(e) => Reply::error(format!("Reload failed: {}", e).as_bytes()),
+                        }
+                    }
+                    b"panic" if command.len() <= 3 => {
+                        // Exercises the recovery in execute_command. With a key, the
+                        // panic happens while that key's shard is write-locked.
+                        let _guard = command.get(2).map(|key| store.shards[store.hash(key)].entry(key.clone()));
+                        panic!("DEBUG PANIC");
+                    }
+                    // Redis-only tooling hooks with nothing to do here
+                    b"jmap" | b"quicklist-packed-threshold" | b"set-skip-checksum-validation" => Reply::ok(),
+                    b"change-repl-id" | b"set-active-expire" | b"object" | b"stringmatch-len"
+                    | b"populate" | b"shard-of" | b"sleep" | b"sleep-before-reply" | b"hotkeys"
+                    | b"set-save-delay" | b"reload" | b"panic" => {
+                        Reply::error(b"wrong number of arguments for 'debug' command")
+                    }
+                    _ => Reply::error(
+                        format!("DEBUG subcommand not supported: '{}'", String::from_utf8_lossy(&command[1]))
+                            .as_bytes(),
+                    ),
+                };
+            }
+            if &lower == b"scard" {
+                // SCARD key
+                return if command.len() >= 2 {
+                    match store.read_value(&command[1], now, |value| value.member_count(false)) {
+                        Some(Some(len)) => Reply::Integer(len as i64),
+                        Some(None) => Reply::wrongtype(),
+                        None => Reply::Integer(0),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if &lower == b"zcard" {
+                // ZCARD key
+                return if command.len() >= 2 {
+                    match store.with_zset(&command[1], now, |zset| zset.len()) {
+                        Ok(len) => Reply::Integer(len.unwrap_or(0) as i64),
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+        }
+        6 => {
+            if eq_ignore_case_6(cmd, b"lolwut") {
+                // LOLWUT [VERSION n]: the banner and server version (n is accepted, not used)
+                match &command[1..] {
+                    [] => {}
+                    [option, n] if option.eq_ignore_ascii_case(b"version") => {
+                        if parse_i64(n).is_none() {
+                            return Reply::error(b"value is not an integer or out of range");
+                        }
+                    }
+                    _ => return Reply::error(b"syntax error"),
+                }
+                let text = format!("{}\nRedistill ver. {}\n", BANNER, env!("CARGO_PKG_VERSION"));
+                return Reply::BulkString(text.into());
+            }
+            if eq_ignore_case_6(cmd, b"zscore") {
+                // ZSCORE key member
+                return if command.len() >= 3 {
+                    match store.with_zset(&command[1], now, |zset| zset.score(&command[2])) {
+                        Ok(Some(Some(score))) => Reply::BulkString(score.to_string().into()),
+                        Ok(_) => Reply::Null,
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"zrange") {
+                // ZRANGE key start stop [WITHSCORES]
+                return if command.len() == 4 || command.len() == 5 {
+                    let with_scores = match command.get(4) {
+                        Some(option) if option.eq_ignore_ascii_case(b"withscores") => true,
+                        Some(_) => {
+                            return Reply::error(b"syntax error");
+                        }
+                        None => false,
+                    };
+                    let (start, stop) = match (parse_i64(&command[2]), parse_i64(&command[3])) {
+                        (Some(start), Some(stop)) => (start, stop),
+                        _ => {
+                            return Reply::error(b"value is not an integer or out of range");
+                        }
+                    };
+                    match store.with_zset(&command[1], now, |zset| zset.range(start, stop)) {
+                        Ok(members) => {
+                            let members = members.unwrap_or_default();
+                            // WITHSCORES interleaves member and score in a flat array
+                            let per_member = if with_scores { 2 } else { 1 };
+                            let mut items = Vec::with_capacity(members.len() * per_member);
+                            for (member, score) in members {
+                                items.push(Reply::BulkString(member));
+                                if with_scores {
+                                    items.push(Reply::BulkString(score.to_string().into()));
+                                }
+                            }
+                            Reply::Array(items)
+                        }
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"lrange") {
+                // LRANGE key start stop
+                return if command.len() >= 4 {
+                    let (start, stop) = match (parse_i64(&command[2]), parse_i64(&command[3])) {
+                        (Some(start), Some(stop)) => (start, stop),
+                        _ => {
+                            return Reply::error(b"value is not an integer or out of range");
+                        }
+                    };
+                    match store.range(&command[1], start, stop, now) {
+                        Ok(items) => Reply::bulk_array(&items),
+                        Err(_) => Reply::wrongtype(),
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"exists") {
+                return if command.len() >= 2 {
+                    let count = store.exists(&command[1..], now);
+                    Reply::Integer(count as i64)
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"object") {
+                // OBJECT REFCOUNT key | ENCODING key
+                return if command.len() >= 3 && command[1].eq_ignore_ascii_case(b"encoding") {
+                    let key = &command[2];
+                    match store.shards[store.hash(key)].get(key.as_ref()) {
+                        Some(entry) if !entry.is_expired(now) => Reply::bulk(entry.value.encoding().as_bytes()),
+                        _ => Reply::Null,
+                    }
+                } else if command.len() >= 3 && command[1].eq_ignore_ascii_case(b"refcount") {
+                    let key = &command[2];
+                    let shard = &store.shards[store.hash(key)];
+                    match shard.get(key.as_ref()) {
+                        Some(entry) if !entry.is_expired(now) => match &entry.value {
+                            Value::String(v) if is_shared_integer(v) => {
+                                Reply::Integer(SHARED_REFCOUNT as i64)
+                            }
+                            Value::Int(n) if (0..SHARED_INTEGERS as i64).contains(n) => {
+                                Reply::Integer(SHARED_REFCOUNT as i64)
+                            }
+                            _ => Reply::Integer(1),
+                        },
+                        _ => Reply::Null,
+                    }
+                } else {
+                    Reply::error(b"unknown subcommand or wrong number of arguments for 'object' command")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"client") {
+                // CLIENT ID | INFO | LIST | NO-EVICT ON|OFF | NO-TOUCH ON|OFF
+                if command.len() == 3
+                    && (command[1].eq_ignore_ascii_case(b"no-evict")
+                        || command[1].eq_ignore_ascii_case(b"no-touch"))
+                {
+                    let on = if command[2].eq_ignore_ascii_case(b"on") {
+                        true
+                    } else if command[2].eq_ignore_ascii_case(b"off") {
+                        false
+                    } else {
+                        return Reply::error(b"syntax error");
+                    };
+                    if command[1].eq_ignore_ascii_case(b"no-touch") {
+                        state.no_touch = on;
+                    } else {
+                        state.client.no_evict.store(on, Ordering::Relaxed);
+                    }
+                    return Reply::ok();
+                }
+                let sub = command.get(1).filter(|_| command.len() == 2);
+                return match sub {
+                    Some(sub) if sub.eq_ignore_ascii_case(b"id") => {
+                        Reply::Integer(state.client.id as i64)
+                    }
+                    Some(sub) if sub.eq_ignore_ascii_case(b"info") => {
+                        let line = state.client.info_line(now) + "\n";
+                        Reply::BulkString(line.into())
+                    }
+                    Some(sub) if sub.eq_ignore_ascii_case(b"list") => {
+                        let mut clients: Vec<Arc<ClientInfo>> =
+                            CLIENTS.iter().map(|c| c.value().clone()).collect();
+                        clients.sort_by_key(|c| c.id);
+                        let mut list = String::new();
+                        for client in clients {
+                            list.push_str(&client.info_line(now));
+                            list.push('\n');
+                        }
+                        Reply::BulkString(list.into())
+                    }
+                    _ => Reply::error(b"unknown subcommand or wrong number of arguments for 'client' command"),
+                };
+            }
+            if eq_ignore_case_6(cmd, b"memory") {
+                // MEMORY DOCTOR | STATS
+                if command.len() == 2 && command[1].eq_ignore_ascii_case(b"stats") {
+                    let keys: usize = dbs.iter().map(ShardedStore::len).sum();
+                    return Reply::Array(vec![
+                        Reply::bulk(b"peak.allocated"),
+                        Reply::Integer(MEMORY_PEAK.load(Ordering::Relaxed) as i64),
+                        Reply::bulk(b"total.allocated"),
+                        Reply::Integer(MEMORY_USED.load(Ordering::Relaxed) as i64),
+                        Reply::bulk(b"dataset.bytes"),
+                        Reply::Integer(MEMORY_USED.load(Ordering::Relaxed) as i64),
+                        Reply::bulk(b"keys.count"),
+                        Reply::Integer(keys as i64),
+                        Reply::bulk(b"maxmemory.policy"),
+                        Reply::bulk(eviction_policy().as_str().as_bytes()),
+                    ]);
+                }
+                return if command.len() == 2 && command[1].eq_ignore_ascii_case(b"doctor") {
+                    let report = memory_doctor_report(
+                        MEMORY_USED.load(Ordering::Relaxed),
+                        max_memory(),
+                        eviction_policy().as_str(),
+                        store.len(),
+                        &sample_keys(store, 1000),
+                    );
+                    Reply::BulkString(report.into())
+                } else {
+                    Reply::error(b"unknown subcommand or wrong number of arguments for 'memory' command")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"bitpos") {
+                // BITPOS key bit [start [end]]
+                if !(3..=5).contains(&command.len()) {
+                    return Reply::error(b"wrong number of arguments for 'bitpos' command");
+                }
+                let bit = match command[2].as_ref() {
+                    b"0" => false,
+                    b"1" => true,
+                    _ => {
+                        return Reply::error(b"The bit argument must be 1 or 0.");
+                    }
+                };
+                let Some(range) = command[3..].iter().map(|arg| parse_i64(arg)).collect::<Option<Vec<_>>>()
+                else {
+                    return Reply::error(b"value is not an integer or out of range");
+                };
+                let start = range.first().copied().unwrap_or(0);
+                let end = range.get(1).copied();
+                return match store.lookup_string(&command[1], now, !state.no_touch) {
+                    Ok(value) => {
+                        let value = value.unwrap_or_default();
+                        Reply::Integer(bitpos(&value, bit, start, end))
+                    }
+                    Err(_) => Reply::wrongtype(),
+                };
+            }
+            if eq_ignore_case_6(cmd, b"select") {
+                // SELECT index - switch this connection's database
+                if command.len() != 2 {
+                    return Reply::error(b"wrong number of arguments for 'select' command");
+                }
+                return match parse_i64(&command[1]) {
+                    Some(index) if index >= 0 && (index as usize) < dbs.len() => {
+                        state.db = index as usize;
+                        Reply::ok()
+                    }
+                    Some(_) => Reply::error(b"DB index is out of range"),
+                    None => Reply::error(b"value is not an integer or out of range"),
+                };
+            }
+            if eq_ignore_case_6(cmd, b"bgsave") {
+                // BGSAVE - snapshot on a blocking thread while commands keep running
+                return if command.len() > 1 {
+                    Reply::error(b"wrong number of arguments for 'bgsave' command")
+                } else if CONFIG.persistence.snapshot_path.is_empty() {
+                    Reply::error(b"Background save disabled: no snapshot path configured")
+                } else if !start_bgsave(dbs) {
+                    Reply::error(b"Background save already in progress")
+                } else {
+                    Reply::SimpleString(Bytes::from_static(b"Background saving started"))
+                };
+            }
+            if eq_ignore_case_6(cmd, b"getdel") {
+                // GETDEL key - GET, then delete the key
+                if command.len() != 2 {
+                    return Reply::error(b"wrong number of arguments for 'getdel' command");
+                }
+                return match store.get_del(&command[1], now) {
+                    Ok((value, freed)) => {
+                        if max_memory() > 0 && freed > 0 {
+                            MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+                        }
+                        if value.is_some() {
+                            propagate(state.db, command);
+                        }
+                        value.map_or(Reply::Null, Reply::BulkString)
+                    }
+                    Err(_) => Reply::wrongtype(),
+                };
+            }
+            if eq_ignore_case_6(cmd, b"unlock") {
+                // UNLOCK key token - delete the key only if it still holds token
+                if command.len() != 3 {
+                    return Reply::error(b"wrong number of arguments for 'unlock' command");
+                }
+                return match store.compare_and_delete(&command[1], &command[2], now) {
+                    Ok(Some(bytes_freed)) => {
+                        if max_memory() > 0 {
+                            MEMORY_USED.fetch_sub(bytes_freed as u64, Ordering::Relaxed);
+                        }
+                        propagate(state.db, command);
+                        Reply::Integer(1)
+                    }
+                    Ok(None) => Reply::Integer(0),
+                    Err(_) => Reply::wrongtype(),
+                };
+            }
+            if eq_ignore_case_6(cmd, b"dbsize") {
+                let size = store.len();
+                return Reply::Integer(size as i64);
+            }
+            if eq_ignore_case_6(cmd, b"config") {
+                // CONFIG GET pattern [pattern ...] | RESETSTAT | REWRITE; anything else is a stub
+                if command.len() >= 3 && command[1].eq_ignore_ascii_case(b"get") {
+                    let patterns: Vec<Vec<u8>> =
+                        command[2..].iter().map(|pattern| pattern.to_ascii_lowercase()).collect();
+                    let mut pairs = Vec::new();
+                    for (name, value) in live_config().parameters() {
+                        if patterns.iter().any(|pattern| glob_match(pattern, name.as_bytes())) {
+                            pairs.push(Reply::bulk(name.as_bytes()));
+                            pairs.push(Reply::BulkString(value.into()));
+                        }
+                    }
+                    return Reply::Array(pairs);
+                }
+                if command.len() == 2 && command[1].eq_ignore_ascii_case(b"resetstat") {
+                    // The peak restarts from current usage, not from zero
+                    MEMORY_PEAK.store(MEMORY_USED.load(Ordering::Relaxed), Ordering::Relaxed);
+                    TOTAL_COMMANDS.store(0, Ordering::Relaxed);
+                    TOTAL_CONNECTIONS.store(0, Ordering::Relaxed);
+                    REJECTED_CONNECTIONS.store(0, Ordering::Relaxed);
+                    EVICTED_KEYS.store(0, Ordering::Relaxed);
+                    EVICTED_CLIENTS.store(0, Ordering::Relaxed);
+                    EXPIRED_KEYS.store(0, Ordering::Relaxed);
+                    KEYSPACE_HITS.store(0, Ordering::Relaxed);
+                    KEYSPACE_MISSES.store(0, Ordering::Relaxed);
+                    COMMAND_LATENCY.iter().for_each(LatencyHistogram::reset);
+                    HOT_KEYS.lock().clear();
+                    return Reply::ok();
+                }
+                if command.len() == 2 && command[1].eq_ignore_ascii_case(b"rewrite") {
+                    let path = Config::path();
+                    if !std::path::Path::new(&path).exists() {
+                        return Reply::error(b"The server is running without a config file");
+                    }
+                    return match live_config().rewrite(&path) {
+                        Ok(()) => Reply::ok(),
+                        Err(e) => Reply::error(format!("Rewriting config file: {}", e).as_bytes()),
+                    };
+                }
+                return Reply::Array(Vec::new());
+            }
+            if eq_ignore_case_6(cmd, b"incrby") {
+                // INCRBY key increment
+                return if command.len() >= 3 {
+                    let Some(by) = parse_i64(&command[2]) else {
+                        return Reply::error(b"value is not an integer or out of range");
+                    };
+                    incr_command(dbs, state.db, command, now, b"increment or decrement would overflow", |n| {
+                        n.checked_add(by)
+                    })
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"decrby") {
+                // DECRBY key decrement
+                return if command.len() >= 3 {
+                    let Some(by) = parse_i64(&command[2]) else {
+                        return Reply::error(b"value is not an integer or out of range");
+                    };
+                    incr_command(dbs, state.db, command, now, b"increment or decrement would overflow", |n| {
+                        n.checked_sub(by)
+                    })
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+            if eq_ignore_case_6(cmd, b"expire") {
+                // EXPIRE key seconds [NX | XX | GT | LT]
+                return expire_command(dbs, state.db, command, now, |seconds| now.saturating_add_signed(seconds));
+            }
+        }
+        7 => {
+            let lower = [
+                cmd[0] | 0x20,
+                cmd[1] | 0x20,
+                cmd[2] | 0x20,
+                cmd[3] | 0x20,
+                cmd[4] | 0x20,
+                cmd[5] | 0x20,
+                cmd[6] | 0x20,
+            ];
+            if &lower == b"flushdb" {
+                // FLUSHDB [ASYNC|SYNC]: only the selected database; the others keep their keys
+                let freed = match flush_is_async(&command[1..]) {
+                    Some(true) => store.clear_with(Some(&reclaim_later)),
+                    Some(false) => store.clear(),
+                    None => return Reply::error(b"syntax error"),
+                };
+                if max_memory() > 0 {
+                    MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+                }
+                propagate(state.db, command);
+                return Reply::ok();
+            }
+            if &lower == b"latency" {
+                // LATENCY LATEST | HISTORY event | RESET [event ...] | DOCTOR
+                let Some(sub) = command.get(1) else {
+                    return Reply::error(b"wrong number of arguments for 'latency' command");
+                };
+                return if sub.eq_ignore_ascii_case(b"latest") && command.len() == 2 {
+                    let monitor = LATENCY_MONITOR.lock();
+                    let latest = monitor.events.iter().filter_map(|(name, event)| {
+                        let last = event.samples.back()?;
+                        Some(Reply::Array(vec![
+                            Reply::bulk(name.as_bytes()),
+                            Reply::Integer(last.time as i64),
+                            Reply::Integer(last.millis as i64),
+                            Reply::Integer(event.max as i64),
+                        ]))
+                    });
+                    Reply::Array(latest.collect())
+                } else if sub.eq_ignore_ascii_case(b"history") && command.len() == 3 {
+                    let history = LATENCY_MONITOR.lock().history(&command[2]);
+                    Reply::Array(
+                        history
+                            .into_iter()
+                            .map(|sample| {
+                                Reply::Array(vec![
+                                    Reply::Integer(sample.time as i64),
+                                    Reply::Integer(sample.millis as i64),
+                                ])
+                            })
+                            .collect(),
+                    )
+                } else if sub.eq_ignore_ascii_case(b"reset") {
+                    Reply::Integer(LATENCY_MONITOR.lock().reset(&command[2..]) as i64)
+                } else if sub.eq_ignore_ascii_case(b"doctor") && command.len() == 2 {
+                    let report = latency_doctor_report(
+                        &LATENCY_MONITOR.lock(),
+                        CONFIG.server.latency_monitor_threshold,
+                    );
+                    Reply::BulkString(report.into())
+                } else {
+                    Reply::error(b"unknown subcommand or wrong number of arguments for 'latency' command")
+                };
+            }
+            if &lower == b"command" {
+                // COMMAND [COUNT | INFO name... | GETKEYS cmd args...]
+                if command.len() == 1 {
+                    return Reply::Array(COMMAND_TABLE.iter().map(command_spec_reply).collect());
+                }
+                let sub = &command[1];
+                return if sub.eq_ignore_ascii_case(b"count") && command.len() == 2 {
+                    Reply::Integer(COMMAND_TABLE.len() as i64)
+                } else if sub.eq_ignore_ascii_case(b"info") {
+                    let specs = command[2..].iter().map(|name| match lookup_command(name) {
+                        Some(spec) => command_spec_reply(spec),
+                        None => Reply::NullArray,
+                    });
+                    Reply::Array(specs.collect())
+                } else if sub.eq_ignore_ascii_case(b"docs") {
+                    // No docs shipped; an empty reply keeps redis-cli's hints quiet
+                    Reply::Array(Vec::new())
+                } else if sub.eq_ignore_ascii_case(b"getkeys") && command.len() >= 3 {
+                    let args = &command[2..];
+                    match lookup_command(&args[0]) {
+                        None => Reply::error(b"Invalid command specified"),
+                        Some(spec) if !spec.arity_ok(args.len()) => Reply::error(b"Invalid number of arguments specified for command"),
+                        Some(spec) => {
+                            let keys = spec.keys(args);
+                            if keys.is_empty() {
+                                Reply::error(b"The command has no key arguments")
+                            } else {
+                                Reply::Array(keys.into_iter().cloned().map(Reply::BulkString).collect())
+                            }
+                        }
+                    }
+                } else {
+                    Reply::error(b"unknown subcommand or wrong number of arguments for 'command' command")
+                };
+            }
+            if &lower == b"persist" {
+                // PERSIST key - remove TTL from key
+                return if command.len() >= 2 {
+                    let key = &command[1];
+                    let shard = &store.shards[store.hash(key)];
+                    
+                    if let Some(mut entry) = shard.get_mut(key.as_ref()) {
+                        if let Some(expiry) = entry.expiry {
+                            if now >= expiry {
+                                drop(entry);
+                                shard.remove(key.as_ref());
+                                Reply::Integer(0)
+                            } else if entry.expiry.is_some() {
+                                entry.expiry = None;
+                                propagate(state.db, command);
+                                Reply::Integer(1)
+                            } else {
+                                Reply::Integer(0)
+                            }
+                        } else {
+                            // Key has no TTL
+                            Reply::Integer(0)
+                        }
+                    } else {
+                        Reply::Integer(0)
+                    }
+                } else {
+                    Reply::error(b"wrong number of arguments")
+                };
+            }
+        }
+        8 if cmd.eq_ignore_ascii_case(b"bitfield") => {
+            // BITFIELD key [GET type offset] [SET type offset value]
+            //   [INCRBY type offset increment] [OVERFLOW WRAP|SAT|FAIL]
+            if command.len() < 2 {
+                return Reply::error(b"wrong number of arguments for 'bitfield' command");
+            }
+            let ops = match parse_bitfield_ops(&command[2..]) {
+                Ok(ops) => ops,
+                Err(msg) => {
+                    return Reply::error(msg);
+                }
+            };
+            let key = &command[1];
+            let results = if ops.iter().all(|op| matches!(op, BitfieldOp::Get(..))) {
+                let current = match store.lookup_string(key, now, !state.no_touch) {
+                    Ok(value) => value,
+                    Err(_) => {
+                        return Reply::wrongtype();
+                    }
+                };
+                bitfield_apply(&mut BytesMut::from(current.as_deref().unwrap_or_default()), &ops)
+            } else {
+                // Room for the furthest field written, before the entry is locked
+                let reach = ops
+                    .iter()
+                    .filter_map(|op| match *op {
+                        BitfieldOp::Set(ty, offset, ..) | BitfieldOp::IncrBy(ty, offset, ..) => {
+                            Some((offset + ty.bits as u64).div_ceil(8) as usize)
+                        }
+                        BitfieldOp::Get(..) => None,
+                    })
+                    .max()
+                    .unwrap_or(0);
+                if !evict_if_needed(dbs, state.db, entry_size(key.len(), reach)) {
+                    return Reply::error(b"OOM command not allowed when used memory > 'maxmemory'");
+                }
+                match store.bitfield(key, &ops, CONFIG.memory.max_value_size, now) {
+                    Ok((results, written)) => {
+                        if written {
+                            propagate(state.db, command);
+                        }
+                        results
+                    }
+                    Err(BitfieldError::WrongType) => return Reply::wrongtype(),
+                    Err(BitfieldError::TooLarge) => {
+                        return Reply::error(b"value exceeds maximum allowed size");
+                    }
+                }
+            };
+
+            let fields = results.into_iter().map(|result| match result {
+                Some(field) => Reply::Integer(field),
+                None => Reply::Null,
+            });
+            return Reply::Array(fields.collect());
+        }
+        8 if cmd.eq_ignore_ascii_case(b"expireat") => {
+            // EXPIREAT key unix-seconds [NX | XX | GT | LT]
+            return expire_command(dbs, state.db, command, now, |at| at.max(0) as u64);
+        }
+        8 if cmd.eq_ignore_ascii_case(b"flushall") => {
+            // FLUSHALL [ASYNC|SYNC] - clear every database. Either way the keys are
+            // gone before the reply; ASYNC leaves freeing the values to the reclaimer.
+            let Some(background) = flush_is_async(&command[1..]) else {
+                return Reply::error(b"syntax error");
+            };
+            let mut freed = 0;
+            for db in dbs {
+                freed += if background {
+                    db.clear_with(Some(&reclaim_later))
+                } else {
+                    db.clear()
+                };
+            }
+            if max_memory() > 0 {
+                MEMORY_USED.fetch_sub(freed as u64, Ordering::Relaxed);
+            }
+            propagate(state.db, command);
+            return Reply::ok();
+        }
+        8 if cmd.eq_ignore_ascii_case(b"smembers") => {
+            // SMEMBERS key
+            return if command.len() >= 2 {
+                let mut budget = CommandBudget::start();
+                match store.read_value(&command[1], now, |value| match value {
+                    Value::Set(set) => Some(budget.collect(set.iter())),
+                    _ => None,
+                }) {
+                    Some(Some(Ok(members))) => Reply::bulk_array(&members),
+                    Some(Some(Err(_))) => Reply::error(TimedOut::MESSAGE),
+                    Some(None) => Reply::wrongtype(),
+                    None => Reply::Array(Vec::new()),
+                }
+            } else {
+                Reply::error(b"wrong number of arguments")
+            };
+        }
+        8 if cmd.eq_ignore_ascii_case(b"replconf") => {
+            // REPLCONF option value [option value ...]: replica handshake
+            // options (listening-port, capa, ack, ...) are acknowledged and
+            // ignored, since there is no replication to apply them to
+            return if command.len() % 2 == 1 {
+                Reply::ok()
+            } else {
+                Reply::error(b"syntax error")
+            };
+        }
+        9 if cmd.eq_ignore_ascii_case(b"pexpireat") => {
+            // PEXPIREAT key unix-ms [NX | XX | GT | LT]: expiry is kept in whole
+            // seconds, so the time is rounded up to the next one
+            return expire_command(dbs, state.db, command, now, |at| (at.max(0) as u64).div_ceil(1000));
+        }
+        9 if cmd.eq_ignore_ascii_case(b"randomkey") => {
+            // RANDOMKEY: a live key drawn uniformly, skipping expired ones the
+            // sweeper has not removed yet
+            for _ in 0..RANDOMKEY_ATTEMPTS {
+                let Some((_, key)) = store.random_keys(1).pop() else {
+                    break;
+                };
+                if store.exists(std::slice::from_ref(&key), now) > 0 {
+                    return Reply::BulkString(key);
+                }
+            }
+            return Reply::Null;
+        }
+        9 if cmd.eq_ignore_ascii_case(b"sismember") => {
+            // SISMEMBER key member
+            return if command.len() >= 3 {
+                match store.read_value(&command[1], now, |value| match value {
+                    Value::Set(set) => Some(set.contains(command[2].as_ref())),
+                    _ => None,
+                }) {
+                    Some(Some(found)) => Reply::Integer(found as i64),
+                    Some(None) => Reply::wrongtype(),
+                    None => Reply::Integer(0),
+                }
+            } else {
+                Reply::error(b"wrong number of arguments")
+            };
+        }
+        10 => {
+            let set = cmd.eq_ignore_ascii_case(b"sintercard");
+            if set || cmd.eq_ignore_ascii_case(b"zintercard") {
+                // SINTERCARD/ZINTERCARD numkeys key [key ...] [LIMIT limit]
+                let (keys, limit) = match parse_intercard_args(&command[1..]) {
+                    Ok(parsed) => parsed,
+                    Err(msg) => {
+                        return Reply::error(msg);
+                    }
+                };
+                return match store.inter_card(keys, !set, limit, now) {
+                    Ok(count) => Reply::Integer(count as i64),
+                    Err(_) => Reply::wrongtype(),
+                };
+            }
+        }
+        _ => {}
+    }
+
+    Reply::error(unknown_command_message(command).as_bytes())
+}
+
+// Unified stream type for both plain TCP and TLS
+pub enum MaybeStream {
+    Plain(TcpStream),
+    Tls(Box<tokio_rustls::server::TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for MaybeStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut *self {
+            MaybeStream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeStream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut *self {
+            MaybeStream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeStream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut *self {
+            MaybeStream::Plain(s) => Pin::new(s).poll_flush(cx),
+            MaybeStream::Tls(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut *self {
+            MaybeStream::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            MaybeStream::Tls(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+impl MaybeStream {
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            MaybeStream::Plain(s) => s,
+            MaybeStream::Tls(s) => s.get_ref().0,
+        }
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.tcp_stream().set_nodelay(nodelay)
+    }
+
+    // Enable TCP keepalive probes after `idle_secs` of silence so dead peers
+    // (e.g. behind a NAT that dropped the mapping) release their slot; 0 disables
+    pub fn set_keepalive(&self, idle_secs: u64) -> io::Result<()> {
+        let socket = socket2::SockRef::from(self.tcp_stream());
+        if idle_secs == 0 {
+            return socket.set_keepalive(false);
+        }
+        let keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs(idle_secs));
+        socket.set_tcp_keepalive(&keepalive)
+    }
+}
+
+// Load TLS configuration from certificate files
+pub async fn load_tls_config(
+    security: &SecurityConfig,
+) -> Result<Arc<RustlsServerConfig>, Box<dyn std::error::Error>> {
+    let (certs, key) = read_cert_and_key(&security.tls_cert_path, &security.tls_key_path).await?;
+
+    // Build TLS configuration
+    let builder = RustlsServerConfig::builder().with_no_client_auth();
+    let mut config = if security.tls_sni.is_empty() {
+        builder.with_single_cert(certs, key)?
+    } else {
+        let provider = builder.crypto_provider().clone();
+        let certified = |certs, key| -> Result<_, Box<dyn std::error::Error>> {
+            let signing_key = provider.key_provider.load_private_key(key)?;
+            Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+        };
+        let mut by_name = HashMap::new();
+        for sni in &security.tls_sni {
+            let (certs, key) = read_cert_and_key(&sni.cert_path, &sni.key_path).await?;
+            by_name.insert(sni.server_name.to_ascii_lowercase(), certified(certs, key)?);
+        }
+        builder.with_cert_resolver(Arc::new(SniCertResolver {
+            by_name,
+            default: certified(certs, key)?,
+        }))
+    };
+    config.alpn_protocols = security
+        .tls_alpn_protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+
+    Ok(Arc::new(config))
+}
+
+// Certificate chain and private key from a pair of PEM files. Every
+// CERTIFICATE block is kept, so a file holding the leaf followed by its
+// intermediates serves the full chain.
+pub async fn read_cert_and_key(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Box<dyn std::error::Error>> {
+    let cert_file = tokio::fs::read(cert_path).await?;
+    let certs = rustls_pemfile::certs(&mut cert_file.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", cert_path).into());
+    }
+
+    let key_file = tokio::fs::read(key_path).await?;
+    let key = parse_private_key(&key_file)?;
+    Ok((certs, key))
+}
+
+// Serves the [[security.tls_sni]] certificate matching the requested server
+// name, and the main certificate when there is no match or no SNI at all
+#[derive(Debug)]
+pub struct SniCertResolver {
+    pub by_name: HashMap<String, Arc<CertifiedKey>>,
+    pub default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let by_name = hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()));
+        Some(by_name.unwrap_or(&self.default).clone())
+    }
+}
+
+// First private key in a PEM file, trying PKCS#8 ("PRIVATE KEY"), then
+// PKCS#1 ("RSA PRIVATE KEY"), then SEC1 ("EC PRIVATE KEY"). Other blocks,
+// such as certificates bundled in the same file, are skipped.
+pub fn parse_private_key(
+    pem: &[u8],
+) -> Result<PrivateKeyDer<'static>, Box<dyn std::error::Error>> {
+    use rustls_pemfile::{ec_private_keys, pkcs8_private_keys, rsa_private_keys};
+
+    if let Some(key) = pkcs8_private_keys(&mut &pem[..]).next() {
+        return Ok(key?.into());
+    }
+    if let Some(key) = rsa_private_keys(&mut &pem[..]).next() {
+        return Ok(key?.into());
+    }
+    if let Some(key) = ec_private_keys(&mut &pem[..]).next() {
+        return Ok(key?.into());
+    }
+    Err("No private keys found in key file (expected PKCS#8, RSA or EC PEM)".into())
+}
+
+pub async fn handle_connection(mut stream: MaybeStream, dbs: Arc<Vec<ShardedStore>>, addr: SocketAddr) {
+    // Set TCP options from config
+    let _ = stream.set_nodelay(CONFIG.performance.tcp_nodelay);
+    let _ = stream.set_keepalive(CONFIG.performance.tcp_keepalive);
+
+    // Track connection
+    TOTAL_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+    ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+
+    let mut parser = RespParser::new();
+    let mut writer = RespWriter::new();
+    let mut state = ConnectionState::new(addr.to_string());
+    let mut batch_count = 0;
+    let mut shutdown = SHUTDOWN.subscribe();
+    
+    // Connection idle timeout (0 = disabled)
+    let timeout_duration = if CONFIG.server.connection_timeout > 0 {
+        Some(Duration::from_secs(CONFIG.server.connection_timeout))
+    } else {
+        None
+    };
+    // Measured from the last command, so releasing buffers part way through
+    // a wait does not restart it
+    let mut waiting_since = tokio::time::Instant::now();
+    let mut release_pending = false;
+
+    // Why the connection ended, if it was not the server shutting down
+    let ended: Option<RedistillError> = loop {
+        let now = get_timestamp();
+
+        // Apply idle timeout if configured
+        let parse = async {
+            match timeout_duration {
+                Some(timeout) => tokio::time::timeout_at(
+                    waiting_since + timeout,
+                    parser.parse_command(&mut stream),
+                )
+                .await
+                .ok(),
+                None => Some(parser.parse_command(&mut stream).await),
+            }
+        };
+        let parse_result = tokio::select! {
+            biased;
+            result = parse => match result {
+                Some(result) => result,
+                None => break Some(RedistillError::Timeout), // Close idle connection
+            },
+            _ = async { shutdown.wait_for(|stopping| *stopping).await.is_ok() } => {
+                // Waiting on the client while the server drains: say why the
+                // connection is going away and release the slot
+                writer.write_error(b"server is shutting down");
+                let _ = writer.flush(&mut stream).await;
+                break None;
+            }
+            _ = tokio::time::sleep(BUFFER_IDLE_RELEASE), if release_pending => {
+                // Reading is cancel safe: anything already received stays
+                // in the parser's buffer for the next attempt
+                parser.release_idle();
+                writer.release_idle();
+                release_pending = false;
+                continue;
+            }
+        };
+
+        match parse_result {
+            Ok(command) => {
+                execute_command(&dbs, &command, &mut writer, &mut state, now);
+                batch_count += 1;
+
+                match state.deferred.take() {
+                    Some(DeferredReply::Keys { count, now }) => {
+                        let streamed =
+                            stream_keys(&dbs[state.db], count, now, &mut writer, &mut stream, &state.client)
+                                .await;
+                        if let Err(e) = streamed {
+                            break Some(e);
+                        }
+                    }
+                    Some(DeferredReply::AuthFailure) => {
+                        // Sleeping before the flush below delays only this
+                        // connection, and every failure by the same amount
+                        tokio::time::sleep(Duration::from_millis(
+                            CONFIG.security.auth_failure_delay_ms,
+                        ))
+                        .await;
+                    }
+                    Some(DeferredReply::Sleep(delay)) => tokio::time::sleep(delay).await,
+                    None => {}
+                }
+
+                // Smart flushing (any one condition is enough):
+                // 1. If buffer is past reply_flush_threshold, flush immediately
+                // 2. If we hit batch size, flush
+                // 3. If no more commands buffered (interactive mode), flush
+                let should_flush = writer.should_flush()
+                    || batch_count >= CONFIG.server.batch_size
+                    || !parser.has_buffered_data();
+
+                if should_flush {
+                    if let Err(e) = flush_reply(&mut writer, &mut stream, &state.client).await {
+                        break Some(e);
+                    }
+                    batch_count = 0;
+                    release_pending = parser.sizer.target > parser.sizer.min
+                        || writer.sizer.target > writer.sizer.min;
+                }
+                waiting_since = tokio::time::Instant::now();
+            }
+            Err(e) => {
+                // Report protocol violations, then flush any pending responses before closing
+                if let Some(msg) = e.reply() {
+                    writer.write_error(msg);
+                }
+                let _ = writer.flush(&mut stream).await;
+                break Some(e);
+            }
+        }
+    };
+
+    if let Some(e) = ended
+        && log_enabled(if e.is_routine() { "debug" } else { "info" })
+    {
+        eprintln!("Connection id={} addr={} closed: {}", state.client.id, addr, e);
+    }
+
+    // Cleanup: decrement active connections
+    ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+}
+
+// Stream a KEYS reply of `count` elements one shard at a time, flushing
+// whenever the writer fills up. The array length was fixed when the keys were
+// counted: keys added since are left out, and keys deleted since are padded
+// with nulls so the reply stays well-formed.
+pub async fn stream_keys<S>(
+    store: &ShardedStore,
+    count: usize,
+    now: u64,
+    writer: &mut RespWriter,
+    stream: &mut S,
+    client: &ClientInfo,
+) -> Result<(), RedistillError>
+where
+    S: AsyncWrite + Unpin,
+{
+    writer.write_array_header(count);
+    let mut written = 0;
+
+    for shard in 0..store.num_shards {
+        for key in store.shard_keys(shard, now) {
+            if written == count {
+                break;
+            }
+            writer.write_bulk_string(&key);
+            written += 1;
+            if writer.should_flush() {
+                flush_reply(writer, stream, client).await?;
+            }
+        }
+    }
+
+    for _ in written..count {
+        writer.write_null();
+    }
+    Ok(())
+}
+
+// Send a connection's buffered replies, counting them against
+// memory.max_memory_clients until they are out. Going over the budget closes
+// the client with the most waiting, which may be this one. Fails if the
+// write fails or the client is evicted before it finishes.
+pub async fn flush_reply<S>(
+    writer: &mut RespWriter,
+    stream: &mut S,
+    client: &ClientInfo,
+) -> Result<(), RedistillError>
+where
+    S: AsyncWrite + Unpin,
+{
+    client.set_output_bytes(writer.buffer.len() as u64);
+    let budget = CONFIG.memory.max_memory_clients;
+    if budget > 0 && CLIENT_OUTPUT_BYTES.load(Ordering::Relaxed) > budget {
+        evict_largest_client();
+    }
+    let result = tokio::select! {
+        result = writer.flush(stream) => result,
+        _ = client.evict.notified() => Err(RedistillError::Evicted),
+    };
+    client.set_output_bytes(0);
+    result
+}
+
+// Start shutting down: readiness probes fail and idle connections close
+pub fn begin_shutdown() {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+    SHUTDOWN.send_replace(true);
+}
+
+// Wait up to `timeout` for every connection to close. Returns how many are
+// still open.
+pub async fn drain_connections(timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let active = ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
+        if active == 0 || Instant::now() >= deadline {
+            return active;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// ==================== Server ====================
+
+// Databases for `config`. Database 0 preallocates its shards; the others
+// grow on first use.
+pub fn new_databases(config: &Config) -> Arc<Vec<ShardedStore>> {
+    Arc::new(
+        (0..config.server.databases)
+            .map(|index| {
+                let store = if index == 0 {
+                    ShardedStore::new(config.server.num_shards)
+                } else {
+                    ShardedStore::sparse(config.server.num_shards)
+                }
+                .with_entry_limits(config.entry_limits());
+                if config.memory.expiry_index {
+                    store.indexed()
+                } else {
+                    store
+                }
+            })
+            .collect(),
+    )
+}
+
+/// A server started by [`run_server`]. It stops accepting connections, and
+/// its expiry and autosave tasks stop, when [`ServerHandle::shutdown`] is
+/// called or the handle is dropped.
+pub struct ServerHandle {
+    /// Address the listener is bound to, with the real port when the
+    /// config asked for port 0
+    pub addr: SocketAddr,
+    pub dbs: Arc<Vec<ShardedStore>>,
+    stop: tokio::sync::watch::Sender<bool>,
+    tasks: Vec<tokio::task::JoinHandle<()>>, // Accept loop, expiry sweeper and autosave
+}
+
+impl ServerHandle {
+    /// Port the listener is bound to
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Stop accepting and the background expiry and autosave tasks, and
+    /// wait for them to finish. Connections already open are not touched;
+    /// `begin_shutdown` and `drain_connections` close those.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(true);
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+// Resolves once `stopped` is set or its sender, the server's handle, is gone
+pub async fn stop_requested(stopped: &mut tokio::sync::watch::Receiver<bool>) {
+    let _ = stopped.wait_for(|&stop| stop).await;
+}
+
+/// Load the snapshot, bind the listener and start serving `config`.
+/// Returns once clients can connect.
+///
+/// The listener, storage, TLS and persistence settings come from `config`.
+/// Per-connection behaviour (password, limits, timeouts, eviction) follows
+/// the process-wide settings, which are read from CONFIG at startup and
+/// changed by CONFIG SET or a reload.
+pub async fn run_server(config: Config) -> io::Result<ServerHandle> {
+    let dbs = new_databases(&config);
+
+    let snapshot_path = config.persistence.snapshot_path.clone();
+    if !snapshot_path.is_empty() {
+        // Off the async workers, so the health endpoint keeps answering
+        let load_dbs = dbs.clone();
+        let loaded = tokio::task::spawn_blocking(move || load_snapshot(&load_dbs, &snapshot_path))
+            .await
+            .expect("snapshot load task panicked");
+        match loaded {
+            Ok(0) => {}
+            Ok(count) => println!(
+                "📂 Loaded {} keys from {}",
+                count, config.persistence.snapshot_path
+            ),
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Failed to load snapshot {}: {}", config.persistence.snapshot_path, e),
+                ));
+            }
+        }
+    }
+    LOADING.store(false, Ordering::Relaxed);
+    LAST_SAVE.store(get_timestamp(), Ordering::Relaxed);
+
+    let tls_acceptor = if config.security.tls_enabled {
+        if config.security.tls_cert_path.is_empty() || config.security.tls_key_path.is_empty() {
+            return Err(io::Error::other("TLS enabled but cert/key paths not configured"));
+        }
+        let tls_config = load_tls_config(&config.security)
+            .await
+            .map_err(|e| {
+                io::Error::other(format!(
+                    "Failed to load TLS configuration: {} (check the certificate and key files exist and are valid)",
+                    e
+                ))
+            })?;
+        Some(TlsAcceptor::from(tls_config))
+    } else {
+        None
+    };
+
+    // port = 0 asks the OS for a free port; the one it picks is what gets
+    // reported from here on
+    let bind_addr = listen_addr(&config.server.bind, config.server.port);
+    let listener = TcpListener::bind(&bind_addr)
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to bind to {}: {}", bind_addr, e)))?;
+    let addr = listener.local_addr()?;
+
+    // Everything spawned here stops with the handle
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let mut tasks = vec![tokio::spawn(expiration_task(dbs.clone(), stopped.clone()))];
+    let save_points = config.save_points();
+    if !save_points.is_empty() {
+        tasks.push(tokio::spawn(autosave_task(dbs.clone(), save_points, stopped.clone())));
+    }
+    tasks.push(tokio::spawn(accept_loop(listener, dbs.clone(), config, tls_acceptor, stopped)));
+
+    Ok(ServerHandle {
+        addr,
+        dbs,
+        stop,
+        tasks,
+    })
+}
+
+// Accept clients until `stopped` fires (or its sender is dropped), and
+// apply config reloads on SIGHUP
+async fn accept_loop(
+    listener: TcpListener,
+    dbs: Arc<Vec<ShardedStore>>,
+    config: Config,
+    mut tls_acceptor: Option<TlsAcceptor>,
+    mut stopped: tokio::sync::watch::Receiver<bool>,
+) {
+    let permits = connection_permits(config.server.max_connections);
+    let mut accept_throttle = (config.server.accept_rate > 0).then(|| {
+        AcceptThrottle::new(config.server.accept_rate, config.server.accept_burst, Instant::now())
+    });
+
+    // What the loop applied last, to tell which reloadable settings a
+    // SIGHUP actually changes
+    let mut applied = config;
+    let mut reload = ReloadSignal::new();
+
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                match result {
+                    Ok((tcp_stream, addr)) => {
+                        // Check connection rate limit
+                        if !check_rate_limit() {
+                            REJECTED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                            drop(tcp_stream);  // Close connection
+                            continue;
+                        }
+
+                        // Check max connections limit. While the server is full
+                        // the loop waits here and later clients stay in the backlog.
+                        let permit = match &permits {
+                            Some(permits) => match acquire_connection_permit(permits, CONNECTION_PERMIT_WAIT).await {
+                                Some(permit) => Some(permit),
+                                None => {
+                                    REJECTED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                                    drop(tcp_stream);  // Close connection
+                                    continue;
+                                }
+                            },
+                            None => None,
+                        };
+
+                        // Pace instead of refusing: while the bucket is empty the
+                        // loop sleeps and later clients wait in the listen backlog
+                        if let Some(throttle) = accept_throttle.as_mut() {
+                            let delay = throttle.acquire(Instant::now());
+                            if !delay.is_zero() {
+                                tokio::time::sleep(delay).await;
+                            }
+                        }
+
+                        let dbs_clone = dbs.clone();
+                        let tls_acceptor_clone = tls_acceptor.clone();
+
+                        tokio::spawn(async move {
+                            // Released when the connection ends, TLS failures included
+                            let _permit = permit;
+
+                            // Wrap in TLS if enabled
+                            let stream = if let Some(acceptor) = tls_acceptor_clone {
+                                match acceptor.accept(tcp_stream).await {
+                                    Ok(tls_stream) => MaybeStream::Tls(Box::new(tls_stream)),
+                                    Err(e) => {
+                                        eprintln!("TLS handshake failed: {}", e);
+                                        return;
+                                    }
+                                }
+                            } else {
+                                MaybeStream::Plain(tcp_stream)
+                            };
+
+                            handle_connection(stream, dbs_clone, addr).await;
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("Accept error: {}", e);
+                    }
+                }
+            }
+            _ = reload.recv() => {
+                let new = match Config::load() {
+                    Ok(new) => new,
+                    Err(e) => {
+                        eprintln!("❌ Config reload: {} is invalid, keeping the current settings: {}", Config::path(), e);
+                        continue;
+                    }
+                };
+                for warning in new.warnings() {
+                    eprintln!("⚠️  Config: {}", warning);
+                }
+                let mut report = apply_config_reload(&dbs, &new);
+
+                if (new.server.accept_rate, new.server.accept_burst)
+                    != (applied.server.accept_rate, applied.server.accept_burst)
+                {
+                    accept_throttle = (new.server.accept_rate > 0).then(|| {
+                        AcceptThrottle::new(new.server.accept_rate, new.server.accept_burst, Instant::now())
+                    });
+                    report.applied.push(format!(
+                        "server.accept_rate/accept_burst: {}/{} -> {}/{}",
+                        applied.server.accept_rate, applied.server.accept_burst,
+                        new.server.accept_rate, new.server.accept_burst
+                    ));
+                }
+
+                // Certificates are read again even if the paths are unchanged,
+                // so a renewed certificate in place is picked up too
+                if tls_acceptor.is_some() {
+                    match load_tls_config(&new.security).await {
+                        Ok(tls_config) => {
+                            tls_acceptor = Some(TlsAcceptor::from(tls_config));
+                            report.applied.push("security.tls (certificates reloaded)".to_string());
+                        }
+                        Err(e) => eprintln!("❌ Config reload: keeping the current TLS certificates: {}", e),
+                    }
+                }
+
+                applied = new;
+                if report.applied.is_empty() {
+                    println!("🔄 Config reloaded from {}: nothing to apply", Config::path());
+                } else {
+                    println!("🔄 Config reloaded from {}: {}", Config::path(), report.applied.join(", "));
+                }
+                if !report.restart_required.is_empty() {
+                    println!("⚠️  Restart required to apply: {}", report.restart_required.join(", "));
+                }
+            }
+            _ = stop_requested(&mut stopped) => break,
+        }
+    }
+}
+
+// ==================== Hot Key Tracking ====================
+
+// With server.track_hot_keys on, one command in this many has its keys counted
+pub const HOT_KEY_SAMPLE_RATE: u32 = 16;
+// Keys HOT_KEYS keeps a counter for
+pub const HOT_KEY_CAPACITY: usize = 256;
+
+// Approximate most-accessed keys (the Space-Saving algorithm): once the
+// table is full, a new key takes over the slot with the lowest count and
+// starts from that count plus one. A key's count may overestimate it by at
+// most the count it inherited, and any key accessed more often than
+// total / capacity is guaranteed a slot.
+pub struct HotKeys {
+    pub counts: HashMap<Bytes, u64>,
+    pub capacity: usize,
+}
+
+impl HotKeys {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            counts: HashMap::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, key: &Bytes) {
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        let mut count = 1;
+        if self.counts.len() >= self.capacity {
+            let Some((coldest, min)) = self
+                .counts
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(key, count)| (key.clone(), *count))
+            else {
+                return;
+            };
+            self.counts.remove(&coldest);
+            count += min;
+        }
+        self.counts.insert(key.clone(), count);
+    }
+
+    // Up to `n` keys, highest count first
+    pub fn top(&self, n: usize) -> Vec<(Bytes, u64)> {
+        let mut top: Vec<_> = self.counts.iter().map(|(k, c)| (k.clone(), *c)).collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+// Keys are counted by name alone, whichever database they are in
+pub static HOT_KEYS: Lazy<Mutex<HotKeys>> = Lazy::new(|| Mutex::new(HotKeys::new(HOT_KEY_CAPACITY)));
+
+// Count the key arguments of a sampled command
+pub fn record_hot_keys(command: &[Bytes]) {
+    let Some(spec) = command.first().and_then(|name| lookup_command(name)) else {
+        return;
+    };
+    let keys = spec.keys(command);
+    if keys.is_empty() {
+        return;
+    }
+    let mut hot = HOT_KEYS.lock();
+    for key in keys {
+        hot.record(key);
+    }
+}
+
+// ==================== Command Latency Metrics ====================
+
+// Histogram bucket upper bounds in microseconds; slower commands land in +Inf
+pub const LATENCY_BUCKETS_US: [u64; 9] = [10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000];
+
+pub struct LatencyHistogram {
+    pub buckets: [AtomicU64; LATENCY_BUCKETS_US.len() + 1], // Not cumulative; last is +Inf
+    pub sum_us: AtomicU64,
+    pub count: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_us: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(micros, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.sum_us.store(0, Ordering::Relaxed);
+        self.count.store(0, Ordering::Relaxed);
+    }
+}
+
+// One histogram per COMMAND_TABLE entry plus a final one for everything
+// else, so label cardinality stays fixed whatever names clients send
+pub static COMMAND_LATENCY: Lazy<Vec<LatencyHistogram>> =
+    Lazy::new(|| (0..=COMMAND_TABLE.len()).map(|_| LatencyHistogram::default()).collect());
+
+// LATENCY HISTORY keeps this many spikes per event, as Redis does
+pub const LATENCY_HISTORY_LEN: usize = 160;
+
+// A command that took at least latency_monitor_threshold: when it ran (unix
+// seconds) and how long it took
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySample {
+    pub time: u64,
+    pub millis: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct LatencyEvent {
+    pub samples: VecDeque<LatencySample>, // Oldest first, at most LATENCY_HISTORY_LEN
+    pub max: u64,                         // Slowest since the last LATENCY RESET
+}
+
+/// Latency spikes by event, for the LATENCY command. Spikes of one event
+/// in the same second are merged into one sample holding the slowest.
+#[derive(Debug, Default)]
+pub struct LatencyMonitor {
+    pub events: BTreeMap<&'static str, LatencyEvent>,
+}
+
+impl LatencyMonitor {
+    pub fn record(&mut self, event: &'static str, time: u64, millis: u64) {
+        let entry = self.events.entry(event).or_default();
+        entry.max = entry.max.max(millis);
+        if let Some(last) = entry.samples.back_mut()
+            && last.time == time
+        {
+            last.millis = last.millis.max(millis);
+            return;
+        }
+        if entry.samples.len() == LATENCY_HISTORY_LEN {
+            entry.samples.pop_front();
+        }
+        entry.samples.push_back(LatencySample { time, millis });
+    }
+
+    pub fn history(&self, event: &[u8]) -> Vec<LatencySample> {
+        std::str::from_utf8(event)
+            .ok()
+            .and_then(|event| self.events.get(event))
+            .map(|entry| entry.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forget the named events, or every event if none are named. Returns
+    /// how many events had samples.
+    pub fn reset(&mut self, events: &[Bytes]) -> usize {
+        if events.is_empty() {
+            let count = self.events.len();
+            self.events.clear();
+            return count;
+        }
+        let before = self.events.len();
+        self.events
+            .retain(|name, _| !events.iter().any(|event| event.as_ref() == name.as_bytes()));
+        before - self.events.len()
+    }
+}
+
+pub static LATENCY_MONITOR: Lazy<Mutex<LatencyMonitor>> = Lazy::new(|| Mutex::new(LatencyMonitor::default()));
+
+// Commands flagged fast in the table get their own event, as in Redis: an
+// O(1) command being slow says more about the host than about the workload
+pub fn latency_event(name: &[u8]) -> &'static str {
+    match lookup_command(name) {
+        Some(spec) if spec.flags.contains(&"fast") => "fast-command",
+        _ => "command",
+    }
+}
+
+// Human-readable LATENCY DOCTOR report
+pub fn latency_doctor_report(monitor: &LatencyMonitor, threshold: u64) -> String {
+    if threshold == 0 {
+        return "Latency monitoring is disabled. Set server.latency_monitor_threshold to a number of \
+                milliseconds to record commands slower than that.\n"
+            .to_string();
+    }
+    if monitor.events.is_empty() {
+        return format!("No latency spikes of {}ms or more recorded since the last reset.\n", threshold);
+    }
+
+    let mut report = String::new();
+    for (name, event) in &monitor.events {
+        let count = event.samples.len() as u64;
+        let avg = event.samples.iter().map(|s| s.millis).sum::<u64>() / count.max(1);
+        let deviation =
+            event.samples.iter().map(|s| s.millis.abs_diff(avg)).sum::<u64>() / count.max(1);
+        report.push_str(&format!(
+            "{}: {} latency spikes (average {}ms, mean deviation {}ms",
+            name, count, avg, deviation
+        ));
+        if let (Some(first), Some(last)) = (event.samples.front(), event.samples.back())
+            && count > 1
+        {
+            report.push_str(&format!(", period {}s", (last.time - first.time) / (count - 1)));
+        }
+        report.push_str(&format!("). Worst all time: {}ms.\n", event.max));
+    }
+
+    report.push_str("\nAdvice:\n");
+    if monitor.events.contains_key("command") {
+        report.push_str(
+            "- Slow commands ran. KEYS, SMEMBERS, SORT and range reads over large values are O(N); \
+             server.command_timeout bounds the ones that walk keys or elements.\n",
+        );
+    }
+    if monitor.events.contains_key("fast-command") {
+        report.push_str(
+            "- O(1) commands were slow, which points at the host rather than the workload: \
+             swapping, CPU starvation or an oversubscribed VM.\n",
+        );
+    }
+    report
+}
+
+pub fn command_metric_index(name: &[u8]) -> usize {
+    COMMAND_TABLE
+        .iter()
+        .position(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+        .unwrap_or(COMMAND_TABLE.len())
+}
+
+// Server counters plus per-command latency in OpenMetrics text format.
+// Commands that have not run yet are left out.
+pub fn render_metrics(histograms: &[LatencyHistogram]) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "# TYPE redistill_connected_clients gauge");
+    let _ = writeln!(
+        out,
+        "redistill_connected_clients {}",
+        ACTIVE_CONNECTIONS.load(Ordering::Relaxed)
+    );
+    let counters = [
+        ("redistill_connections", TOTAL_CONNECTIONS.load(Ordering::Relaxed)),
+        ("redistill_rejected_connections", REJECTED_CONNECTIONS.load(Ordering::Relaxed)),
+        ("redistill_commands", TOTAL_COMMANDS.load(Ordering::Relaxed)),
+        ("redistill_evicted_keys", EVICTED_KEYS.load(Ordering::Relaxed)),
+        ("redistill_evicted_clients", EVICTED_CLIENTS.load(Ordering::Relaxed)),
+        ("redistill_expired_keys", EXPIRED_KEYS.load(Ordering::Relaxed)),
+        ("redistill_keyspace_hits", KEYSPACE_HITS.load(Ordering::Relaxed)),
+        ("redistill_keyspace_misses", KEYSPACE_MISSES.load(Ordering::Relaxed)),
+    ];
+    for (name, value) in counters {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{}_total {}", name, value);
+    }
+
+    let _ = writeln!(out, "# TYPE redistill_command_duration_seconds histogram");
+    let _ = writeln!(out, "# UNIT redistill_command_duration_seconds seconds");
+    for (index, histogram) in histograms.iter().enumerate() {
+        let count = histogram.count.load(Ordering::Relaxed);
+        if count == 0 {
+            continue;
+        }
+        let cmd = COMMAND_TABLE.get(index).map_or("other", |spec| spec.name);
+        let mut cumulative = 0;
+        for (bucket, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS_US) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "redistill_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}",
+                cmd,
+                bound as f64 / 1_000_000.0,
+                cumulative
+            );
+        }
+        // _count repeats +Inf so the series agree even while commands are recorded
+        cumulative += histogram.buckets[LATENCY_BUCKETS_US.len()].load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "redistill_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}",
+            cmd, cumulative
+        );
+        let _ = writeln!(
+            out,
+            "redistill_command_duration_seconds_count{{cmd=\"{}\"}} {}",
+            cmd, cumulative
+        );
+        let _ = writeln!(
+            out,
+            "redistill_command_duration_seconds_sum{{cmd=\"{}\"}} {}",
+            cmd,
+            histogram.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+// Why the server should not receive traffic right now, if anything: still
+// loading its snapshot, draining for shutdown, or full with writes refused
+pub fn not_ready_reason(
+    loading: bool,
+    shutting_down: bool,
+    memory_used: u64,
+    max_memory: u64,
+    policy: EvictionPolicy,
+) -> Option<&'static str> {
+    if loading {
+        Some("loading")
+    } else if shutting_down {
+        Some("shutting_down")
+    } else if max_memory > 0 && memory_used >= max_memory && policy == EvictionPolicy::NoEviction {
+        Some("out_of_memory")
+    } else {
+        None
+    }
+}
+
+// Health check HTTP handler. /health/live fails only while shutting down;
+// /health/ready (and its alias /health) also fails while not ready to serve.
+pub async fn handle_health_check(
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let problem = match req.uri().path() {
+        "/metrics" => {
+            let response = Response::builder()
+                .header(
+                    "Content-Type",
+                    "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                )
+                .body(Full::new(Bytes::from(render_metrics(&COMMAND_LATENCY))))
+                .unwrap();
+            return Ok(response);
+        }
+        "/health/live" => SHUTTING_DOWN.load(Ordering::Relaxed).then_some("shutting_down"),
+        "/health" | "/health/ready" => not_ready_reason(
+            LOADING.load(Ordering::Relaxed),
+            SHUTTING_DOWN.load(Ordering::Relaxed),
+            MEMORY_USED.load(Ordering::Relaxed),
+            max_memory(),
+            eviction_policy(),
+        ),
+        _ => {
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            return Ok(response);
+        }
+    };
+
+    let status = format!(
+        r#"{{"status":"{}","uptime_seconds":{},"active_connections":{},"total_connections":{},"rejected_connections":{},"memory_used":{},"max_memory":{},"evicted_keys":{},"total_commands":{}}}"#,
+        problem.unwrap_or("ok"),
+        server_uptime(),
+        ACTIVE_CONNECTIONS.load(Ordering::Relaxed),
+        TOTAL_CONNECTIONS.load(Ordering::Relaxed),
+        REJECTED_CONNECTIONS.load(Ordering::Relaxed),
+        MEMORY_USED.load(Ordering::Relaxed),
+        max_memory(),
+        EVICTED_KEYS.load(Ordering::Relaxed),
+        TOTAL_COMMANDS.load(Ordering::Relaxed)
+    );
+
+    let response = Response::builder()
+        .status(if problem.is_some() {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::OK
+        })
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(status)))
+        .unwrap();
+
+    Ok(response)
+}
+
+// Start health check HTTP server
+pub async fn start_health_check_server(bind: &str, port: u16) {
+    let addr = listen_addr(bind, port);
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!(
+                "⚠️  Failed to bind health check endpoint on {}: {}",
+                addr, e
+            );
+            return;
+        }
+    };
+
+    println!(
+        "🏥 Health check endpoints: http://{0}/health/live, http://{0}/health/ready, http://{0}/metrics",
+        addr
+    );
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        let io = TokioIo::new(stream);
+
+        tokio::spawn(async move {
+            let _ = http1::Builder::new()
+                .serve_connection(io, service_fn(handle_health_check))
+                .await;
+        });
+    }
+}
+
@@ -0,0 +1,38 @@
+// AUTH against a real server. The password is process-wide, so these tests
+// run in their own binary rather than with the other integration tests.
+//
+// Run with: cargo test --test auth
+
+#[path = "../common/mod.rs"]
+mod common;
+
+use common::{Client, Value, start_server};
+use redistill::*;
+
+#[tokio::test]
+async fn test_commands_need_auth_when_a_password_is_set() {
+    *PASSWORD.write() = "s3cret".to_string();
+    let server = start_server().await;
+    let mut client = Client::connect(server.addr).await;
+
+    match client.call(&["SET", "k", "v"]).await {
+        Value::Error(e) => assert!(e.contains("NOAUTH"), "{}", e),
+        other => panic!("expected NOAUTH, got {:?}", other),
+    }
+    match client.call(&["AUTH", "wrong"]).await {
+        Value::Error(e) => assert!(e.contains("invalid password"), "{}", e),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert_eq!(server.dbs[0].len(), 0);
+
+    assert_eq!(client.call(&["AUTH", "s3cret"]).await, Value::ok());
+    assert_eq!(client.call(&["SET", "k", "v"]).await, Value::ok());
+    assert_eq!(client.call(&["GET", "k"]).await, Value::bulk("v"));
+
+    // Authenticating one connection leaves the others locked
+    let mut other = Client::connect(server.addr).await;
+    match other.call(&["GET", "k"]).await {
+        Value::Error(e) => assert!(e.contains("NOAUTH"), "{}", e),
+        other => panic!("expected NOAUTH, got {:?}", other),
+    }
+}
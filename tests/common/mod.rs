@@ -0,0 +1,108 @@
+// Test helpers shared by the integration test binaries: a server on an
+// ephemeral port and a raw RESP client to talk to it.
+#![allow(dead_code)]
+
+use redistill::*;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+// Test helper: Start a server with default settings on 127.0.0.1:0
+pub async fn start_server() -> ServerHandle {
+    let mut config = Config::default();
+    config.server.bind = "127.0.0.1".to_string();
+    config.server.port = 0;
+    config.server.num_shards = 16;
+    run_server(config).await.expect("server starts")
+}
+
+// A decoded RESP reply
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Value>>),
+}
+
+impl Value {
+    pub fn bulk(s: &str) -> Value {
+        Value::Bulk(Some(s.as_bytes().to_vec()))
+    }
+
+    pub fn ok() -> Value {
+        Value::Simple("OK".to_string())
+    }
+}
+
+// Test helper: A raw RESP client
+pub struct Client {
+    stream: BufReader<TcpStream>,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Client {
+        let stream = TcpStream::connect(addr).await.expect("connect to test server");
+        Client {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    // Send raw bytes, for malformed input and hand-built pipelines
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.get_mut().write_all(bytes).await.unwrap();
+    }
+
+    pub async fn send(&mut self, args: &[&str]) {
+        let mut out = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        self.send_raw(&out).await;
+    }
+
+    pub async fn call(&mut self, args: &[&str]) -> Value {
+        self.send(args).await;
+        self.read().await
+    }
+
+    pub async fn line(&mut self) -> String {
+        let mut line = String::new();
+        let n = self.stream.read_line(&mut line).await.unwrap();
+        assert!(n > 0, "server closed the connection");
+        line.trim_end_matches("\r\n").to_string()
+    }
+
+    pub async fn read(&mut self) -> Value {
+        let line = self.line().await;
+        let (kind, rest) = line.split_at(1);
+        match kind {
+            "+" => Value::Simple(rest.to_string()),
+            "-" => Value::Error(rest.to_string()),
+            ":" => Value::Integer(rest.parse().unwrap()),
+            "$" => {
+                let len: i64 = rest.parse().unwrap();
+                if len < 0 {
+                    return Value::Bulk(None);
+                }
+                let mut data = vec![0; len as usize + 2];
+                self.stream.read_exact(&mut data).await.unwrap();
+                data.truncate(len as usize);
+                Value::Bulk(Some(data))
+            }
+            "*" => {
+                let len: i64 = rest.parse().unwrap();
+                if len < 0 {
+                    return Value::Array(None);
+                }
+                let mut items = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    items.push(Box::pin(self.read()).await);
+                }
+                Value::Array(Some(items))
+            }
+            _ => panic!("unexpected reply line: {:?}", line),
+        }
+    }
+}
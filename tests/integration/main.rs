@@ -0,0 +1,143 @@
+// Integration tests for Redistill: each test starts a real server on an
+// ephemeral port with run_server() and talks RESP to it over TCP, covering
+// the parser, command dispatch and per-connection state end to end.
+//
+// Run with: cargo test --test integration
+
+#[path = "../common/mod.rs"]
+mod common;
+
+use common::{Client, Value, start_server};
+use redistill::*;
+use std::sync::atomic::Ordering;
+use tokio::net::TcpStream;
+
+// ==================== Connection Tests ====================
+
+#[tokio::test]
+async fn test_server_binds_an_ephemeral_port() {
+    let server = start_server().await;
+    assert!(server.addr.ip().is_loopback());
+    assert_ne!(server.port(), 0);
+    // Only main publishes its port process-wide
+    assert_eq!(BOUND_PORT.load(Ordering::Relaxed), 0);
+
+    let mut client = Client::connect(server.addr).await;
+    assert_eq!(client.call(&["PING"]).await, Value::Simple("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_accepting() {
+    let server = start_server().await;
+    let addr = server.addr;
+    server.shutdown().await;
+
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_the_expiry_sweeper() {
+    let running = start_server().await;
+    let stopped = start_server().await;
+    let dbs = stopped.dbs.clone();
+    stopped.shutdown().await;
+
+    // A key nobody reads again goes only if the sweeper removes it
+    for store in [&running.dbs[0], &dbs[0]] {
+        store.set(bytes::Bytes::from("short"), bytes::Bytes::from("v"), Some(1), get_timestamp());
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    assert_eq!(running.dbs[0].len(), 0);
+    assert_eq!(dbs[0].len(), 1);
+}
+
+#[tokio::test]
+async fn test_servers_keep_separate_data() {
+    let first = start_server().await;
+    let second = start_server().await;
+    assert_ne!(first.addr, second.addr);
+
+    let mut a = Client::connect(first.addr).await;
+    let mut b = Client::connect(second.addr).await;
+    assert_eq!(a.call(&["SET", "shared", "first"]).await, Value::ok());
+    assert_eq!(b.call(&["GET", "shared"]).await, Value::Bulk(None));
+    assert_eq!(first.dbs[0].len(), 1);
+    assert_eq!(second.dbs[0].len(), 0);
+}
+
+// ==================== Command Dispatch Tests ====================
+
+#[tokio::test]
+async fn test_set_get_round_trip() {
+    let server = start_server().await;
+    let mut client = Client::connect(server.addr).await;
+
+    assert_eq!(client.call(&["SET", "greeting", "hello world"]).await, Value::ok());
+    assert_eq!(client.call(&["GET", "greeting"]).await, Value::bulk("hello world"));
+    assert_eq!(client.call(&["INCR", "counter"]).await, Value::Integer(1));
+    assert_eq!(client.call(&["INCRBY", "counter", "41"]).await, Value::Integer(42));
+    assert_eq!(client.call(&["DEL", "greeting", "missing"]).await, Value::Integer(1));
+    assert_eq!(client.call(&["GET", "greeting"]).await, Value::Bulk(None));
+}
+
+#[tokio::test]
+async fn test_errors_keep_the_connection_usable() {
+    let server = start_server().await;
+    let mut client = Client::connect(server.addr).await;
+
+    match client.call(&["NOSUCHCOMMAND", "x"]).await {
+        Value::Error(e) => assert!(e.starts_with("ERR unknown command"), "{}", e),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    match client.call(&["GET"]).await {
+        Value::Error(e) => assert!(e.contains("wrong number of arguments"), "{}", e),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert_eq!(client.call(&["SET", "list", "v"]).await, Value::ok());
+    match client.call(&["LPUSH", "list", "x"]).await {
+        Value::Error(e) => assert!(e.starts_with("WRONGTYPE"), "{}", e),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert_eq!(client.call(&["PING"]).await, Value::Simple("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn test_pipelined_commands_reply_in_order() {
+    let server = start_server().await;
+    let mut client = Client::connect(server.addr).await;
+
+    // Three commands in one write, the last split across two
+    client
+        .send_raw(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\n1\r\n*2\r\n$4\r\nINCR\r\n$1\r\nk\r\n*2\r\n$3\r\nGE")
+        .await;
+    client.send_raw(b"T\r\n$1\r\nk\r\n").await;
+
+    assert_eq!(client.read().await, Value::ok());
+    assert_eq!(client.read().await, Value::Integer(2));
+    assert_eq!(client.read().await, Value::bulk("2"));
+}
+
+#[tokio::test]
+async fn test_inline_commands_with_either_line_ending() {
+    let server = start_server().await;
+    let mut client = Client::connect(server.addr).await;
+
+    client.send_raw(b"SET greeting hello\r\nGET greeting\nPING\n").await;
+    assert_eq!(client.read().await, Value::ok());
+    assert_eq!(client.read().await, Value::bulk("hello"));
+    assert_eq!(client.read().await, Value::Simple("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn test_select_is_per_connection() {
+    let server = start_server().await;
+    let mut first = Client::connect(server.addr).await;
+    let mut second = Client::connect(server.addr).await;
+
+    assert_eq!(first.call(&["SELECT", "1"]).await, Value::ok());
+    assert_eq!(first.call(&["SET", "k", "in-db-1"]).await, Value::ok());
+    assert_eq!(second.call(&["GET", "k"]).await, Value::Bulk(None));
+    assert_eq!(second.call(&["SELECT", "1"]).await, Value::ok());
+    assert_eq!(second.call(&["GET", "k"]).await, Value::bulk("in-db-1"));
+    assert_eq!(server.dbs[1].len(), 1);
+}
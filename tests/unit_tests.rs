@@ -8,6 +8,7 @@ use bytes::Bytes;
 use redistill::*;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 // Test helper: Create a test store
 fn create_test_store() -> ShardedStore {
@@ -19,6 +20,15 @@ fn now() -> u64 {
     get_timestamp()
 }
 
+// Test helper: Build command arguments / member lists from string slices
+fn args(parts: &[&str]) -> Vec<Bytes> {
+    parts.iter().map(|p| Bytes::from(p.to_string())).collect()
+}
+
+// Test helper: CONFIG RESETSTAT zeroes the global counters, so the test that
+// runs it and the tests that measure counter deltas take turns
+static GLOBAL_COUNTERS: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+
 // ==================== Basic Storage Tests ====================
 
 #[test]
@@ -51,7 +61,7 @@ fn test_store_delete() {
 
     store.set(key.clone(), value, None, now());
 
-    let count = store.delete(&[key.clone()]);
+    let (count, _) = store.delete(std::slice::from_ref(&key));
     assert_eq!(count, 1);
 
     let result = store.get(&key, now());
@@ -69,7 +79,7 @@ fn test_store_delete_multiple() {
     }
 
     // Delete all
-    let count = store.delete(&keys);
+    let (count, _) = store.delete(&keys);
     assert_eq!(count, 5);
 
     // Verify all deleted
@@ -145,6 +155,112 @@ fn test_store_flush() {
     assert_eq!(store.len(), 0);
 }
 
+#[test]
+fn test_flush_one_database_leaves_others() {
+    let dbs = [ShardedStore::new(8), ShardedStore::new(8)];
+    dbs[0].set(Bytes::from("kept"), Bytes::from("value"), None, now());
+    dbs[1].set(Bytes::from("a"), Bytes::from("12345"), None, now());
+    dbs[1].set(Bytes::from("bb"), Bytes::from("x"), None, now());
+
+    // Freed bytes cover only the flushed database's keys
+    let freed = dbs[1].clear();
+    assert_eq!(freed, entry_size(1, 5) + entry_size(2, 1));
+    assert!(dbs[1].is_empty());
+    assert_eq!(dbs[0].get(b"kept", now()), Some(Bytes::from("value")));
+    assert_eq!(dbs[1].clear(), 0);
+}
+
+#[test]
+fn test_parallel_clear_hands_values_to_discard() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Enough keys to take the multi-threaded path
+    let store = create_test_store();
+    let mut expected = 0;
+    for i in 0..PARALLEL_CLEAR_MIN_KEYS {
+        let key = Bytes::from(format!("key{}", i));
+        expected += entry_size(key.len(), 1);
+        store.set(key, Bytes::from("v"), None, now());
+    }
+    for i in 0..10 {
+        let key = Bytes::from(format!("set{}", i));
+        expected += entry_size(key.len(), 2);
+        store.sadd(&key, &args(&["a", "b"]), now()).unwrap();
+    }
+
+    let sets = AtomicUsize::new(0);
+    let discarded = AtomicUsize::new(0);
+    let freed = store.clear_with(Some(&|values: Vec<Value>| {
+        let count = values.iter().filter(|value| value.type_name() == "set").count();
+        sets.fetch_add(count, Ordering::Relaxed);
+        discarded.fetch_add(values.len(), Ordering::Relaxed);
+    }));
+    assert_eq!(freed, expected);
+    assert_eq!(sets.into_inner(), 10);
+    assert_eq!(discarded.into_inner(), PARALLEL_CLEAR_MIN_KEYS + 10);
+    assert!(store.is_empty());
+}
+
+#[test]
+fn test_flush_modes() {
+    assert_eq!(flush_is_async(&[]), Some(false));
+    assert_eq!(flush_is_async(&args(&["async"])), Some(true));
+    assert_eq!(flush_is_async(&args(&["SYNC"])), Some(false));
+    assert_eq!(flush_is_async(&args(&["LAZY"])), None);
+    assert_eq!(flush_is_async(&args(&["ASYNC", "SYNC"])), None);
+
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50012".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+    run(&["SADD", "s", "a"]);
+    assert_eq!(run(&["FLUSHALL", "ASYNC"]), Reply::ok());
+    assert_eq!(run(&["EXISTS", "s"]), Reply::Integer(0));
+    assert_eq!(run(&["FLUSHDB", "later"]), Reply::error(b"syntax error"));
+}
+
+#[test]
+fn test_shard_keys_cover_all_keys() {
+    let store = ShardedStore::new(8);
+    let now = 1000;
+    for i in 0..200 {
+        store.set(Bytes::from(format!("key{}", i)), Bytes::from("v"), None, now);
+    }
+    store.set(Bytes::from("expired"), Bytes::from("v"), Some(1), now - 10);
+
+    assert_eq!(store.count_keys(now, &mut CommandBudget::unlimited()), Ok(200));
+
+    let mut streamed: Vec<Bytes> = (0..store.num_shards)
+        .flat_map(|shard| store.shard_keys(shard, now))
+        .collect();
+    let mut all = store.keys(now, &mut CommandBudget::unlimited()).unwrap();
+    streamed.sort();
+    all.sort();
+    assert_eq!(streamed, all);
+}
+
+#[test]
+fn test_command_budget_stops_long_scans() {
+    let store = ShardedStore::new(8);
+    let now = 1000;
+    for i in 0..3000 {
+        store.set(Bytes::from(format!("key{}", i)), Bytes::from("v"), None, now);
+    }
+
+    let expired = || CommandBudget::new(Some(Duration::ZERO));
+    assert_eq!(store.keys(now, &mut expired()), Err(TimedOut));
+    assert_eq!(store.count_keys(now, &mut expired()), Err(TimedOut));
+    assert_eq!(store.scan(0, 5000, None, None, now, &mut expired()), Err(TimedOut));
+
+    // The clock is only read every BUDGET_CHECK_INTERVAL items
+    let mut budget = expired();
+    assert!(budget.collect(0..BUDGET_CHECK_INTERVAL - 1).is_ok());
+    assert_eq!(budget.tick(), Err(TimedOut));
+
+    let mut generous = CommandBudget::new(Some(Duration::from_secs(60)));
+    assert_eq!(store.keys(now, &mut generous).unwrap().len(), 3000);
+    assert_eq!(store.len(), 3000);
+}
+
 // ==================== TTL/Expiration Tests ====================
 
 #[test]
@@ -254,6 +370,19 @@ fn test_entry_size_calculation() {
     assert_eq!(size, 174); // 10 + 100 + 64 overhead
 }
 
+#[test]
+fn test_value_size_limit_boundary() {
+    let max = 1024;
+    assert!(value_size_allowed(1023, max));
+    assert!(value_size_allowed(1024, max));
+    assert!(!value_size_allowed(1025, max));
+    assert!(value_size_allowed(0, max));
+
+    // 0 disables the limit
+    assert!(value_size_allowed(usize::MAX, 0));
+    assert_eq!(Config::default().memory.max_value_size, 0);
+}
+
 #[test]
 fn test_format_bytes() {
     assert_eq!(format_bytes(500), "500B");
@@ -262,6 +391,106 @@ fn test_format_bytes() {
     assert_eq!(format_bytes(1024 * 1024 * 1024), "1.00GB");
 }
 
+#[test]
+fn test_listen_addr_brackets_ipv6() {
+    assert_eq!(listen_addr("127.0.0.1", 8080), "127.0.0.1:8080");
+    assert_eq!(listen_addr("localhost", 8080), "localhost:8080");
+    assert_eq!(listen_addr("::", 8080), "[::]:8080");
+    assert_eq!(listen_addr("[::1]", 8080), "[::1]:8080");
+}
+
+#[test]
+fn test_memory_peak_and_stats() {
+    use std::sync::atomic::Ordering;
+
+    add_memory_used(1000);
+    assert!(MEMORY_PEAK.load(Ordering::Relaxed) >= MEMORY_USED.load(Ordering::Relaxed));
+    MEMORY_USED.fetch_sub(1000, Ordering::Relaxed);
+
+    let dbs = vec![create_test_store(), create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50013".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+    run(&["SET", "a", "1"]);
+    run(&["SELECT", "1"]);
+    run(&["SET", "b", "2"]);
+    let Reply::Array(stats) = run(&["MEMORY", "STATS"]) else {
+        panic!("MEMORY STATS should reply with an array");
+    };
+    let names: Vec<_> = stats.iter().step_by(2).cloned().collect();
+    assert_eq!(names[0], Reply::bulk(b"peak.allocated"));
+    assert!(names.contains(&Reply::bulk(b"dataset.bytes")));
+    let keys = names.iter().position(|name| *name == Reply::bulk(b"keys.count")).unwrap();
+    assert_eq!(stats[keys * 2 + 1], Reply::Integer(2));
+    let policy = names.iter().position(|name| *name == Reply::bulk(b"maxmemory.policy")).unwrap();
+    assert_eq!(stats[policy * 2 + 1], Reply::bulk(CONFIG.memory.eviction_policy.as_bytes()));
+}
+
+#[test]
+fn test_config_resetstat() {
+    use std::sync::atomic::Ordering;
+    let _counters = GLOBAL_COUNTERS.lock();
+
+    // Marks far above anything the other tests running alongside can reach
+    const MARK: u64 = 1 << 40;
+    let histogram = &COMMAND_LATENCY[command_metric_index(b"randomkey")];
+    histogram.record(Duration::from_micros(30));
+    EVICTED_KEYS.fetch_add(MARK, Ordering::Relaxed);
+    MEMORY_PEAK.fetch_max(MARK, Ordering::Relaxed);
+
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50037".to_string());
+    assert_eq!(execute(&dbs, &args(&["CONFIG", "RESETSTAT"]), &mut state, now()), Reply::ok());
+
+    // Cumulative counters and the memory peak restart
+    assert_eq!(histogram.count.load(Ordering::Relaxed), 0);
+    assert!(histogram.buckets.iter().all(|b| b.load(Ordering::Relaxed) == 0));
+    assert!(EVICTED_KEYS.load(Ordering::Relaxed) < MARK);
+    assert!(MEMORY_PEAK.load(Ordering::Relaxed) < MARK);
+}
+
+// Other tests count concurrently, so a counter grows by at least what the
+// test itself does
+fn keyspace_counters() -> [u64; 3] {
+    use std::sync::atomic::Ordering;
+    [&KEYSPACE_HITS, &KEYSPACE_MISSES, &EXPIRED_KEYS].map(|c| c.load(Ordering::Relaxed))
+}
+
+#[test]
+fn test_keyspace_hits_misses_and_expiry_are_counted() {
+    let _counters = GLOBAL_COUNTERS.lock();
+    let store = create_test_store();
+    store.set(Bytes::from("live"), Bytes::from("v"), None, 1000);
+    store.set(Bytes::from("ttl"), Bytes::from("v"), Some(10), 1000);
+
+    let before = keyspace_counters();
+    assert!(store.get(b"live", 1000).is_some());
+    assert!(store.get(b"absent", 1000).is_none());
+    assert!(store.get(b"ttl", 1010).is_none());
+    assert!(store.get(b"ttl", 1010).is_none()); // Already gone: a plain miss
+    let after = keyspace_counters();
+    assert!(after[0] > before[0]);
+    assert!(after[1] >= before[1] + 3);
+    assert!(after[2] > before[2]);
+}
+
+#[test]
+fn test_lazy_expiry_by_writes_is_counted() {
+    let _counters = GLOBAL_COUNTERS.lock();
+    let store = create_test_store();
+    for key in ["getdel", "getex", "incr", "list", "expire"] {
+        store.set(Bytes::from(key), Bytes::from("1"), Some(10), 1000);
+    }
+
+    // Writes and GETDEL/GETEX that find a key expired count it
+    let before = keyspace_counters();
+    assert_eq!(store.get_del(&Bytes::from("getdel"), 1010).unwrap().0, None);
+    assert_eq!(store.get_ex(&Bytes::from("getex"), None, 1010).unwrap().0, None);
+    assert_eq!(store.update_integer(&Bytes::from("incr"), 1010, |n| n.checked_add(1)), Ok(1));
+    assert_eq!(store.push(&Bytes::from("list"), &[Bytes::from("x")], false, 1010), Ok(1));
+    assert!(!store.expire_at(&Bytes::from("expire"), 2000, ExpireCondition::Always, 1010));
+    assert!(keyspace_counters()[2] >= before[2] + 5);
+}
+
 // ==================== Eviction Policy Tests ====================
 
 #[test]
@@ -298,10 +527,629 @@ fn test_memory_limit_zero_cost() {
     let store = create_test_store();
 
     // This should work even with large size
-    let result = evict_if_needed(&store, 999999999);
+    let result = evict_if_needed(std::slice::from_ref(&store), 0, 999999999);
     assert!(result);
 }
 
+#[test]
+fn test_lru_sample_prefers_least_recently_written() {
+    use std::sync::atomic::Ordering;
+
+    let store = ShardedStore::new(64);
+    let (old, new) = (Bytes::from("old"), Bytes::from("new"));
+    assert_ne!(store.hash(&old), store.hash(&new));
+
+    // Writes are stamped with the uptime at the time of the write
+    SERVER_START_TIME.store((get_timestamp() - 1000) as u32, Ordering::Relaxed);
+    store.set(old.clone(), Bytes::from("v"), None, now());
+    let entry = store.shards[store.hash(&old)].get(&old).unwrap();
+    assert!(entry.last_accessed.load(Ordering::Relaxed) >= 1000);
+    drop(entry);
+
+    SERVER_START_TIME.store((get_timestamp() - 2000) as u32, Ordering::Relaxed);
+    store.set(new.clone(), Bytes::from("v"), None, now());
+
+    let sample = [(store.hash(&new), new.clone()), (store.hash(&old), old.clone())];
+    assert_eq!(oldest_in_sample(&store, sample.into_iter()), Some((store.hash(&old), old)));
+    assert_eq!(oldest_in_sample(&store, std::iter::empty()), None);
+}
+
+// One key per shard, all last accessed at `last_accessed`
+fn fill_every_shard(store: &ShardedStore, last_accessed: u32) -> Vec<Bytes> {
+    use std::sync::atomic::Ordering;
+
+    let mut keys = vec![Bytes::new(); store.num_shards];
+    let mut i = 0;
+    while keys.iter().any(|k| k.is_empty()) {
+        let key = Bytes::from(format!("key{}", i));
+        let shard = store.hash(&key);
+        keys[shard] = key;
+        i += 1;
+    }
+    for key in &keys {
+        store.set(key.clone(), Bytes::from("v"), None, now());
+        let entry = store.shards[store.hash(key)].get(key).unwrap();
+        entry.last_accessed.store(last_accessed, Ordering::Relaxed);
+    }
+    keys
+}
+
+#[test]
+fn test_seeded_lru_eviction_picks_oldest_sampled_key() {
+    use std::sync::atomic::Ordering;
+
+    let store = ShardedStore::new(16).seeded(7);
+    let keys = fill_every_shard(&store, 100);
+
+    // Same seed, same shards sampled
+    let mut rng = fastrand::Rng::with_seed(7);
+    let victim_shard = rng.usize(..16);
+    let victim = &keys[victim_shard];
+    let entry = store.shards[victim_shard].get(victim).unwrap();
+    entry.last_accessed.store(1, Ordering::Relaxed);
+    drop(entry);
+
+    assert!(evict_lru(&store) > 0);
+    assert!(store.get(victim, now()).is_none());
+    assert_eq!(store.len(), 15);
+}
+
+#[test]
+fn test_seeded_random_eviction_is_repeatable() {
+    let store = ShardedStore::new(16).seeded(99);
+    let keys = fill_every_shard(&store, 0);
+
+    let mut rng = fastrand::Rng::with_seed(99);
+    let victim = &keys[rng.usize(..16)];
+
+    assert!(evict_random(&store) > 0);
+    assert!(store.get(victim, now()).is_none());
+    assert_eq!(store.len(), 15);
+}
+
+#[test]
+fn test_random_keys_are_uniform_across_uneven_shards() {
+    // Shard 0 holds one key and shard 1 holds nine: every key should still
+    // come up about a tenth of the time
+    let store = ShardedStore::new(2).seeded(3);
+    let mut wanted = [1, 9];
+    for i in 0.. {
+        let key = Bytes::from(format!("key{}", i));
+        let shard = store.hash(&key);
+        if wanted[shard] > 0 {
+            wanted[shard] -= 1;
+            store.set(key, Bytes::from("v"), None, now());
+        }
+        if wanted == [0, 0] {
+            break;
+        }
+    }
+
+    let draws = 20_000;
+    let mut counts = std::collections::HashMap::new();
+    for (shard, key) in store.random_keys(draws) {
+        assert_eq!(shard, store.hash(&key));
+        *counts.entry(key).or_insert(0usize) += 1;
+    }
+    assert_eq!(counts.len(), 10);
+    for count in counts.values() {
+        assert!((1500..2500).contains(count), "{:?}", counts);
+    }
+
+    assert!(ShardedStore::new(4).random_keys(5).is_empty());
+}
+
+#[test]
+fn test_eviction_goal_frees_to_low_watermark() {
+    // 95KB used of 100KB, writing 10KB: free down to 90% (90KB) including the write
+    assert_eq!(eviction_goal(95_000, 10_000, 100_000, 90), 15_000);
+
+    // Never free less than the pending write needs
+    assert_eq!(eviction_goal(100_000, 10, 100_000, 100), 10);
+    assert_eq!(eviction_goal(0, 500, 100_000, 90), 500);
+}
+
+#[test]
+fn test_eviction_hysteresis_evicts_in_batches() {
+    // Simulate a steady write load of 1KB entries against a 100KB limit and
+    // count how many eviction passes run with and without hysteresis
+    fn eviction_passes(low_pct: u64) -> usize {
+        let max_memory = 100_000u64;
+        let entry = 1_000usize;
+        let mut used = 0u64;
+        let mut passes = 0;
+
+        for _ in 0..1_000 {
+            if used + entry as u64 > max_memory {
+                let goal = eviction_goal(used, entry, max_memory, low_pct) as u64;
+                // Evict whole entries until the goal is met
+                used -= goal.div_ceil(entry as u64) * entry as u64;
+                passes += 1;
+            }
+            used += entry as u64;
+            assert!(used <= max_memory);
+        }
+        passes
+    }
+
+    let one_at_a_time = eviction_passes(100);
+    let batched = eviction_passes(90);
+
+    assert!(one_at_a_time > 800);
+    assert!(batched * 5 < one_at_a_time, "batched={batched}");
+}
+
+#[test]
+fn test_expiry_index_pops_soonest_first() {
+    let index = ExpiryIndex::default();
+    index.insert(300, Bytes::from("late"));
+    index.insert(100, Bytes::from("early"));
+    index.insert(200, Bytes::from("middle"));
+
+    // Nothing is due yet
+    assert!(index.pop_due(50, 10).is_empty());
+
+    assert_eq!(index.pop_due(250, 10), vec![Bytes::from("early"), Bytes::from("middle")]);
+    assert_eq!(index.len(), 1);
+
+    // Limit caps how many keys are popped per call
+    index.insert(300, Bytes::from("late2"));
+    assert_eq!(index.pop_due(300, 1).len(), 1);
+    assert_eq!(index.pop_due(300, 10).len(), 1);
+    assert!(index.is_empty());
+}
+
+#[test]
+fn test_expire_indexed_skips_stale_entries() {
+    let store = ShardedStore::with_expiry_index(4);
+    let now = 1000;
+
+    store.set(Bytes::from("short"), Bytes::from("v"), Some(10), now);
+    store.set(Bytes::from("long"), Bytes::from("v"), Some(100), now);
+    // Overwritten without a TTL: its index entry is now stale
+    store.set(Bytes::from("persisted"), Bytes::from("v"), Some(10), now);
+    store.set(Bytes::from("persisted"), Bytes::from("v"), None, now);
+
+    assert_eq!(store.expire_indexed(now + 10, 100), 1);
+    assert!(!store.shards[store.hash(b"short")].contains_key(b"short".as_slice()));
+    assert!(store.get(b"persisted", now + 10).is_some());
+    assert!(store.get(b"long", now + 10).is_some());
+
+    assert_eq!(store.expire_indexed(now + 100, 100), 1);
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn test_store_without_expiry_index() {
+    let store = ShardedStore::new(4);
+    store.set(Bytes::from("key"), Bytes::from("v"), Some(10), 1000);
+    assert!(store.expiry_index.is_none());
+    assert_eq!(store.expire_indexed(2000, 100), 0);
+}
+
+#[test]
+fn test_memory_doctor_sampling() {
+    let store = ShardedStore::new(4);
+    store.set(Bytes::from("a"), Bytes::from("1234"), Some(60), 1000);
+    store.set(Bytes::from("b"), Bytes::from("12"), None, 1000);
+
+    let sample = sample_keys(&store, 1000);
+    assert_eq!(sample.keys, 2);
+    assert_eq!(sample.with_expiry, 1);
+    assert_eq!(sample.value_bytes, 6);
+
+    // The sample is bounded
+    assert_eq!(sample_keys(&store, 1).keys, 1);
+}
+
+#[test]
+fn test_memory_doctor_report_warnings() {
+    let sample = KeySample {
+        keys: 10,
+        with_expiry: 5,
+        value_bytes: 1000,
+    };
+
+    let healthy = memory_doctor_report(1024, 0, "allkeys-lru", 100, &sample);
+    assert!(healthy.contains("~50% (~50 keys"));
+    assert!(healthy.contains("Average value size: 100B"));
+    assert!(healthy.contains("No issues detected."));
+
+    // Tiny max_memory with noeviction, nearly full
+    let report = memory_doctor_report(1000, 1024, "noeviction", 100, &sample);
+    assert!(report.contains("(97%)"));
+    assert_eq!(report.matches("WARNING").count(), 3);
+    assert!(report.contains("noeviction"));
+}
+
+// ==================== Snapshot Tests ====================
+
+#[test]
+fn test_snapshot_roundtrip() {
+    let store = ShardedStore::new(4);
+    let now = 1000;
+    store.set(Bytes::from("str"), Bytes::from("value"), None, now);
+    store.set(Bytes::from("ttl"), Bytes::from("v"), Some(100), now);
+    store.set(Bytes::from("gone"), Bytes::from("v"), Some(1), now - 10);
+    store
+        .push(&Bytes::from("list"), &[Bytes::from("a"), Bytes::from("b")], false, now)
+        .unwrap();
+    store
+        .zadd(&Bytes::from("zset"), &[(1.5, Bytes::from("m")), (-2.0, Bytes::from("n"))], now)
+        .unwrap();
+    store.sadd(&Bytes::from("set"), &[Bytes::from("x"), Bytes::from("y")], now).unwrap();
+
+    let mut buf = Vec::new();
+    assert_eq!(write_snapshot(std::slice::from_ref(&store), &mut buf, now).unwrap(), 5);
+
+    let restored = ShardedStore::new(8);
+    assert_eq!(
+        read_snapshot(std::slice::from_ref(&restored), &mut buf.as_slice(), now).unwrap(),
+        5
+    );
+    assert_eq!(restored.get(b"str", now), Some(Bytes::from("value")));
+    assert!(restored.get(b"gone", now).is_none());
+    assert_eq!(
+        restored.range(b"list", 0, -1, now).unwrap(),
+        vec![Bytes::from("a"), Bytes::from("b")]
+    );
+    assert_eq!(
+        restored.with_zset(b"zset", now, |z| z.range(0, -1)).unwrap(),
+        Some(vec![(Bytes::from("n"), -2.0), (Bytes::from("m"), 1.5)])
+    );
+    let set_keys = args(&["set", "set"]);
+    assert_eq!(restored.inter_card(&set_keys, false, 0, now).unwrap(), 2);
+
+    // TTLs survive the round trip and still expire
+    assert!(restored.get(b"ttl", now + 99).is_some());
+    assert!(restored.get(b"ttl", now + 100).is_none());
+}
+
+#[test]
+fn test_snapshot_empty_and_invalid() {
+    let dbs = [ShardedStore::new(4)];
+    let mut buf = Vec::new();
+    assert_eq!(write_snapshot(&dbs, &mut buf, 0).unwrap(), 0);
+    assert_eq!(read_snapshot(&dbs, &mut buf.as_slice(), 0).unwrap(), 0);
+
+    assert!(read_snapshot(&dbs, &mut b"not a snapshot".as_slice(), 0).is_err());
+    // Truncated file
+    assert!(read_snapshot(&dbs, &mut &buf[..4], 0).is_err());
+}
+
+#[test]
+fn test_snapshot_keeps_databases_apart() {
+    let dbs = [ShardedStore::new(4), ShardedStore::new(4), ShardedStore::new(4)];
+    dbs[0].set(Bytes::from("k"), Bytes::from("zero"), None, 0);
+    dbs[2].set(Bytes::from("k"), Bytes::from("two"), None, 0);
+
+    let mut buf = Vec::new();
+    assert_eq!(write_snapshot(&dbs, &mut buf, 0).unwrap(), 2);
+
+    let restored = [ShardedStore::new(4), ShardedStore::new(4), ShardedStore::new(4)];
+    assert_eq!(read_snapshot(&restored, &mut buf.as_slice(), 0).unwrap(), 2);
+    assert_eq!(restored[0].get(b"k", 0), Some(Bytes::from("zero")));
+    assert!(restored[1].is_empty());
+    assert_eq!(restored[2].get(b"k", 0), Some(Bytes::from("two")));
+
+    // A server configured with fewer databases refuses the file
+    assert!(read_snapshot(&restored[..2], &mut buf.as_slice(), 0).is_err());
+}
+
+#[test]
+fn test_snapshot_during_writes_captures_one_moment_per_shard() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // One shard, rewritten key by key in rounds: a copy taken at one moment
+    // sees a prefix of the keys at round r and the rest at round r - 1
+    const KEYS: usize = 2000;
+    let store = ShardedStore::new(1);
+    for i in 0..KEYS {
+        store.set(Bytes::from(format!("k{}", i)), Bytes::from("0"), None, 0);
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let store = store.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut round = 0u64;
+            while !stop.load(Ordering::Relaxed) {
+                round += 1;
+                for i in 0..KEYS {
+                    store.set(Bytes::from(format!("k{}", i)), Bytes::from(round.to_string()), None, 0);
+                }
+            }
+            round
+        })
+    };
+
+    for _ in 0..20 {
+        let mut buf = Vec::new();
+        assert_eq!(write_snapshot(std::slice::from_ref(&store), &mut buf, 0).unwrap(), KEYS);
+
+        let restored = ShardedStore::new(1);
+        assert_eq!(read_snapshot(std::slice::from_ref(&restored), &mut buf.as_slice(), 0).unwrap(), KEYS);
+        let rounds: Vec<u64> = (0..KEYS)
+            .map(|i| {
+                let value = restored.get(format!("k{}", i).as_bytes(), 0).unwrap();
+                std::str::from_utf8(&value).unwrap().parse().unwrap()
+            })
+            .collect();
+        assert!(rounds.windows(2).all(|pair| pair[0] == pair[1] || pair[0] == pair[1] + 1), "{:?}", rounds);
+        assert!(rounds[0] <= rounds[KEYS - 1] + 1);
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    assert!(writer.join().unwrap() > 0);
+}
+
+#[test]
+fn test_save_points_parse_and_trigger() {
+    let points = parse_save_points("3600 1  300 100\n60 10000").unwrap();
+    assert_eq!(points.len(), 3);
+    assert_eq!(points[1], SavePoint { seconds: 300, changes: 100 });
+    assert_eq!(parse_save_points(""), Ok(Vec::new()));
+    assert!(parse_save_points("3600").is_err());
+    assert!(parse_save_points("3600 one").is_err());
+
+    assert!(!save_due(&points, 0, 100_000));
+    assert!(!save_due(&points, 99, 3599));
+    assert!(save_due(&points, 100, 300));
+    assert!(save_due(&points, 1, 3600));
+    assert!(save_due(&points, 10_000, 60));
+    assert!(!save_due(&[], 10_000, 100_000));
+
+    let mut config = Config::default();
+    config.persistence.save = "60 1".to_string();
+    assert!(config.validate().is_err(), "save points without a snapshot_path");
+    config.persistence.snapshot_path = "dump.rdst".to_string();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.save_points(), vec![SavePoint { seconds: 60, changes: 1 }]);
+}
+
+#[test]
+fn test_paced_snapshot_leaves_store_writable() {
+    use std::sync::atomic::Ordering;
+
+    let dbs = vec![create_test_store()];
+    for i in 0..20 {
+        dbs[0].set(Bytes::from(format!("k{}", i)), Bytes::from("v"), None, 0);
+    }
+
+    // 20 keys at 25ms each: the save is still running while the writes below land
+    let saver = {
+        let dbs: Vec<ShardedStore> = dbs.iter().map(ShardedStore::clone).collect();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            write_snapshot_paced(&dbs, &mut buf, 0, Duration::from_millis(25)).unwrap()
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    dbs[0].set(Bytes::from("during"), Bytes::from("save"), None, 0);
+    assert_eq!(dbs[0].get(b"during", 0), Some(Bytes::from("save")));
+    assert!(!saver.is_finished());
+    assert!(saver.join().unwrap() >= 20);
+
+    let mut state = ConnectionState::new("127.0.0.1:50021".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+    assert_eq!(run(&["DEBUG", "SET-SAVE-DELAY", "1000"]), Reply::ok());
+    assert_eq!(SAVE_KEY_DELAY_US.load(Ordering::Relaxed), 1000);
+    assert_eq!(run(&["DEBUG", "SET-SAVE-DELAY", "0"]), Reply::ok());
+    assert_eq!(
+        run(&["DEBUG", "SLEEP-BEFORE-REPLY", "-1"]),
+        Reply::error(b"value is not a valid float")
+    );
+    assert_eq!(run(&["DEBUG", "SLEEP-BEFORE-REPLY", "0.25"]), Reply::ok());
+    assert!(matches!(
+        state.deferred,
+        Some(DeferredReply::Sleep(delay)) if delay == Duration::from_millis(250)
+    ));
+}
+
+// ==================== TLS Tests ====================
+
+#[test]
+fn test_parse_private_key_accepts_pkcs8_rsa_and_ec() {
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+
+    // Only the PEM framing is read here, so any base64 body will do
+    let pem = |label: &str| format!("-----BEGIN {0}-----\nAQID\n-----END {0}-----\n", label);
+    let cert = pem("CERTIFICATE");
+
+    let key = parse_private_key(pem("PRIVATE KEY").as_bytes()).unwrap();
+    assert!(matches!(key, PrivateKeyDer::Pkcs8(_)));
+    let key = parse_private_key(pem("RSA PRIVATE KEY").as_bytes()).unwrap();
+    assert!(matches!(key, PrivateKeyDer::Pkcs1(_)));
+    // A key bundled after a certificate chain is still found
+    let bundle = format!("{}{}{}", cert, cert, pem("EC PRIVATE KEY"));
+    let key = parse_private_key(bundle.as_bytes()).unwrap();
+    assert!(matches!(key, PrivateKeyDer::Sec1(_)));
+    assert_eq!(key.secret_der(), &[1, 2, 3]);
+
+    assert!(parse_private_key(cert.as_bytes()).is_err());
+}
+
+#[test]
+fn test_tls_config_sets_alpn_and_sni_certificates() {
+    let mut security = SecurityConfig {
+        tls_enabled: true,
+        tls_cert_path: "tests/certs/server-cert.pem".to_string(),
+        tls_key_path: "tests/certs/server-key.pem".to_string(),
+        tls_alpn_protocols: vec!["redis".to_string(), "resp3".to_string()],
+        ..SecurityConfig::default()
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let tls = runtime.block_on(load_tls_config(&security)).unwrap();
+    assert_eq!(tls.alpn_protocols, vec![b"redis".to_vec(), b"resp3".to_vec()]);
+
+    security.tls_sni = vec![TlsSniConfig {
+        server_name: "Cache.Example.com".to_string(),
+        cert_path: "tests/certs/server-cert.pem".to_string(),
+        key_path: "tests/certs/server-key.pem".to_string(),
+    }];
+    assert!(runtime.block_on(load_tls_config(&security)).is_ok());
+    security.tls_sni[0].cert_path = "tests/certs/missing.pem".to_string();
+    assert!(runtime.block_on(load_tls_config(&security)).is_err());
+
+    let mut config = Config::default();
+    config.security.tls_alpn_protocols = vec![String::new()];
+    assert!(config.validate().is_err());
+    config.security.tls_alpn_protocols.clear();
+    config.security.tls_sni = security.tls_sni;
+    config.security.tls_sni[0].server_name.clear();
+    assert!(config.validate().is_err());
+}
+
+// ==================== Health Check Tests ====================
+
+#[test]
+fn test_readiness_reasons() {
+    let lru = EvictionPolicy::AllKeysLru;
+    let noeviction = EvictionPolicy::NoEviction;
+    assert_eq!(not_ready_reason(false, false, 0, 0, lru), None);
+    assert_eq!(not_ready_reason(true, false, 0, 0, lru), Some("loading"));
+    assert_eq!(not_ready_reason(false, true, 0, 0, lru), Some("shutting_down"));
+
+    // Full only matters when writes are refused rather than evicting
+    assert_eq!(not_ready_reason(false, false, 100, 100, lru), None);
+    assert_eq!(not_ready_reason(false, false, 99, 100, noeviction), None);
+    assert_eq!(not_ready_reason(false, false, 100, 100, noeviction), Some("out_of_memory"));
+    assert_eq!(not_ready_reason(false, false, 100, 0, noeviction), None);
+}
+
+#[test]
+fn test_latency_monitor_merges_and_bounds_spikes() {
+    let mut monitor = LatencyMonitor::default();
+    monitor.record("command", 100, 5);
+    monitor.record("command", 100, 9); // Same second: one sample, the slowest
+    monitor.record("command", 100, 2);
+    monitor.record("fast-command", 101, 3);
+    assert_eq!(monitor.history(b"command"), vec![LatencySample { time: 100, millis: 9 }]);
+
+    for time in 0..LATENCY_HISTORY_LEN as u64 + 10 {
+        monitor.record("command", 200 + time, 1);
+    }
+    let history = monitor.history(b"command");
+    assert_eq!(history.len(), LATENCY_HISTORY_LEN);
+    assert_eq!(history[0].time, 210);
+    // The all-time max outlives the samples that set it
+    assert_eq!(monitor.events["command"].max, 9);
+
+    let report = latency_doctor_report(&monitor, 1);
+    assert!(report.contains("command: 160 latency spikes"), "{}", report);
+    assert!(report.contains("fast-command: 1 latency spikes"), "{}", report);
+    assert!(report.contains("host rather than the workload"), "{}", report);
+    assert!(latency_doctor_report(&monitor, 0).contains("disabled"));
+
+    assert_eq!(monitor.reset(&args(&["fast-command", "missing"])), 1);
+    assert!(monitor.history(b"fast-command").is_empty());
+    assert_eq!(monitor.reset(&[]), 1);
+    assert!(latency_doctor_report(&monitor, 1).starts_with("No latency spikes of 1ms"));
+}
+
+#[test]
+fn test_latency_command_replies() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50033".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+
+    // Nothing else records while latency_monitor_threshold is 0
+    LATENCY_MONITOR.lock().record("command", 1_700_000_000, 12);
+    LATENCY_MONITOR.lock().record("command", 1_700_000_005, 30);
+    assert_eq!(
+        run(&["LATENCY", "LATEST"]),
+        Reply::Array(vec![Reply::Array(vec![
+            Reply::bulk(b"command"),
+            Reply::Integer(1_700_000_005),
+            Reply::Integer(30),
+            Reply::Integer(30),
+        ])])
+    );
+    assert_eq!(
+        run(&["LATENCY", "HISTORY", "command"]),
+        Reply::Array(vec![
+            Reply::Array(vec![Reply::Integer(1_700_000_000), Reply::Integer(12)]),
+            Reply::Array(vec![Reply::Integer(1_700_000_005), Reply::Integer(30)]),
+        ])
+    );
+    assert_eq!(run(&["LATENCY", "HISTORY", "fast-command"]), Reply::Array(vec![]));
+    assert!(matches!(run(&["LATENCY", "DOCTOR"]), Reply::BulkString(_)));
+    assert_eq!(run(&["LATENCY", "RESET"]), Reply::Integer(1));
+    assert_eq!(run(&["LATENCY", "LATEST"]), Reply::Array(vec![]));
+    assert!(matches!(run(&["LATENCY"]), Reply::Error(_)));
+}
+
+#[test]
+fn test_command_latency_metrics_render() {
+    let histograms: Vec<LatencyHistogram> =
+        (0..=COMMAND_TABLE.len()).map(|_| LatencyHistogram::default()).collect();
+    let get = command_metric_index(b"GET");
+    histograms[get].record(Duration::from_micros(5));
+    histograms[get].record(Duration::from_micros(700));
+    histograms[get].record(Duration::from_secs(1));
+    histograms[command_metric_index(b"nosuchcommand")].record(Duration::from_micros(20));
+
+    let text = render_metrics(&histograms);
+    assert!(text.contains("redistill_command_duration_seconds_bucket{cmd=\"get\",le=\"0.00001\"} 1\n"));
+    assert!(text.contains("redistill_command_duration_seconds_bucket{cmd=\"get\",le=\"0.001\"} 2\n"));
+    assert!(text.contains("redistill_command_duration_seconds_bucket{cmd=\"get\",le=\"+Inf\"} 3\n"));
+    assert!(text.contains("redistill_command_duration_seconds_sum{cmd=\"get\"} 1.000705\n"));
+    assert!(text.contains("redistill_command_duration_seconds_count{cmd=\"other\"} 1\n"));
+    // Commands that never ran get no series
+    assert!(!text.contains("cmd=\"set\""));
+    assert!(text.ends_with("# EOF\n"));
+}
+
+#[test]
+fn test_hot_keys_keep_frequent_keys_in_a_full_table() {
+    let mut hot = HotKeys::new(8);
+    let (a, b) = (Bytes::from("hot:a"), Bytes::from("hot:b"));
+    for i in 0..1000 {
+        hot.record(&a);
+        if i % 2 == 0 {
+            hot.record(&b);
+        }
+        // A stream of keys seen once each, far more than the table holds
+        hot.record(&Bytes::from(format!("cold:{}", i)));
+    }
+    assert_eq!(hot.counts.len(), 8);
+    let top = hot.top(2);
+    assert_eq!(top, vec![(a, 1000), (b, 500)]);
+    assert_eq!(hot.top(100).len(), 8);
+
+    hot.clear();
+    assert!(hot.top(10).is_empty());
+
+    // Off unless server.track_hot_keys is set
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50027".to_string());
+    if !CONFIG.server.track_hot_keys {
+        assert!(matches!(execute(&dbs, &args(&["DEBUG", "HOTKEYS"]), &mut state, now()), Reply::Error(_)));
+    }
+}
+
+// ==================== Replication ID Tests ====================
+
+#[test]
+fn test_repl_id_is_40_hex_chars() {
+    let id = new_repl_id();
+    assert_eq!(id.len(), 40);
+    assert!(id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+    assert_ne!(new_repl_id(), id);
+}
+
+#[test]
+fn test_resp_encoded_len_matches_wire_format() {
+    // *3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n
+    assert_eq!(resp_encoded_len(&args(&["SET", "a", "1"])), 27);
+    let value = "x".repeat(100);
+    assert_eq!(resp_encoded_len(&args(&["SET", "key", &value])), 4 + 9 + 9 + 108);
+}
+
 // ==================== Configuration Tests ====================
 
 #[test]
@@ -311,18 +1159,274 @@ fn test_config_defaults() {
     assert_eq!(config.server.bind, "127.0.0.1");
     assert_eq!(config.server.port, 6379);
     assert_eq!(config.server.num_shards, 256);
+    assert_eq!(config.server.databases, 16);
     assert_eq!(config.server.batch_size, 16);
     assert_eq!(config.server.max_connections, 10000);
+    assert_eq!(config.server.max_command_args, 1_000_000);
+    assert_eq!(config.server.max_command_bytes, 536_870_912);
+    assert_eq!(config.server.keys_stream_threshold, 10_000);
+    assert_eq!(config.server.reply_flush_threshold, 8192);
+    assert_eq!(config.server.min_buffer_size, 1024);
+    assert_eq!(config.server.accept_rate, 0);
+    assert_eq!(config.server.accept_burst, 100);
+    assert_eq!(config.server.health_check_bind, "127.0.0.1");
     assert_eq!(config.memory.max_memory, 0);
     assert_eq!(config.memory.eviction_policy, "allkeys-lru");
+    assert_eq!(config.memory.max_memory_low_pct, 90);
+    assert!(!config.memory.expiry_index);
     assert_eq!(config.security.password, "");
-    assert_eq!(config.security.tls_enabled, false);
+    assert_eq!(config.security.auth_failure_delay_ms, 5);
+    assert!(!config.security.tls_enabled);
+}
+
+#[test]
+fn test_config_is_set_once() {
+    // Once read, CONFIG keeps serving the config it started with
+    let port = CONFIG.server.port;
+    let mut other = Config::default();
+    other.server.port = port.wrapping_add(1);
+    assert!(!set_config(other));
+    assert_eq!(CONFIG.server.port, port);
+}
+
+#[test]
+fn test_config_validate_and_warnings() {
+    let config = Config::default();
+    assert!(config.validate().is_ok());
+    assert!(config.warnings().is_empty());
+
+    let mut config = Config::default();
+    config.memory.eviction_policy = "volatile-lru".to_string();
+    assert!(config.validate().unwrap_err().to_string().contains("eviction_policy"));
+
+    let mut config = Config::default();
+    config.server.health_check_port = config.server.port;
+    assert!(config.validate().is_err());
+
+    let mut config = Config::default();
+    config.server.max_inline_len = 0;
+    assert!(config.validate().unwrap_err().to_string().contains("max_inline_len"));
+
+    // Port 0 binds an ephemeral port, and CONFIG GET reports the one bound
+    let mut config = Config::default();
+    config.server.port = 0;
+    assert!(config.validate().is_ok());
+    BOUND_PORT.store(40123, std::sync::atomic::Ordering::Relaxed);
+    assert!(config.parameters().contains(&("port", "40123".to_string())));
+    BOUND_PORT.store(0, std::sync::atomic::Ordering::Relaxed);
+
+    // Risky but workable combinations only warn
+    let mut config = Config::default();
+    config.memory.max_memory = 1 << 20;
+    config.memory.eviction_policy = "noeviction".to_string();
+    config.server.connection_rate_limit = 100;
+    config.server.max_connections = 0;
+    config.security.tls_cert_path = "cert.pem".to_string();
+    assert!(config.validate().is_ok());
+    let warnings = config.warnings();
+    assert_eq!(warnings.len(), 3);
+    assert!(warnings[0].contains("noeviction"));
+    assert!(warnings[1].contains("connection_rate_limit"));
+    assert!(warnings[2].contains("plaintext"));
+}
+
+#[test]
+fn test_config_rewrite_round_trips() {
+    let path = std::env::temp_dir().join(format!("redistill-rewrite-{}.toml", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "# old comment\n[server]\nport = 1\n").unwrap();
+
+    let mut config = Config::default();
+    config.server.port = 7000;
+    config.memory.eviction_policy = "allkeys-random".to_string();
+    config.rewrite(path).unwrap();
+
+    let contents = std::fs::read_to_string(path).unwrap();
+    assert!(contents.contains("[server]") && contents.contains("[memory]"));
+    let reloaded: Config = toml::from_str(&contents).unwrap();
+    assert_eq!(reloaded.server.port, 7000);
+    assert_eq!(reloaded.memory.eviction_policy, "allkeys-random");
+    assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_config_reload_applies_live_settings_and_lists_the_rest() {
+    let mut old = Config::default();
+    let mut new = old.clone();
+    new.server.num_shards *= 2;
+    new.memory.max_memory = 1024;
+    new.security.tls_sni.push(TlsSniConfig {
+        server_name: "a.example".to_string(),
+        cert_path: "a.pem".to_string(),
+        key_path: "a.key".to_string(),
+    });
+    assert_eq!(
+        changed_settings(&old, &new),
+        vec!["memory.max_memory", "security.tls_sni", "server.num_shards"]
+    );
+    old.server.num_shards = new.server.num_shards;
+    assert_eq!(changed_settings(&old, &new).len(), 2);
+
+    // Reloading what is running changes nothing. Only the log level is
+    // exercised live, since other tests depend on the rest.
+    let dbs = vec![create_test_store()];
+    let mut same = live_config();
+    assert_eq!(apply_config_reload(&dbs, &same), ConfigReload::default());
+
+    let level = same.logging.level.clone();
+    same.logging.level = "trace".to_string();
+    same.server.bind = "10.1.2.3".to_string();
+    let report = apply_config_reload(&dbs, &same);
+    assert_eq!(report.applied, vec![format!("logging.level: {} -> trace", level)]);
+    assert_eq!(report.restart_required, vec!["server.bind"]);
+    assert!(log_enabled("trace"));
+
+    same.logging.level = level;
+    apply_config_reload(&dbs, &same);
+    assert!(!log_enabled("trace"));
+}
+
+#[test]
+fn test_config_env_overrides_any_setting() {
+    let vars = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    };
+
+    let mut config = Config::default();
+    config
+        .apply_env(vars(&[
+            ("REDISTILL_SERVER_NUM_SHARDS", "64"),
+            ("REDISTILL_MEMORY_MAXMEMORY", "1048576"),
+            ("REDISTILL_MEMORY_EVICTION_POLICY", "allkeys-random"),
+            ("REDISTILL_PERFORMANCE_TCP_NODELAY", "no"),
+            ("REDISTILL_SECURITY_TLS_ALPN_PROTOCOLS", r#"["h2", "http/1.1"]"#),
+            ("REDISTILL_CONFIG", "ignored.toml"),
+            ("REDISTILL_GIT_SHA", "abc123"),
+            ("REDIS_PORT", "1"),
+        ]))
+        .unwrap();
+    assert_eq!(config.server.num_shards, 64);
+    assert_eq!(config.memory.max_memory, 1048576);
+    assert_eq!(config.memory.eviction_policy, "allkeys-random");
+    assert!(!config.performance.tcp_nodelay);
+    assert_eq!(config.security.tls_alpn_protocols, vec!["h2", "http/1.1"]);
+    assert_eq!(config.server.port, Config::default().server.port);
+
+    for bad in [
+        ("REDISTILL_SERVER_NO_SUCH_THING", "1"),
+        ("REDISTILL_SERVER_PORT", "lots"),
+        ("REDISTILL_SERVER_PORT", "70000"),
+        ("REDISTILL_PERFORMANCE_TCP_NODELAY", "maybe"),
+    ] {
+        let mut config = Config::default();
+        assert!(config.apply_env(vars(&[bad])).is_err(), "{:?} was accepted", bad);
+    }
+}
+
+#[test]
+fn test_config_parameters_map_settings() {
+    let mut config = Config::default();
+    config.memory.max_memory = 1024;
+    config.persistence.snapshot_path = "/var/lib/redistill/dump.rdst".to_string();
+    let parameters = config.parameters();
+    let get = |name: &str| {
+        parameters.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    };
+
+    assert_eq!(get("maxmemory"), Some("1024"));
+    assert_eq!(get("maxmemory-policy"), Some("allkeys-lru"));
+    assert_eq!(get("dir"), Some("/var/lib/redistill"));
+    assert_eq!(get("dbfilename"), Some("dump.rdst"));
+    assert_eq!(get("appendonly"), Some("no"));
+    assert_eq!(get("save"), Some(""));
+    assert_eq!(get("proto-max-inline-len"), Some("65536"));
+    assert_eq!(get("nosuch"), None);
+}
+
+#[test]
+fn test_config_get_matches_patterns() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50008".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    assert_eq!(
+        run(&["CONFIG", "GET", "APPENDONLY"]),
+        Reply::bulk_array(&args(&["appendonly", "no"]))
+    );
+    let Reply::Array(pairs) = run(&["CONFIG", "GET", "maxmemory*", "save"]) else {
+        panic!("CONFIG GET should reply with an array");
+    };
+    assert_eq!(pairs.len(), 10);
+    assert_eq!(run(&["CONFIG", "GET", "nosuch"]), Reply::Array(Vec::new()));
+    assert!(matches!(run(&["CONFIG", "GET", "*"]), Reply::Array(all) if all.len() > 40));
+}
+
+#[test]
+fn test_accept_throttle_paces_after_burst() {
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut throttle = AcceptThrottle::new(10, 2, start);
+    assert_eq!(throttle.acquire(start), Duration::ZERO);
+    assert_eq!(throttle.acquire(start), Duration::ZERO);
+
+    // Burst used up: each further connection waits one more 1/rate slot
+    let wait = throttle.acquire(start);
+    assert!((wait.as_secs_f64() - 0.1).abs() < 1e-9);
+    let wait = throttle.acquire(start);
+    assert!((wait.as_secs_f64() - 0.2).abs() < 1e-9);
+
+    // A quiet second refills the bucket, but never beyond the burst
+    let later = start + Duration::from_secs(10);
+    assert_eq!(throttle.acquire(later), Duration::ZERO);
+    assert_eq!(throttle.acquire(later), Duration::ZERO);
+    assert!(throttle.acquire(later) > Duration::ZERO);
+}
+
+#[test]
+fn test_connection_permits_refuse_when_full() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+    assert!(connection_permits(0).is_none());
+    let permits = connection_permits(1).unwrap();
+    let wait = Duration::from_millis(20);
+
+    runtime.block_on(async {
+        let held = acquire_connection_permit(&permits, wait).await;
+        assert!(held.is_some());
+        assert!(acquire_connection_permit(&permits, wait).await.is_none());
+
+        // A slot that frees up while the accept loop waits is taken
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            drop(held);
+        });
+        assert!(acquire_connection_permit(&permits, Duration::from_secs(5)).await.is_some());
+        release.await.unwrap();
+    });
+}
+
+#[test]
+fn test_client_info_tracks_commands() {
+    let client = ClientInfo::new(7, "127.0.0.1:50000".to_string(), 1000);
+    assert_eq!(
+        client.info_line(1000),
+        "id=7 addr=127.0.0.1:50000 age=0 idle=0 cmd=NULL tot-cmds=0"
+    );
+
+    client.record_command(&Bytes::from("SET"), 1002);
+    client.record_command(&Bytes::from("KEYS"), 1005);
+    assert_eq!(
+        client.info_line(1010),
+        "id=7 addr=127.0.0.1:50000 age=10 idle=5 cmd=keys tot-cmds=2"
+    );
 }
 
 #[test]
 fn test_connection_state_default() {
-    let state = ConnectionState::new();
-    // In the lib.rs version, authenticated defaults to true for testing
+    let state = ConnectionState::new("127.0.0.1:50000".to_string());
+    // No password is configured, so connections start authenticated
     assert!(state.authenticated);
 }
 
@@ -367,7 +1471,7 @@ fn test_delete_nonexistent_keys() {
 
     let keys = vec![Bytes::from("nonexistent1"), Bytes::from("nonexistent2")];
 
-    let count = store.delete(&keys);
+    let (count, _) = store.delete(&keys);
     assert_eq!(count, 0);
 }
 
@@ -387,7 +1491,7 @@ fn test_mixed_delete() {
         Bytes::from("nonexistent2"),
     ];
 
-    let count = store.delete(&keys);
+    let (count, _) = store.delete(&keys);
     assert_eq!(count, 2);
 }
 
@@ -410,7 +1514,7 @@ fn test_stress_delete() {
         .map(|i| Bytes::from(format!("key{}", i)))
         .collect();
 
-    let count = store.delete(&keys);
+    let (count, _) = store.delete(&keys);
     assert_eq!(count, 1000);
     assert_eq!(store.len(), 0);
 }
@@ -531,7 +1635,7 @@ fn test_entry_clone() {
     use std::sync::atomic::{AtomicU32, Ordering};
 
     let entry1 = Entry {
-        value: Bytes::from("test"),
+        value: Value::String(Bytes::from("test")),
         expiry: Some(12345),
         last_accessed: AtomicU32::new(100),
     };
@@ -546,17 +1650,382 @@ fn test_entry_clone() {
     );
 }
 
-// ==================== INCR/DECR Support Tests ====================
-// These tests verify the underlying store functionality used by INCR/DECR commands
+#[test]
+fn test_entry_debug_object_reports_expiry() {
+    let entry = Entry::new(Value::String(Bytes::from("12")), Some(100));
+    let live = entry.debug_object(90, 30);
+    assert!(live.contains("encoding:int"));
+    assert!(live.contains("lru_seconds_idle:30"));
+    assert!(live.ends_with("ttl:10 expired:0"));
+
+    // Past its TTL but still stored: visible as logically expired
+    assert!(entry.debug_object(100, 30).ends_with("ttl:0 expired:1"));
+
+    let entry = Entry::new(Value::String(Bytes::from("x".repeat(45))), None);
+    assert!(entry.debug_object(0, 0).contains("encoding:raw"));
+    assert!(entry.debug_object(0, 0).ends_with("ttl:-1 expired:0"));
+}
+
+// ==================== Value Type Tests ====================
 
 #[test]
-fn test_integer_value_storage() {
+fn test_get_on_list_returns_wrongtype() {
     let store = create_test_store();
-    let key = Bytes::from("counter");
-    
-    // Store integer as string (like Redis does)
-    store.set(key.clone(), Bytes::from("42"), None, now());
-    
+    let key = Bytes::from("mylist");
+
+    let len = store.push(&key, &[Bytes::from("a"), Bytes::from("b")], true, now());
+    assert_eq!(len, Ok(2));
+
+    // GET must reject the list instead of returning a garbled value
+    assert_eq!(store.get_string(&key, now()), Err(WrongTypeError));
+    assert!(WrongTypeError::MESSAGE.starts_with(b"WRONGTYPE "));
+
+    // MGET-style reads treat non-string keys as missing
+    assert!(store.get(&key, now()).is_none());
+}
+
+#[test]
+fn test_push_on_string_returns_wrongtype() {
+    let store = create_test_store();
+    let key = Bytes::from("str");
+
+    store.set(key.clone(), Bytes::from("value"), None, now());
+
+    assert_eq!(
+        store.push(&key, &[Bytes::from("x")], true, now()),
+        Err(CollectionError::WrongType)
+    );
+    assert_eq!(store.get_string(&key, now()), Ok(Some(Bytes::from("value"))));
+}
+
+#[test]
+fn test_set_overwrites_list() {
+    let store = create_test_store();
+    let key = Bytes::from("key");
+
+    store.push(&key, &[Bytes::from("a")], false, now()).unwrap();
+    store.set(key.clone(), Bytes::from("now a string"), None, now());
+
+    assert_eq!(
+        store.get_string(&key, now()),
+        Ok(Some(Bytes::from("now a string")))
+    );
+}
+
+#[test]
+fn test_list_push_order_and_range() {
+    let store = create_test_store();
+    let key = Bytes::from("list");
+
+    store.push(&key, &[Bytes::from("b"), Bytes::from("c")], false, now()).unwrap();
+    store.push(&key, &[Bytes::from("a")], true, now()).unwrap();
+
+    let all = store.range(&key, 0, -1, now()).unwrap();
+    assert_eq!(all, vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")]);
+
+    let tail = store.range(&key, -2, 100, now()).unwrap();
+    assert_eq!(tail, vec![Bytes::from("b"), Bytes::from("c")]);
+
+    assert!(store.range(&key, 5, 10, now()).unwrap().is_empty());
+    assert!(store.range(&Bytes::from("missing"), 0, -1, now()).unwrap().is_empty());
+}
+
+#[test]
+fn test_list_converts_between_listpack_and_quicklist() {
+    // The default list_max_listpack_size of -2 packs up to 8KB
+    assert_eq!(CONFIG.memory.list_max_listpack_size, -2);
+    let mut list = ListValue::default();
+    for element in ["b", "c", "d"] {
+        list.push(Bytes::from(element), false);
+    }
+    list.push(Bytes::from("a"), true);
+    assert_eq!(list.encoding(), "listpack");
+    assert_eq!(list.range(1, 2), args(&["b", "c"]));
+    assert_eq!(list.mem_size(), 4);
+
+    // One element past 8KB turns it into a quicklist with the same contents
+    let big = Bytes::from(vec![b'x'; 8192]);
+    list.push(big.clone(), false);
+    assert_eq!(list.encoding(), "quicklist");
+    assert_eq!(list.len(), 5);
+    assert_eq!(list.mem_size(), 4 + 8192);
+
+    // Popping back under half the limit packs it again
+    assert_eq!(list.pop(1, false), vec![big]);
+    assert_eq!(list.encoding(), "listpack");
+    assert_eq!(list.pop(2, false), args(&["d", "c"]));
+    assert_eq!(list.pop(5, true), args(&["a", "b"]));
+    assert!(list.is_empty());
+
+    // OBJECT ENCODING follows the same rule
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50028".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+    run(&["RPUSH", "l", "1", "2", "3"]);
+    assert_eq!(run(&["OBJECT", "ENCODING", "l"]), Reply::bulk(b"listpack"));
+    let big = "y".repeat(9000);
+    run(&["LPUSH", "l", &big]);
+    assert_eq!(run(&["OBJECT", "ENCODING", "l"]), Reply::bulk(b"quicklist"));
+    assert_eq!(run(&["LRANGE", "l", "1", "-1"]), Reply::bulk_array(&args(&["1", "2", "3"])));
+}
+
+#[test]
+fn test_value_type_names() {
+    assert_eq!(Value::String(Bytes::from("x")).type_name(), "string");
+    assert_eq!(Value::List(Default::default()).type_name(), "list");
+    assert_eq!(Value::Set(Default::default()).type_name(), "set");
+    assert_eq!(Value::ZSet(Default::default()).type_name(), "zset");
+}
+
+#[test]
+fn test_set_add_remove_deletes_empty_set() {
+    let store = create_test_store();
+    let key = Bytes::from("set");
+    assert_eq!(store.sadd(&key, &args(&["a", "b", "a"]), now()).unwrap(), 2);
+    assert_eq!(store.sadd(&key, &args(&["b", "c"]), now()).unwrap(), 1);
+
+    assert_eq!(store.srem(&key, &args(&["a", "missing"]), now()).unwrap(), 1);
+    assert_eq!(store.srem(&key, &args(&["b", "c"]), now()).unwrap(), 2);
+    assert_eq!(store.exists(std::slice::from_ref(&key), now()), 0);
+
+    store.set(Bytes::from("str"), Bytes::from("v"), None, now());
+    assert!(store.sadd(&Bytes::from("str"), &args(&["a"]), now()).is_err());
+}
+
+#[test]
+fn test_set_encoding_converts_from_intset() {
+    let mut set = SetValue::default();
+    assert!(set.insert(Bytes::from("-12")));
+    assert!(set.insert(Bytes::from("7")));
+    assert!(!set.insert(Bytes::from("7")));
+    assert_eq!(set.encoding(), "intset");
+    assert!(set.contains(b"-12"));
+    assert!(!set.contains(b"07"));
+    assert_eq!(set.mem_size(), 4);
+
+    // A non-canonical integer is not an intset member
+    assert!(set.insert(Bytes::from("07")));
+    assert_eq!(set.encoding(), "hashtable");
+    assert!(set.contains(b"7") && set.contains(b"07"));
+    assert_eq!(set.mem_size(), 6);
+    // Removing it does not convert back
+    assert!(set.remove(b"07"));
+    assert_eq!(set.encoding(), "hashtable");
+
+    let mut set = SetValue::default();
+    let limit = CONFIG.memory.set_max_intset_entries as i64;
+    for n in 0..limit {
+        set.insert(Bytes::from(n.to_string()));
+    }
+    assert_eq!(set.encoding(), "intset");
+    set.insert(Bytes::from(limit.to_string()));
+    assert_eq!(set.encoding(), "hashtable");
+    assert_eq!(set.len(), limit as usize + 1);
+}
+
+#[test]
+fn test_object_encoding_reports_set_encoding() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50011".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+
+    run(&["SADD", "s", "1", "2", "3"]);
+    assert_eq!(run(&["OBJECT", "ENCODING", "s"]), Reply::bulk(b"intset"));
+    run(&["SADD", "s", "x"]);
+    assert_eq!(run(&["OBJECT", "ENCODING", "s"]), Reply::bulk(b"hashtable"));
+    assert_eq!(run(&["SISMEMBER", "s", "2"]), Reply::Integer(1));
+    assert_eq!(run(&["OBJECT", "ENCODING", "missing"]), Reply::Null);
+}
+
+#[test]
+fn test_intercard_with_limit() {
+    let store = create_test_store();
+    store.sadd(&Bytes::from("s1"), &args(&["a", "b", "c", "d"]), now()).unwrap();
+    store.sadd(&Bytes::from("s2"), &args(&["b", "c", "d", "e"]), now()).unwrap();
+    store.sadd(&Bytes::from("s3"), &args(&["c", "d", "x"]), now()).unwrap();
+
+    let keys = args(&["s1", "s2", "s3"]);
+    assert_eq!(store.inter_card(&keys, false, 0, now()).unwrap(), 2);
+    // LIMIT stops counting early; a limit above the result changes nothing
+    assert_eq!(store.inter_card(&keys, false, 1, now()).unwrap(), 1);
+    assert_eq!(store.inter_card(&keys, false, 10, now()).unwrap(), 2);
+    assert_eq!(store.inter_card(&args(&["s1"]), false, 3, now()).unwrap(), 3);
+    assert_eq!(store.inter_card(&args(&["s1", "missing"]), false, 0, now()).unwrap(), 0);
+
+    // ZINTERCARD accepts sorted sets and plain sets; SINTERCARD only sets
+    store
+        .zadd(&Bytes::from("z"), &[(1.0, Bytes::from("c")), (2.0, Bytes::from("q"))], now())
+        .unwrap();
+    assert_eq!(store.inter_card(&args(&["z", "s1"]), true, 0, now()).unwrap(), 1);
+    assert!(store.inter_card(&args(&["z", "s1"]), false, 0, now()).is_err());
+    // A wrong type is reported even when another key is missing
+    assert!(store.inter_card(&args(&["missing", "z"]), false, 0, now()).is_err());
+}
+
+#[test]
+fn test_list_pop_deletes_empty_list() {
+    let store = create_test_store();
+    let key = Bytes::from("list");
+    store
+        .push(&key, &[Bytes::from("a"), Bytes::from("b"), Bytes::from("c")], false, now())
+        .unwrap();
+
+    assert_eq!(store.pop(&key, 1, true, now()).unwrap(), vec![Bytes::from("a")]);
+    // Popping from the tail returns elements in pop order
+    assert_eq!(
+        store.pop(&key, 5, false, now()).unwrap(),
+        vec![Bytes::from("c"), Bytes::from("b")]
+    );
+    assert_eq!(store.exists(std::slice::from_ref(&key), now()), 0);
+    assert!(store.pop(&key, 1, true, now()).unwrap().is_empty());
+
+    store.set(Bytes::from("str"), Bytes::from("v"), None, now());
+    assert!(store.pop(b"str", 1, true, now()).is_err());
+}
+
+#[test]
+fn test_sorted_set_order_and_updates() {
+    let store = create_test_store();
+    let key = Bytes::from("zset");
+    let members = [
+        (2.0, Bytes::from("b")),
+        (1.0, Bytes::from("a")),
+        (f64::INFINITY, Bytes::from("top")),
+        (1.0, Bytes::from("aa")),
+    ];
+    assert_eq!(store.zadd(&key, &members, now()).unwrap(), 4);
+    // Updating a score is not a new member
+    assert_eq!(store.zadd(&key, &[(-1.0, Bytes::from("b"))], now()).unwrap(), 0);
+
+    let order: Vec<Bytes> = store
+        .with_zset(&key, now(), |z| z.range(0, -1))
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|(member, _)| member)
+        .collect();
+    // Equal scores are ordered by member
+    assert_eq!(order, vec![Bytes::from("b"), Bytes::from("a"), Bytes::from("aa"), Bytes::from("top")]);
+    assert_eq!(store.with_zset(&key, now(), |z| z.score(b"b")).unwrap(), Some(Some(-1.0)));
+    assert_eq!(store.with_zset(b"missing", now(), |z| z.len()).unwrap(), None);
+
+    store.set(Bytes::from("str"), Bytes::from("v"), None, now());
+    assert!(store.zadd(&Bytes::from("str"), &members, now()).is_err());
+}
+
+#[test]
+fn test_sorted_set_pop_min_and_max() {
+    let store = create_test_store();
+    let key = Bytes::from("zset");
+    let members = [(1.0, Bytes::from("one")), (2.0, Bytes::from("two")), (3.0, Bytes::from("three"))];
+    store.zadd(&key, &members, now()).unwrap();
+
+    assert_eq!(store.zpop(&key, 1, true, now()).unwrap(), vec![(Bytes::from("three"), 3.0)]);
+    assert_eq!(
+        store.zpop(&key, 10, false, now()).unwrap(),
+        vec![(Bytes::from("one"), 1.0), (Bytes::from("two"), 2.0)]
+    );
+    assert_eq!(store.exists(std::slice::from_ref(&key), now()), 0);
+}
+
+#[test]
+fn test_entry_limits_refuse_growth_past_the_cap() {
+    let limits = EntryLimits { list: 3, set: 2, zset: 2 };
+    let store = create_test_store().with_entry_limits(limits);
+    let too_many = |type_name| Err(CollectionError::TooManyEntries(type_name));
+
+    // Lists: filling to the cap works, one more is refused whole
+    let list = Bytes::from("list");
+    assert_eq!(store.push(&list, &args(&["a", "b"]), false, now()), Ok(2));
+    assert_eq!(store.push(&list, &args(&["c", "d"]), false, now()), too_many("list"));
+    assert_eq!(store.push(&list, &args(&["c"]), true, now()), Ok(3));
+    assert_eq!(store.push(&list, &args(&["d"]), false, now()), too_many("list"));
+    assert_eq!(store.range(&list, 0, -1, now()).unwrap().len(), 3);
+
+    // A refused push onto a missing key leaves no empty list behind
+    let fresh = Bytes::from("fresh");
+    assert_eq!(store.push(&fresh, &args(&["a", "b", "c", "d"]), false, now()), too_many("list"));
+    assert_eq!(store.exists(std::slice::from_ref(&fresh), now()), 0);
+
+    // Sets and sorted sets count only members that are new
+    let set = Bytes::from("set");
+    assert_eq!(store.sadd(&set, &args(&["a", "b"]), now()), Ok(2));
+    assert_eq!(store.sadd(&set, &args(&["a", "b", "b"]), now()), Ok(0));
+    assert_eq!(store.sadd(&set, &args(&["a", "c"]), now()), too_many("set"));
+
+    let zset = Bytes::from("zset");
+    let pairs = |names: &[&str]| names.iter().map(|n| (1.0, Bytes::from(n.to_string()))).collect::<Vec<_>>();
+    assert_eq!(store.zadd(&zset, &pairs(&["a", "b"]), now()), Ok(2));
+    assert_eq!(store.zadd(&zset, &pairs(&["a", "b"]), now()), Ok(0));
+    assert_eq!(store.zadd(&zset, &pairs(&["c"]), now()), too_many("zset"));
+    assert_eq!(store.zadd(&Bytes::from("z2"), &pairs(&["a", "b", "c"]), now()), too_many("zset"));
+    assert_eq!(store.exists(&args(&["z2"]), now()), 0);
+
+    // The reply names the type. SORT ... STORE writes a list, so it is
+    // held to the list cap whatever it sorted.
+    let dbs = vec![create_test_store().with_entry_limits(EntryLimits { list: 2, set: 3, zset: 2 })];
+    let mut state = ConnectionState::new("127.0.0.1:50034".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+    assert_eq!(run(&["RPUSH", "l", "1", "2", "3"]), Reply::error(b"list exceeds maximum allowed entries"));
+    assert_eq!(run(&["SADD", "s", "1", "2", "3", "4"]), Reply::error(b"set exceeds maximum allowed entries"));
+    assert_eq!(run(&["ZADD", "z", "1", "a", "2", "b", "3", "c"]), Reply::error(b"zset exceeds maximum allowed entries"));
+    assert_eq!(run(&["SADD", "s", "3", "1", "2"]), Reply::Integer(3));
+    assert_eq!(run(&["SORT", "s", "STORE", "sorted"]), Reply::error(b"list exceeds maximum allowed entries"));
+    assert_eq!(run(&["SORT", "s", "LIMIT", "0", "2", "STORE", "sorted"]), Reply::Integer(2));
+    assert_eq!(run(&["EXISTS", "l", "z"]), Reply::Integer(0));
+}
+
+#[test]
+fn test_sort_lists_and_sets() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50019".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+    let array = |items: &[&str]| Reply::bulk_array(&args(items));
+
+    run(&["RPUSH", "scores", "10", "2", "-1.5", "2", "3e1"]);
+    assert_eq!(run(&["SORT", "scores"]), array(&["-1.5", "2", "2", "10", "3e1"]));
+    assert_eq!(run(&["SORT", "scores", "DESC"]), array(&["3e1", "10", "2", "2", "-1.5"]));
+    assert_eq!(run(&["SORT", "scores", "ALPHA"]), array(&["-1.5", "10", "2", "2", "3e1"]));
+    assert_eq!(run(&["SORT", "scores", "LIMIT", "1", "2"]), array(&["2", "2"]));
+    assert_eq!(run(&["SORT", "scores", "LIMIT", "3", "-1", "DESC"]), array(&["2", "-1.5"]));
+    assert_eq!(run(&["SORT", "scores", "LIMIT", "9", "1"]), array(&[]));
+
+    // Non-numbers only sort with ALPHA
+    run(&["SADD", "names", "carol", "alice", "bob"]);
+    assert_eq!(
+        run(&["SORT", "names"]),
+        Reply::error(b"One or more scores can't be converted into double")
+    );
+    assert_eq!(run(&["sort", "names", "alpha", "desc"]), array(&["carol", "bob", "alice"]));
+    run(&["SADD", "ints", "3", "1", "2"]);
+    assert_eq!(run(&["SORT", "ints"]), array(&["1", "2", "3"]));
+
+    // STORE replaces the destination with a list
+    run(&["SET", "dest", "old"]);
+    assert_eq!(run(&["SORT", "names", "ALPHA", "LIMIT", "0", "2", "STORE", "dest"]), Reply::Integer(2));
+    assert_eq!(run(&["LRANGE", "dest", "0", "-1"]), array(&["alice", "bob"]));
+    assert_eq!(run(&["SORT", "missing", "STORE", "dest"]), Reply::Integer(0));
+    assert_eq!(run(&["EXISTS", "dest"]), Reply::Integer(0));
+
+    assert_eq!(run(&["SORT", "missing"]), array(&[]));
+    assert_eq!(run(&["SORT", "scores", "BY", "w_*"]), Reply::error(b"syntax error"));
+    assert_eq!(run(&["SORT", "scores", "LIMIT", "x", "1"]), Reply::error(b"value is not an integer or out of range"));
+    run(&["SET", "str", "1"]);
+    assert_eq!(run(&["SORT", "str"]), Reply::wrongtype());
+}
+
+// ==================== INCR/DECR Support Tests ====================
+// These tests verify the underlying store functionality used by INCR/DECR commands
+
+#[test]
+fn test_integer_value_storage() {
+    let store = create_test_store();
+    let key = Bytes::from("counter");
+    
+    // Store integer as string (like Redis does)
+    store.set(key.clone(), Bytes::from("42"), None, now());
+    
     let result = store.get(&key, now()).unwrap();
     assert_eq!(result.as_ref(), b"42");
     
@@ -595,7 +2064,7 @@ fn test_counter_with_ttl_preservation() {
     // Simulate increment (get, parse, increment, set with same TTL)
     let shard = &store.shards[store.hash(&key)];
     let existing_ttl = shard.get(key.as_ref()).and_then(|e| {
-        e.expiry.map(|exp| if exp > timestamp { exp - timestamp } else { 0 })
+        e.expiry.map(|exp| exp.saturating_sub(timestamp))
     });
     
     assert!(existing_ttl.is_some());
@@ -610,6 +2079,70 @@ fn test_counter_with_ttl_preservation() {
     assert_eq!(entry.expiry.unwrap(), timestamp + 60);
 }
 
+#[test]
+fn test_integer_strings_are_int_encoded() {
+    assert_eq!(Value::string(Bytes::from("42")), Value::Int(42));
+    assert_eq!(Value::string(Bytes::from("-9223372036854775807")), Value::Int(-9223372036854775807));
+    for raw in ["007", "+1", "-0", "", " 1", "1.0", "99999999999999999999", "abc"] {
+        assert_eq!(Value::string(Bytes::from(raw)), Value::String(Bytes::from(raw)), "{:?}", raw);
+    }
+
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50020".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    run(&["SET", "n", "123456"]);
+    assert_eq!(run(&["OBJECT", "ENCODING", "n"]), Reply::bulk(b"int"));
+    assert_eq!(run(&["GET", "n"]), Reply::bulk(b"123456"));
+    run(&["SET", "padded", "007"]);
+    assert_eq!(run(&["OBJECT", "ENCODING", "padded"]), Reply::bulk(b"embstr"));
+    assert_eq!(run(&["GET", "padded"]), Reply::bulk(b"007"));
+    assert_eq!(run(&["INCR", "padded"]), Reply::Integer(8));
+    assert_eq!(run(&["OBJECT", "ENCODING", "padded"]), Reply::bulk(b"int"));
+
+    // embstr up to EMBSTR_SIZE_LIMIT bytes, raw past it; int wins over both
+    let short = "x".repeat(EMBSTR_SIZE_LIMIT);
+    let long = "x".repeat(EMBSTR_SIZE_LIMIT + 1);
+    run(&["SET", "short", &short]);
+    run(&["SET", "long", &long]);
+    run(&["SET", "neg", "-9223372036854775807"]);
+    assert_eq!(EMBSTR_SIZE_LIMIT, 44);
+    assert_eq!(run(&["OBJECT", "ENCODING", "short"]), Reply::bulk(b"embstr"));
+    assert_eq!(run(&["OBJECT", "ENCODING", "long"]), Reply::bulk(b"raw"));
+    assert_eq!(run(&["OBJECT", "ENCODING", "neg"]), Reply::bulk(b"int"));
+
+    // INCR changes the integer in place, keeping the TTL
+    run(&["SET", "c", "10", "EX", "100"]);
+    assert_eq!(run(&["INCRBY", "c", "5"]), Reply::Integer(15));
+    assert_eq!(run(&["DECR", "c"]), Reply::Integer(14));
+    assert_eq!(run(&["DECRBY", "c", "20"]), Reply::Integer(-6));
+    assert_eq!(run(&["GET", "c"]), Reply::bulk(b"-6"));
+    assert_eq!(run(&["TTL", "c"]), Reply::Integer(100));
+    assert_eq!(
+        dbs[0].shards[dbs[0].hash(b"c")].get(b"c".as_ref()).unwrap().value,
+        Value::Int(-6)
+    );
+
+    // Missing keys start at 0; failures leave the key as it was
+    assert_eq!(run(&["INCR", "fresh"]), Reply::Integer(1));
+    run(&["SET", "max", "9223372036854775807"]);
+    assert_eq!(run(&["INCR", "max"]), Reply::error(b"increment would produce overflow"));
+    assert_eq!(run(&["GET", "max"]), Reply::bulk(b"9223372036854775807"));
+    run(&["SET", "word", "abc"]);
+    assert_eq!(run(&["INCR", "word"]), Reply::error(b"value is not an integer or out of range"));
+    run(&["RPUSH", "list", "1"]);
+    assert_eq!(run(&["INCR", "list"]), Reply::wrongtype());
+
+    // Snapshots store the decimal form and load it int-encoded again
+    let mut buf = Vec::new();
+    write_snapshot(&dbs, &mut buf, now).unwrap();
+    let restored = [create_test_store()];
+    read_snapshot(&restored, &mut buf.as_slice(), now).unwrap();
+    assert_eq!(restored[0].get(b"n", now), Some(Bytes::from("123456")));
+    assert_eq!(restored[0].shards[restored[0].hash(b"n")].get(b"n".as_ref()).unwrap().value, Value::Int(123456));
+}
+
 // ==================== MGET/MSET Support Tests ====================
 
 #[test]
@@ -628,7 +2161,7 @@ fn test_bulk_set_and_get() {
     }
     
     // Simulate MGET: get multiple keys
-    let keys = vec![
+    let keys = [
         Bytes::from("key1"),
         Bytes::from("key2"),
         Bytes::from("key3"),
@@ -681,6 +2214,54 @@ fn test_bulk_operations_atomicity() {
     assert_eq!(store.len(), 1000);
 }
 
+#[test]
+fn test_set_many_groups_by_shard_and_keeps_last_duplicate() {
+    let store = create_test_store();
+    store.set(Bytes::from("k0"), Bytes::from("old"), Some(100), now());
+
+    let pairs: Vec<(Bytes, Bytes)> = (0..50)
+        .map(|i| (Bytes::from(format!("k{}", i)), Bytes::from(format!("v{}", i))))
+        .chain([(Bytes::from("k7"), Bytes::from("last"))])
+        .collect();
+    let replaced = store.set_many(pairs);
+
+    // Only the pre-existing k0 and the first k7 were replaced
+    assert_eq!(replaced, entry_size(2, 3) + entry_size(2, 2));
+    assert_eq!(store.len(), 50);
+    assert_eq!(store.get(b"k7", now()), Some(Bytes::from("last")));
+    assert_eq!(store.get(b"k49", now()), Some(Bytes::from("v49")));
+    // MSET semantics: the old TTL is dropped
+    assert_eq!(store.get(b"k0", now()), Some(Bytes::from("v0")));
+    let shard = &store.shards[store.hash(b"k0")];
+    assert_eq!(shard.get(b"k0".as_slice()).unwrap().expiry, None);
+}
+
+#[test]
+fn test_mget_never_sees_half_an_mset_within_a_shard() {
+    // One shard, so every key of the batch lands together
+    let store = Arc::new(ShardedStore::new(1));
+    let keys = args(&["a", "b", "c", "d"]);
+    store.set_many(keys.iter().map(|k| (k.clone(), Bytes::from("0"))).collect());
+
+    let writer = {
+        let (store, keys) = (store.clone(), keys.clone());
+        thread::spawn(move || {
+            for round in 1..=2000 {
+                let value = Bytes::from(round.to_string());
+                store.set_many(keys.iter().map(|k| (k.clone(), value.clone())).collect());
+            }
+        })
+    };
+    let mut reads = 0;
+    while !writer.is_finished() || reads == 0 {
+        let values = store.get_many(&keys, now(), true);
+        assert!(values.iter().all(|v| v == &values[0]), "half-applied MSET: {:?}", values);
+        reads += 1;
+    }
+    writer.join().unwrap();
+    assert_eq!(store.get_many(&keys, now(), true), vec![Some(Bytes::from("2000")); 4]);
+}
+
 // ==================== SET NX/XX Support Tests ====================
 
 #[test]
@@ -703,7 +2284,7 @@ fn test_conditional_set_nx_behavior() {
     assert!(exists);
     
     // Simulate second NX attempt - should not overwrite
-    let should_set = !store.get(&key, now()).is_some();
+    let should_set = store.get(&key, now()).is_none();
     assert!(!should_set); // Should NOT set because key exists
     
     // Value should still be "first"
@@ -741,6 +2322,66 @@ fn test_conditional_set_xx_behavior() {
 
 // ==================== EXPIRE/TTL Support Tests ====================
 
+#[test]
+fn test_expire_conditions() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50014".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+    run(&["SET", "k", "v"]);
+
+    // No expiry yet: XX and GT fail, NX and LT apply
+    assert_eq!(run(&["EXPIRE", "k", "100", "XX"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIRE", "k", "100", "GT"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIRE", "k", "100", "nx"]), Reply::Integer(1));
+    assert_eq!(run(&["EXPIRE", "k", "200", "NX"]), Reply::Integer(0));
+
+    // A lease is never shortened by GT, only by LT
+    assert_eq!(run(&["EXPIRE", "k", "50", "GT"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIRE", "k", "300", "XX", "GT"]), Reply::Integer(1));
+    assert_eq!(run(&["EXPIRE", "k", "400", "LT"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIRE", "k", "30", "LT"]), Reply::Integer(1));
+    assert_eq!(run(&["TTL", "k"]), Reply::Integer(30));
+
+    // The condition also guards deletion by a non-positive TTL
+    assert_eq!(run(&["EXPIRE", "k", "0", "NX"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIRE", "k", "-1", "LT"]), Reply::Integer(1));
+    assert_eq!(run(&["EXISTS", "k"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIRE", "missing", "10", "LT"]), Reply::Integer(0));
+
+    assert_eq!(
+        run(&["EXPIRE", "k", "10", "NX", "GT"]),
+        Reply::error(b"NX and XX, GT or LT options at the same time are not compatible")
+    );
+    assert_eq!(
+        run(&["EXPIRE", "k", "10", "GT", "LT"]),
+        Reply::error(b"GT and LT options at the same time are not compatible")
+    );
+    assert_eq!(run(&["EXPIRE", "k", "10", "EQ"]), Reply::error(b"Unsupported option EQ"));
+}
+
+#[test]
+fn test_expireat_and_pexpireat_set_absolute_expiry() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50015".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+    let at = (now + 1000).to_string();
+    let at_ms = ((now + 2000) * 1000 - 1).to_string();
+
+    run(&["SET", "k", "v"]);
+    assert_eq!(run(&["EXPIREAT", "k", &at]), Reply::Integer(1));
+    assert_eq!(run(&["TTL", "k"]), Reply::Integer(1000));
+    // Rounded up to the whole second
+    assert_eq!(run(&["PEXPIREAT", "k", &at_ms, "GT"]), Reply::Integer(1));
+    assert_eq!(run(&["TTL", "k"]), Reply::Integer(2000));
+    assert_eq!(run(&["EXPIREAT", "k", &at, "GT"]), Reply::Integer(0));
+
+    // A time in the past deletes the key
+    assert_eq!(run(&["PEXPIREAT", "k", "1000"]), Reply::Integer(1));
+    assert_eq!(run(&["EXISTS", "k"]), Reply::Integer(0));
+    assert_eq!(run(&["EXPIREAT", "k", &at]), Reply::Integer(0));
+}
+
 #[test]
 fn test_expire_on_existing_key() {
     let store = create_test_store();
@@ -838,6 +2479,84 @@ fn test_persist_removes_ttl() {
     assert!(store.get(&key, timestamp + 1000).is_some());
 }
 
+// ==================== Lock Release Tests ====================
+
+#[test]
+fn test_unlock_deletes_only_matching_token() {
+    let store = ShardedStore::new(4);
+    let now = 1000;
+    store.set(Bytes::from("lock"), Bytes::from("token-a"), Some(30), now);
+
+    // Another client's token must not release the lock
+    assert_eq!(store.compare_and_delete(b"lock", b"token-b", now), Ok(None));
+    assert!(store.get(b"lock", now).is_some());
+
+    assert!(matches!(store.compare_and_delete(b"lock", b"token-a", now), Ok(Some(_))));
+    assert!(store.get(b"lock", now).is_none());
+    assert_eq!(store.compare_and_delete(b"lock", b"token-a", now), Ok(None));
+}
+
+#[test]
+fn test_unlock_expired_and_wrong_type() {
+    let store = ShardedStore::new(4);
+    let now = 1000;
+    store.set(Bytes::from("lock"), Bytes::from("t"), Some(5), now);
+    assert_eq!(store.compare_and_delete(b"lock", b"t", now + 5), Ok(None));
+
+    store
+        .push(&Bytes::from("list"), &[Bytes::from("t")], false, now)
+        .unwrap();
+    assert!(store.compare_and_delete(b"list", b"t", now).is_err());
+}
+
+// ==================== GETDEL/GETEX Tests ====================
+
+#[test]
+fn test_getdel_and_getex_remove_expired_keys() {
+    let store = ShardedStore::new(4);
+    let now = 1000;
+    store.set(Bytes::from("a"), Bytes::from("1"), Some(1), now);
+    store.set(Bytes::from("b"), Bytes::from("2"), Some(1), now);
+
+    // Past the TTL both read as missing and the entries are gone, even
+    // though the sweeper never ran
+    assert_eq!(store.get_del(&Bytes::from("a"), now + 1), Ok((None, entry_size(1, 1))));
+    let (value, freed) = store.get_ex(&Bytes::from("b"), Some(None), now + 1).unwrap();
+    assert_eq!(value, None);
+    assert!(freed > 0);
+    assert_eq!(store.len(), 0);
+
+    store
+        .push(&Bytes::from("list"), &[Bytes::from("x")], false, now)
+        .unwrap();
+    assert!(store.get_del(&Bytes::from("list"), now).is_err());
+    assert!(store.get_ex(&Bytes::from("list"), None, now).is_err());
+}
+
+#[test]
+fn test_getdel_and_getex_commands() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50026".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    run(&["SET", "k", "v"]);
+    assert_eq!(run(&["GETEX", "k", "EX", "100"]), Reply::BulkString(Bytes::from("v")));
+    assert_eq!(run(&["TTL", "k"]), Reply::Integer(100));
+    assert_eq!(run(&["GETEX", "k", "PERSIST"]), Reply::BulkString(Bytes::from("v")));
+    assert_eq!(run(&["TTL", "k"]), Reply::Integer(-1));
+    assert!(matches!(run(&["GETEX", "k", "EX", "0"]), Reply::Error(_)));
+    assert!(matches!(run(&["GETEX", "k", "EX"]), Reply::Error(_)));
+
+    // An expiry already in the past returns the value and deletes the key
+    assert_eq!(run(&["GETEX", "k", "EXAT", "1"]), Reply::BulkString(Bytes::from("v")));
+    assert_eq!(run(&["EXISTS", "k"]), Reply::Integer(0));
+
+    run(&["SET", "k", "v"]);
+    assert_eq!(run(&["GETDEL", "k"]), Reply::BulkString(Bytes::from("v")));
+    assert_eq!(run(&["GETDEL", "k"]), Reply::Null);
+}
+
 // ==================== SET GET Option Support Tests ====================
 
 #[test]
@@ -859,8 +2578,1102 @@ fn test_set_returns_old_value() {
     assert_eq!(store.get(&key, now()), Some(Bytes::from("new_value")));
 }
 
+// ==================== Propagation Tests ====================
+
+#[test]
+fn test_writes_propagate_with_absolute_expiry_times() {
+    use std::sync::Mutex;
+
+    let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+    let sink = seen.clone();
+    // Hooks are global: keep only this test's keys
+    add_propagation_hook(Box::new(move |db, command| {
+        if command.get(1).is_some_and(|key| key.starts_with(b"prop:")) {
+            let words: Vec<_> = command.iter().map(|arg| String::from_utf8_lossy(arg).into_owned()).collect();
+            sink.lock().unwrap().push(format!("{} {}", db, words.join(" ")));
+        }
+    }));
+
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50031".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+    let at = |seconds: u64| ((now + seconds) * 1000).to_string();
+
+    run(&["SET", "prop:a", "1", "EX", "10"]);
+    run(&["SET", "prop:b", "2"]);
+    run(&["EXPIRE", "prop:a", "100", "GT"]);
+    run(&["GETEX", "prop:a", "PERSIST"]);
+    run(&["GETEX", "prop:b", "PX", "1500"]);
+    run(&["EXPIRE", "prop:b", "-1"]);
+    // Not applied, so nothing to propagate
+    run(&["EXPIRE", "prop:missing", "10"]);
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            format!("0 SET prop:a 1 PXAT {}", at(10)),
+            "0 SET prop:b 2".to_string(),
+            format!("0 PEXPIREAT prop:a {}", at(100)),
+            "0 PERSIST prop:a".to_string(),
+            format!("0 PEXPIREAT prop:b {}", at(2)),
+            "0 DEL prop:b".to_string(),
+        ]
+    );
+
+    // The rewritten SET replays to the same expiry
+    let replica = vec![create_test_store()];
+    let mut replica_state = ConnectionState::new("127.0.0.1:50032".to_string());
+    let set = format!("SET prop:c 1 PXAT {}", at(10));
+    let parts: Vec<&str> = set.split(' ').collect();
+    assert_eq!(execute(&replica, &args(&parts), &mut replica_state, now), Reply::ok());
+    assert_eq!(execute(&replica, &args(&["TTL", "prop:c"]), &mut replica_state, now), Reply::Integer(10));
+    // EXAT in the past stores a key that is already gone
+    assert_eq!(execute(&replica, &args(&["SET", "prop:d", "1", "EXAT", "1"]), &mut replica_state, now), Reply::ok());
+    assert_eq!(execute(&replica, &args(&["EXISTS", "prop:d"]), &mut replica_state, now), Reply::Integer(0));
+}
+
+// ==================== SCAN Tests ====================
+
+fn scan_all(store: &ShardedStore, pattern: Option<&[u8]>, type_name: Option<&[u8]>) -> Vec<Bytes> {
+    let mut cursor = 0;
+    let mut keys = Vec::new();
+    loop {
+        let (next, batch) = store
+            .scan(cursor, 10, pattern, type_name, 1000, &mut CommandBudget::unlimited())
+            .unwrap();
+        keys.extend(batch);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    keys.sort();
+    keys
+}
+
+#[test]
+fn test_scan_full_iteration() {
+    let store = ShardedStore::new(16);
+    for i in 0..100 {
+        store.set(Bytes::from(format!("key:{}", i)), Bytes::from("v"), None, 1000);
+    }
+    // Expired keys are skipped
+    store.set(Bytes::from("old"), Bytes::from("v"), Some(1), 900);
+
+    let keys = scan_all(&store, None, None);
+    assert_eq!(keys.len(), 100);
+    keys.windows(2).for_each(|w| assert_ne!(w[0], w[1]));
+}
+
+#[test]
+fn test_scan_returns_stable_keys_under_concurrent_writes() {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let store = Arc::new(ShardedStore::new(16));
+    for i in 0..2000 {
+        store.set(Bytes::from(format!("stable:{}", i)), Bytes::from("v"), None, 1000);
+    }
+
+    // Churn grows the shard maps well past their size at the start, so
+    // they resize while the scans below are running
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let (store, stop) = (store.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut round = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let keys: Vec<Bytes> = (0..20_000)
+                    .map(|i| Bytes::from(format!("churn:{}:{}", round, i)))
+                    .collect();
+                for key in &keys {
+                    store.set(key.clone(), Bytes::from("v"), None, 1000);
+                }
+                store.delete(&keys);
+                round += 1;
+            }
+        })
+    };
+
+    for _ in 0..5 {
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = store
+                .scan(cursor, 10, None, None, 1000, &mut CommandBudget::unlimited())
+                .unwrap();
+            seen.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        for i in 0..2000 {
+            assert!(seen.contains(&Bytes::from(format!("stable:{}", i))), "stable:{} missed", i);
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
+
+#[test]
+fn test_scan_match_and_type_filters() {
+    let store = ShardedStore::new(16);
+    store.set(Bytes::from("user:1"), Bytes::from("v"), None, 1000);
+    store.set(Bytes::from("user:2"), Bytes::from("v"), None, 1000);
+    store.set(Bytes::from("session:1"), Bytes::from("v"), None, 1000);
+    store
+        .push(&Bytes::from("user:queue"), &[Bytes::from("job")], false, 1000)
+        .unwrap();
+
+    assert_eq!(scan_all(&store, Some(b"user:*"), None).len(), 3);
+    assert_eq!(
+        scan_all(&store, None, Some(b"list")),
+        vec![Bytes::from("user:queue")]
+    );
+    assert_eq!(scan_all(&store, Some(b"user:?"), Some(b"STRING")).len(), 2);
+    assert!(scan_all(&store, None, Some(b"hash")).is_empty());
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match(b"*", b"anything"));
+    assert!(glob_match(b"*", b""));
+    assert!(glob_match(b"h?llo", b"hello"));
+    assert!(!glob_match(b"h?llo", b"hllo"));
+    assert!(glob_match(b"h*llo", b"heeeello"));
+    assert!(glob_match(b"h[ae]llo", b"hallo"));
+    assert!(!glob_match(b"h[ae]llo", b"hillo"));
+    assert!(glob_match(b"h[^e]llo", b"hallo"));
+    assert!(!glob_match(b"h[^e]llo", b"hello"));
+    assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+    assert!(glob_match(b"h[b-a]llo", b"hallo"));
+    assert!(glob_match(b"h\\*llo", b"h*llo"));
+    assert!(!glob_match(b"h\\*llo", b"hello"));
+    assert!(glob_match(b"a*b*", b"ab"));
+    assert!(!glob_match(b"abc", b"ab"));
+    assert!(!glob_match(b"ab", b"abc"));
+    // Unterminated class
+    assert!(glob_match(b"[ab", b"a"));
+    assert!(glob_match(b"*[ab", b"xxb"));
+    assert!(glob_match(b"a*?c", b"abbc"));
+    assert!(!glob_match(b"a*?c", b"ac"));
+
+    // Many stars against a long near-miss: linear per star, not exponential
+    let pattern = format!("{}b", "a*".repeat(30));
+    assert!(!glob_match(pattern.as_bytes(), "a".repeat(60).as_bytes()));
+    assert!(glob_match(pattern.as_bytes(), format!("{}b", "a".repeat(60)).as_bytes()));
+}
+
+#[test]
+fn test_debug_stringmatch_len() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50010".to_string());
+    let mut matches = |pattern: &str, string: &str| {
+        let command = args(&["DEBUG", "STRINGMATCH-LEN", pattern, string]);
+        match execute(&dbs, &command, &mut state, now()) {
+            Reply::Integer(n) => n,
+            _ => panic!("expected an integer reply"),
+        }
+    };
+    assert_eq!(matches("a*[bc]?", "axxcz"), 1);
+    assert_eq!(matches("[\\]]", "]"), 1);
+    assert_eq!(matches("[^a-c]x", "bx"), 0);
+    assert_eq!(matches("\\?", "?"), 1);
+    assert_eq!(matches("\\?", "a"), 0);
+}
+
+#[test]
+fn test_debug_populate() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50017".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+
+    run(&["SET", "key:1", "mine"]);
+    assert_eq!(run(&["DEBUG", "POPULATE", "3"]), Reply::ok());
+    assert_eq!(run(&["DBSIZE"]), Reply::Integer(3));
+    assert_eq!(run(&["GET", "key:0"]), Reply::bulk(b"value:0"));
+    assert_eq!(run(&["GET", "key:1"]), Reply::bulk(b"mine"));
+
+    assert_eq!(run(&["DEBUG", "POPULATE", "2", "big", "10"]), Reply::ok());
+    assert_eq!(run(&["GET", "big:1"]), Reply::bulk(b"value:1\0\0\0"));
+    assert_eq!(run(&["DEBUG", "POPULATE", "1", "small", "3"]), Reply::ok());
+    assert_eq!(run(&["GET", "small:0"]), Reply::bulk(b"val"));
+
+    assert_eq!(
+        run(&["DEBUG", "POPULATE", "-1"]),
+        Reply::error(b"value is not an integer or out of range")
+    );
+    assert_eq!(run(&["DBSIZE"]), Reply::Integer(6));
+}
+
+#[test]
+fn test_debug_shard_of_matches_store_hash() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50016".to_string());
+    for key in ["user:1", "{user}:1", "{user}:2"] {
+        let reply = execute(&dbs, &args(&["DEBUG", "SHARD-OF", key]), &mut state, now());
+        assert_eq!(reply, Reply::Integer(dbs[0].hash(key.as_bytes()) as i64));
+    }
+}
+
+#[test]
+fn test_debug_rejects_unknown_subcommands() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50030".to_string());
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now());
+
+    assert_eq!(run(&["DEBUG", "JMAP"]), Reply::ok());
+    assert_eq!(run(&["debug", "sleep", "0"]), Reply::ok());
+    assert_eq!(
+        run(&["DEBUG", "Frobnicate", "1"]),
+        Reply::error(b"DEBUG subcommand not supported: 'Frobnicate'")
+    );
+    // A known subcommand with the wrong arguments says so instead
+    assert_eq!(
+        run(&["DEBUG", "OBJECT"]),
+        Reply::error(b"wrong number of arguments for 'debug' command")
+    );
+    assert_eq!(
+        run(&["DEBUG", "RELOAD"]),
+        Reply::error(b"Reload disabled: no snapshot path configured")
+    );
+}
+
+#[test]
+fn test_reload_snapshot_restores_keys() {
+    let dbs = [ShardedStore::new(4), ShardedStore::new(4)];
+    let now = get_timestamp();
+    dbs[0].set(Bytes::from("a"), Bytes::from("1"), None, now);
+    dbs[1].set(Bytes::from("b"), Bytes::from("2"), Some(100), now);
+    let path = std::env::temp_dir().join(format!("redistill-reload-{}.rdb", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    assert_eq!(reload_snapshot(&dbs, path).unwrap(), 2);
+    assert_eq!(dbs[0].get(b"a", now), Some(Bytes::from("1")));
+    assert_eq!(dbs[1].get(b"b", now), Some(Bytes::from("2")));
+    assert!(dbs[1].get(b"b", now + 100).is_none());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_panicking_command_replies_error_and_releases_locks() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50029".to_string());
+    let mut writer = RespWriter::new();
+    let mut run = |parts: &[&str], writer: &mut RespWriter| {
+        writer.buffer.clear();
+        execute_command(&dbs, &args(parts), writer, &mut state, now());
+        String::from_utf8(writer.buffer.clone()).unwrap()
+    };
+
+    assert_eq!(run(&["SET", "k", "v"], &mut writer), "+OK\r\n");
+    assert_eq!(run(&["DEBUG", "PANIC"], &mut writer), "-ERR internal error\r\n");
+    // The shard lock taken before the panic is gone, or these would deadlock
+    assert_eq!(run(&["DEBUG", "PANIC", "k"], &mut writer), "-ERR internal error\r\n");
+    assert_eq!(run(&["SET", "k", "v2"], &mut writer), "+OK\r\n");
+    assert_eq!(run(&["GET", "k"], &mut writer), "$2\r\nv2\r\n");
+}
+
+// ==================== Command Table Tests ====================
+
+#[test]
+fn test_command_getkeys_single_key() {
+    let command = args(&["SET", "foo", "bar"]);
+    let spec = lookup_command(&command[0]).unwrap();
+    assert!(spec.arity_ok(command.len()));
+    assert_eq!(spec.keys(&command), vec![&Bytes::from("foo")]);
+}
+
+#[test]
+fn test_command_getkeys_stepped_and_variadic() {
+    let mset = args(&["MSET", "a", "1", "b", "2"]);
+    let spec = lookup_command(b"mset").unwrap();
+    assert_eq!(spec.keys(&mset), vec![&Bytes::from("a"), &Bytes::from("b")]);
+
+    let del = args(&["del", "x", "y", "z"]);
+    assert_eq!(lookup_command(b"DEL").unwrap().keys(&del).len(), 3);
+
+    // OBJECT's key comes after the subcommand
+    let object = args(&["OBJECT", "REFCOUNT", "k"]);
+    assert_eq!(lookup_command(b"object").unwrap().keys(&object), vec![&Bytes::from("k")]);
+
+    // SORT's key is the first argument whatever it is named, and STORE adds
+    // the destination; option arguments such as a BY pattern are not keys
+    let sort = lookup_command(b"sort").unwrap();
+    let keys = |parts: &[&str]| {
+        let command = args(parts);
+        sort.keys(&command).into_iter().cloned().collect::<Vec<_>>()
+    };
+    assert_eq!(keys(&["SORT", "mylist", "STORE", "dest"]), args(&["mylist", "dest"]));
+    assert_eq!(keys(&["SORT", "3", "ALPHA", "DESC"]), args(&["3"]));
+    assert_eq!(
+        keys(&["SORT", "k", "BY", "store", "LIMIT", "0", "10", "GET", "#", "STORE", "out"]),
+        args(&["k", "out"])
+    );
+
+    // LMPOP/ZMPOP keys follow numkeys
+    let lmpop = args(&["LMPOP", "2", "a", "b", "LEFT", "COUNT", "3"]);
+    assert_eq!(
+        lookup_command(b"lmpop").unwrap().keys(&lmpop),
+        vec![&Bytes::from("a"), &Bytes::from("b")]
+    );
+}
+
+#[test]
+fn test_command_getkeys_sort() {
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50035".to_string());
+    assert_eq!(
+        execute(&dbs, &args(&["COMMAND", "GETKEYS", "SORT", "mylist", "STORE", "dest"]), &mut state, now()),
+        Reply::bulk_array(&args(&["mylist", "dest"]))
+    );
+    assert_eq!(
+        execute(&dbs, &args(&["COMMAND", "GETKEYS", "SORT", "mylist", "ALPHA"]), &mut state, now()),
+        Reply::bulk_array(&args(&["mylist"]))
+    );
+}
+
+#[test]
+fn test_command_table_arity_and_keyless() {
+    let get = lookup_command(b"get").unwrap();
+    assert!(get.arity_ok(2));
+    assert!(!get.arity_ok(3));
+
+    let set = lookup_command(b"set").unwrap();
+    assert!(!set.arity_ok(2));
+    assert!(set.arity_ok(5));
+
+    assert!(lookup_command(b"ping").unwrap().keys(&args(&["PING"])).is_empty());
+    assert!(lookup_command(b"nosuchcommand").is_none());
+}
+
+#[test]
+fn test_unknown_command_message() {
+    assert_eq!(
+        unknown_command_message(&args(&["FOO"])),
+        "unknown command 'FOO', with args beginning with: "
+    );
+    assert_eq!(
+        unknown_command_message(&args(&["foo", "a", "b"])),
+        "unknown command 'foo', with args beginning with: 'a' 'b' "
+    );
+
+    // Non-printable bytes are escaped
+    let command = vec![Bytes::from_static(b"fo\r\no"), Bytes::from_static(b"\x00")];
+    assert_eq!(
+        unknown_command_message(&command),
+        "unknown command 'fo\\x0d\\x0ao', with args beginning with: '\\x00' "
+    );
+
+    // The argument preview is truncated
+    let long = "x".repeat(500);
+    let message = unknown_command_message(&args(&["foo", &long, "next"]));
+    assert!(!message.contains("next"));
+    assert!(message.len() < 250);
+}
+
+#[test]
+fn test_error_text_cannot_forge_reply() {
+    // Framed the way RespWriter::write_error frames it
+    let error_line = |text: &[u8]| {
+        let mut out = b"-ERR ".to_vec();
+        push_error_text(&mut out, text);
+        out.extend_from_slice(b"\r\n");
+        out
+    };
+    let crlf_count = |line: &[u8]| line.windows(2).filter(|w| w == b"\r\n").count();
+
+    let line = error_line(b"no such key 'k\r\n+OK\r\n'");
+    assert_eq!(line, b"-ERR no such key 'k\\x0d\\x0a+OK\\x0d\\x0a'\r\n".to_vec());
+    assert_eq!(crlf_count(&line), 1);
+
+    // Same key through the unknown-command path
+    let command = vec![Bytes::from_static(b"nosuch"), Bytes::from_static(b"k\r\n+OK\r\n")];
+    let line = error_line(unknown_command_message(&command).as_bytes());
+    assert_eq!(crlf_count(&line), 1);
+    assert!(line.starts_with(b"-ERR unknown command"));
+
+    // Oversized text is capped
+    assert!(error_line(&[b'x'; 10_000]).len() < 1100);
+}
+
+#[test]
+fn test_acl_rules_apply_left_to_right() {
+    let get = lookup_command(b"get").unwrap();
+    let del = lookup_command(b"del").unwrap();
+    let flushall = lookup_command(b"flushall").unwrap();
+    let keys = lookup_command(b"keys").unwrap();
+
+    let acl = AclRules::parse("+@read -@dangerous").unwrap();
+    assert!(acl.allows(get));
+    assert!(!acl.allows(keys)); // @read, but also @dangerous
+    assert!(!acl.allows(del)); // matched by no rule
+    assert!(!acl.allows_all);
+
+    let acl = AclRules::parse("allcommands -@dangerous +KEYS").unwrap();
+    assert!(acl.allows(del) && acl.allows(keys));
+    assert!(!acl.allows(flushall));
+    assert_eq!(acl.describe(), "+@all -@dangerous +keys");
+
+    assert!(AclRules::parse("+@all").unwrap().allows_all);
+    assert!(AclRules::parse("+@nosuch").is_err());
+    assert!(AclRules::parse("+nosuchcommand").is_err());
+    assert!(AclRules::parse("@read").is_err());
+}
+
+// ==================== Command Reply Tests ====================
+
+#[test]
+fn test_execute_returns_replies() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50001".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    assert_eq!(run(&["GET", "x"]), Reply::Null);
+    assert_eq!(run(&["SET", "x", "1"]), Reply::ok());
+    assert_eq!(run(&["GET", "x"]), Reply::bulk(b"1"));
+    assert_eq!(run(&["INCR", "x"]), Reply::Integer(2));
+    assert_eq!(run(&["LPUSH", "x", "a"]), Reply::wrongtype());
+    assert_eq!(
+        run(&["MGET", "x", "missing"]),
+        Reply::Array(vec![Reply::bulk(b"2"), Reply::Null])
+    );
+    assert_eq!(run(&["SET", "x"]), Reply::error(b"wrong number of arguments"));
+}
+
+#[test]
+fn test_client_no_touch_and_no_evict_flags() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50002".to_string());
+    let now = now();
+
+    let reply = execute(&dbs, &args(&["CLIENT", "NO-TOUCH", "on"]), &mut state, now);
+    assert_eq!(reply, Reply::ok());
+    assert!(state.no_touch && !state.client.no_evict.load(std::sync::atomic::Ordering::Relaxed));
+
+    execute(&dbs, &args(&["CLIENT", "no-evict", "ON"]), &mut state, now);
+    execute(&dbs, &args(&["CLIENT", "NO-TOUCH", "OFF"]), &mut state, now);
+    assert!(!state.no_touch && state.client.no_evict.load(std::sync::atomic::Ordering::Relaxed));
+
+    let reply = execute(&dbs, &args(&["CLIENT", "NO-TOUCH", "maybe"]), &mut state, now);
+    assert_eq!(reply, Reply::error(b"syntax error"));
+}
+
+#[test]
+fn test_evict_largest_client_skips_no_evict() {
+    use std::sync::atomic::Ordering;
+
+    // Sizes far above any real connection's, so parallel tests cannot be picked
+    let small = ConnectionState::new("127.0.0.1:50022".to_string());
+    let large = ConnectionState::new("127.0.0.1:50023".to_string());
+    let exempt = ConnectionState::new("127.0.0.1:50024".to_string());
+    small.client.set_output_bytes(1 << 40);
+    large.client.set_output_bytes(3 << 40);
+    exempt.client.set_output_bytes(5 << 40);
+    exempt.client.no_evict.store(true, Ordering::Relaxed);
+
+    assert_eq!(evict_largest_client(), Some(large.client.id));
+    assert_eq!(evict_largest_client(), Some(small.client.id));
+    assert!(!exempt.client.evicted.load(Ordering::Relaxed));
+
+    // An evicted client stuck writing to a peer that never reads gives up
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let stuck = ConnectionState::new("127.0.0.1:50025".to_string());
+    runtime.block_on(async {
+        let (mut stream, _peer) = tokio::io::duplex(64);
+        let mut writer = RespWriter::new();
+        writer.write_bulk_string(&[b'x'; 4096]);
+        let client = stuck.client.clone();
+        tokio::spawn(async move { client.evict.notify_one() });
+        let flushed = flush_reply(&mut writer, &mut stream, &stuck.client).await;
+        assert!(matches!(flushed, Err(RedistillError::Evicted)));
+    });
+    assert_eq!(stuck.client.output_bytes.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_acl_denies_commands_outside_allowed_categories() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50003".to_string());
+    state.acl = Arc::new(AclRules::parse("+@read +@connection -@dangerous +acl").unwrap());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    assert_eq!(run(&["GET", "x"]), Reply::Null);
+    assert_eq!(
+        run(&["SET", "x", "1"]),
+        Reply::Error(Bytes::from("NOPERM User default has no permissions to run the 'set' command"))
+    );
+    assert!(matches!(run(&["KEYS", "*"]), Reply::Error(e) if e.starts_with(b"NOPERM")));
+    assert_eq!(run(&["PING"]), Reply::SimpleString(Bytes::from("PONG")));
+    assert!(matches!(run(&["NOSUCH"]), Reply::Error(e) if e.starts_with(b"ERR unknown command")));
+
+    assert_eq!(
+        run(&["ACL", "CAT", "bitmap"]),
+        Reply::bulk_array(&args(&["bitpos", "bitfield"]))
+    );
+    assert_eq!(run(&["ACL", "CAT", "nosuch"]), Reply::error(b"Unknown category 'nosuch'"));
+    assert_eq!(run(&["ACL", "GETUSER", "someone"]), Reply::Null);
+}
+
+// A typical call of each keyed command, with "k" as its key
+fn example_call(name: &str) -> Vec<Bytes> {
+    let parts: &[&str] = match name {
+        "get" | "getdel" | "getex" | "incr" | "decr" | "llen" | "lpop" | "rpop" | "scard" | "smembers" | "zcard" => &[name, "k"],
+        "sort" => &[name, "k", "ALPHA"],
+        "del" | "exists" | "mget" | "persist" | "ttl" | "pttl" | "type" => &[name, "k"],
+        "set" | "mset" => &[name, "k", "v"],
+        "unlock" => &[name, "k", "token"],
+        "incrby" | "decrby" | "bitpos" => &[name, "k", "1"],
+        "expire" => &[name, "k", "100"],
+        "expireat" => &[name, "k", "4102444800"],
+        "pexpireat" => &[name, "k", "4102444800000"],
+        "bitfield" => &[name, "k", "GET", "u8", "0"],
+        "lpush" | "rpush" | "sadd" | "srem" | "sismember" | "zscore" => &[name, "k", "a"],
+        "lrange" | "zrange" => &[name, "k", "0", "-1"],
+        "lmpop" => &[name, "1", "k", "LEFT"],
+        "zmpop" => &[name, "1", "k", "MIN"],
+        "sintercard" | "zintercard" => &[name, "1", "k"],
+        "zadd" => &[name, "k", "1", "a"],
+        "object" => &[name, "REFCOUNT", "k"],
+        _ => panic!("no example call for '{}'; add one", name),
+    };
+    args(parts)
+}
+
+#[test]
+fn test_type_checks_follow_command_table() {
+    let creators: [(&str, &[&str]); 4] = [
+        ("string", &["SET", "k", "v"]),
+        ("list", &["RPUSH", "k", "a"]),
+        ("set", &["SADD", "k", "a"]),
+        ("zset", &["ZADD", "k", "1", "a"]),
+    ];
+    let now = now();
+
+    for spec in COMMAND_TABLE {
+        let Some(call) = example_call_if_keyed(spec) else { continue };
+        for (value_type, create) in &creators {
+            let dbs = [create_test_store()];
+            let mut state = ConnectionState::new("127.0.0.1:50005".to_string());
+            execute(&dbs, &args(create), &mut state, now);
+
+            let reply = execute(&dbs, &call, &mut state, now);
+            let expect_wrongtype =
+                !spec.value_types.is_empty() && !spec.value_types.contains(value_type);
+            assert_eq!(
+                reply == Reply::wrongtype(),
+                expect_wrongtype,
+                "{} on a {} key replied {:?}",
+                spec.name,
+                value_type,
+                reply
+            );
+        }
+    }
+}
+
+fn example_call_if_keyed(spec: &CommandSpec) -> Option<Vec<Bytes>> {
+    let keyed = spec.first_key > 0 || spec.flags.contains(&"movablekeys");
+    keyed.then(|| example_call(spec.name))
+}
+
+#[test]
+fn test_exists_and_del_work_on_every_type() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50006".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    run(&["SET", "s", "v"]);
+    run(&["RPUSH", "l", "a"]);
+    run(&["SADD", "st", "a"]);
+    run(&["ZADD", "z", "1", "a"]);
+    assert_eq!(run(&["EXISTS", "s", "l", "st", "z", "missing"]), Reply::Integer(4));
+    assert_eq!(run(&["TYPE", "z"]), Reply::SimpleString(Bytes::from("zset")));
+    assert_eq!(run(&["EXPIRE", "l", "100"]), Reply::Integer(1));
+    assert_eq!(run(&["PERSIST", "l"]), Reply::Integer(1));
+    assert_eq!(run(&["DEL", "s", "l", "st", "z"]), Reply::Integer(4));
+    assert_eq!(run(&["EXISTS", "s", "l", "st", "z"]), Reply::Integer(0));
+}
+
+#[test]
+fn test_dispatch_type_check_order() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50036".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    run(&["SET", "s", "v"]);
+    run(&["RPUSH", "l", "3", "1", "2"]);
+    // A short command gets its arity error, not WRONGTYPE
+    assert_eq!(run(&["LRANGE", "s", "0"]), Reply::error(b"wrong number of arguments"));
+    assert_eq!(run(&["LRANGE", "s", "0", "-1"]), Reply::wrongtype());
+    // Any named key of the wrong type fails the whole command
+    assert_eq!(run(&["LMPOP", "2", "l", "s", "LEFT"]), Reply::wrongtype());
+    assert_eq!(run(&["LLEN", "l"]), Reply::Integer(3));
+    // SORT ... STORE overwrites its destination whatever its type
+    assert_eq!(run(&["SORT", "l", "STORE", "s"]), Reply::Integer(3));
+    assert_eq!(run(&["TYPE", "s"]), Reply::SimpleString(Bytes::from("list")));
+    assert_eq!(run(&["TYPE", "missing"]), Reply::SimpleString(Bytes::from("none")));
+}
+
+#[test]
+fn test_option_keywords_ignore_case_but_keys_do_not() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50009".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    assert_eq!(run(&["set", "k", "v", "ex", "10"]), Reply::ok());
+    assert_eq!(run(&["SET", "K", "V", "Px", "10000", "nX"]), Reply::ok());
+    assert_eq!(run(&["SET", "k", "w", "xX", "GeT"]), Reply::bulk(b"v"));
+    assert_eq!(run(&["TTL", "K"]), Reply::Integer(10));
+    // Keys and values keep their case
+    assert_eq!(run(&["MGET", "k", "K"]), Reply::bulk_array(&args(&["w", "V"])));
+
+    run(&["RPUSH", "l", "a", "b"]);
+    assert_eq!(
+        run(&["LMPOP", "1", "l", "Right", "count", "1"]),
+        Reply::Array(vec![Reply::bulk(b"l"), Reply::bulk_array(&args(&["b"]))])
+    );
+    run(&["ZADD", "z", "1", "m"]);
+    assert_eq!(run(&["ZRANGE", "z", "0", "-1", "WithScores"]), Reply::bulk_array(&args(&["m", "1"])));
+    assert_eq!(run(&["SINTERCARD", "1", "missing", "Limit", "5"]), Reply::Integer(0));
+    assert_eq!(
+        run(&["BITFIELD", "b", "Set", "U8", "0", "255", "get", "u8", "0"]),
+        Reply::Array(vec![Reply::Integer(0), Reply::Integer(255)])
+    );
+    assert_eq!(run(&["SET", "k", "v", "EXX", "10"]), Reply::error(b"syntax error"));
+}
+
+#[test]
+fn test_lolwut_reports_version_before_auth() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50004".to_string());
+    state.authenticated = false;
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    let Reply::BulkString(text) = run(&["LOLWUT", "VERSION", "5"]) else {
+        panic!("LOLWUT should reply with a bulk string");
+    };
+    let version = format!("Redistill ver. {}\n", env!("CARGO_PKG_VERSION"));
+    assert!(text.starts_with(BANNER.as_bytes()));
+    assert!(text.ends_with(version.as_bytes()));
+
+    assert_eq!(run(&["lolwut", "VERSION", "x"]), Reply::error(b"value is not an integer or out of range"));
+    assert_eq!(run(&["LOLWUT", "COLUMNS"]), Reply::error(b"syntax error"));
+    assert_eq!(run(&["GET", "x"]), Reply::error(b"NOAUTH Authentication required"));
+}
+
+#[test]
+fn test_replconf_handshake_is_acknowledged() {
+    let dbs = [create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50007".to_string());
+    let now = now();
+    let mut run = |parts: &[&str]| execute(&dbs, &args(parts), &mut state, now);
+
+    assert_eq!(run(&["REPLCONF", "listening-port", "6380"]), Reply::ok());
+    assert_eq!(run(&["replconf", "capa", "eof", "capa", "psync2"]), Reply::ok());
+    assert_eq!(run(&["REPLCONF", "ACK", "1234"]), Reply::ok());
+    assert_eq!(run(&["REPLCONF", "capa"]), Reply::error(b"syntax error"));
+}
+
+#[test]
+fn test_write_reply_serializes_nested_arrays() {
+    let mut writer = RespWriter::new();
+    writer.write_reply(&Reply::Array(vec![
+        Reply::bulk(b"k"),
+        Reply::Array(vec![Reply::Integer(-1), Reply::Null, Reply::NullArray]),
+    ]));
+    assert_eq!(writer.buffer, b"*2\r\n$1\r\nk\r\n*3\r\n:-1\r\n$-1\r\n*-1\r\n".to_vec());
+
+    writer.buffer.clear();
+    writer.write_reply(&Reply::error(b"bad\r\nkey"));
+    writer.write_reply(&Reply::wrongtype());
+    writer.write_reply(&Reply::Deferred);
+    assert!(writer.buffer.starts_with(b"-ERR bad\\x0d\\x0akey\r\n-WRONGTYPE "));
+}
+
+#[test]
+fn test_writer_flush_threshold() {
+    let mut writer = RespWriter::new();
+    assert_eq!(writer.flush_threshold, CONFIG.server.reply_flush_threshold);
+
+    writer.flush_threshold = 20;
+    writer.write_bulk_string(b"0123456789");
+    assert!(!writer.should_flush());
+    writer.write_bulk_string(b"x");
+    assert!(writer.should_flush());
+}
+
+#[test]
+fn test_buffer_sizer_grows_fast_and_shrinks_slowly() {
+    let mut sizer = BufferSizer::new(1024, 16384);
+    sizer.observe(1024);
+    assert_eq!(sizer.target, 2048);
+    sizer.observe(5000);
+    assert_eq!(sizer.target, 8192);
+    sizer.observe(1_000_000);
+    assert_eq!(sizer.target, 16384);
+
+    // Middling use holds the target; only a run of small ones halves it
+    for _ in 0..BUFFER_SHRINK_AFTER - 1 {
+        sizer.observe(100);
+    }
+    sizer.observe(9000);
+    assert_eq!(sizer.target, 16384);
+    for _ in 0..BUFFER_SHRINK_AFTER {
+        sizer.observe(100);
+    }
+    assert_eq!(sizer.target, 8192);
+
+    sizer.reset();
+    assert_eq!(sizer.target, 1024);
+}
+
+#[test]
+fn test_connection_buffers_idle_vs_busy() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let dbs = vec![create_test_store()];
+    let mut state = ConnectionState::new("127.0.0.1:50018".to_string());
+    let mut parser = RespParser::new();
+    let mut writer = RespWriter::new();
+    let footprint = |parser: &RespParser, writer: &RespWriter| {
+        parser.buffer.capacity() + writer.buffer.capacity()
+    };
+
+    // A connection that has sent nothing holds only the small buffers
+    let idle = footprint(&parser, &writer);
+    assert!(idle <= 2 * min_buffer_size(), "idle connection holds {} bytes", idle);
+
+    // A pipeline of 500-byte values grows both toward buffer_size
+    let value = "v".repeat(500);
+    let mut input = Vec::new();
+    for i in 0..200 {
+        let key = format!("key:{}", i);
+        input.extend_from_slice(
+            format!("*3\r\n$3\r\nSET\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, value.len(), value)
+                .as_bytes(),
+        );
+        input.extend_from_slice(format!("*2\r\n$3\r\nGET\r\n${}\r\n{}\r\n", key.len(), key).as_bytes());
+    }
+    let mut reader = &input[..];
+    let mut sink = Vec::new();
+    let mut replies = 0;
+    runtime.block_on(async {
+        while let Ok(command) = parser.parse_command(&mut reader).await {
+            execute_command(&dbs, &command, &mut writer, &mut state, now());
+            replies += 1;
+            if writer.should_flush() || !parser.has_buffered_data() {
+                writer.flush(&mut sink).await.unwrap();
+            }
+        }
+    });
+    assert_eq!(replies, 400);
+    assert!(sink.ends_with(format!("${}\r\n{}\r\n", value.len(), value).as_bytes()));
+    let busy = footprint(&parser, &writer);
+    assert!(busy > idle, "busy connection holds {} bytes", busy);
+    assert!(busy <= 4 * CONFIG.server.buffer_size, "busy connection holds {} bytes", busy);
+
+    // Going quiet gives it all back
+    parser.release_idle();
+    writer.release_idle();
+    assert!(footprint(&parser, &writer) <= idle);
+}
+
+#[test]
+fn test_unread_pipeline_keeps_reply_buffer_bounded() {
+    use std::sync::atomic::Ordering;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let dbs = Arc::new(vec![create_test_store()]);
+        dbs[0].set(Bytes::from("k"), Bytes::from("v"), None, now());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            handle_connection(MaybeStream::Plain(stream), dbs, peer).await;
+        });
+
+        // A million GETs sent without reading any reply
+        let commands = 1_000_000;
+        let client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let local = client.local_addr().unwrap().to_string();
+        let (mut reader, mut sender) = client.into_split();
+        let sending = tokio::spawn(async move {
+            let chunk = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n".repeat(10_000);
+            for _ in 0..commands / 10_000 {
+                sender.write_all(&chunk).await.unwrap();
+            }
+            sender
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let info = CLIENTS
+            .iter()
+            .find(|c| c.addr == local)
+            .map(|c| c.value().clone())
+            .unwrap();
+
+        // The server blocks on the socket with at most one flush worth of
+        // replies pending, however far ahead the client has written
+        let limit = (CONFIG.server.reply_flush_threshold + 64) as u64;
+        let reply = b"$1\r\nv\r\n";
+        let mut received = 0;
+        let mut buf = vec![0; 1 << 16];
+        while received < commands * reply.len() {
+            assert!(info.output_bytes.load(Ordering::Relaxed) <= limit);
+            let n = reader.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            received += n;
+        }
+        assert_eq!(received, commands * reply.len());
+
+        drop(sending.await.unwrap());
+        server.await.unwrap();
+    });
+}
+
+// ==================== Protocol Parser Tests ====================
+
+#[test]
+fn test_connection_errors_keep_their_cause() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        // A client that hangs up, even part way through a command
+        for input in [&b""[..], b"*1\r\n$4\r\nPI"] {
+            let mut parser = RespParser::new();
+            let mut reader = input;
+            let result = parser.parse_command(&mut reader).await;
+            assert!(matches!(result, Err(RedistillError::ConnectionClosed)), "{:?}", input);
+        }
+
+        let mut parser = RespParser::new();
+        let result = parser.parse_command(&mut &b"*1\r\n:1\r\n"[..]).await;
+        let Err(e) = result else { panic!("arguments must be bulk strings") };
+        assert_eq!(e.reply(), Some(&b"Protocol error: expected '$'"[..]));
+        assert_eq!(e.to_string(), "Protocol error: expected '$'");
+        assert!(!e.is_routine());
+
+        // A peer that is gone surfaces the socket error
+        let (mut stream, peer) = tokio::io::duplex(64);
+        drop(peer);
+        let mut writer = RespWriter::new();
+        writer.write_simple_string(b"OK");
+        assert!(matches!(writer.flush(&mut stream).await, Err(RedistillError::Io(_))));
+    });
+    assert!(RedistillError::Timeout.is_routine());
+    assert_eq!(RedistillError::Timeout.reply(), None);
+}
+
+#[test]
+fn test_unterminated_inline_line_is_rejected() {
+    // An inline line without a \n is buffered up to server.max_inline_len only
+    let mut parser = RespParser {
+        buffer: BytesMut::from(&b"SET key "[..]),
+        max_args: 16,
+        max_bytes: 1024,
+        max_inline: 64 * 1024,
+        strict: false,
+        sizer: BufferSizer::new(64, 1024),
+    };
+    parser.buffer.extend_from_slice(&vec![b'x'; 64 * 1024]);
+    assert!(matches!(parser.try_parse(), Err(RedistillError::Protocol(_))));
+
+    // A RESP header still waits for the rest of its line
+    parser.buffer = BytesMut::from(&b"*1\r\n$3\r\nGE"[..]);
+    assert!(matches!(parser.try_parse(), Ok(None)));
+
+    // A custom limit: a line may reach it, unterminated or not, but not pass it
+    parser.max_inline = 16;
+    parser.buffer = BytesMut::from(&b"GET 012345678901"[..]);
+    assert!(matches!(parser.try_parse(), Ok(None)));
+    parser.buffer.extend_from_slice(b"\n");
+    assert!(matches!(parser.try_parse(), Ok(Some(cmd)) if cmd == args(&["GET", "012345678901"])));
+    for line in [&b"GET 0123456789012"[..], b"GET 0123456789012\r\n"] {
+        parser.buffer = BytesMut::from(line);
+        let Err(e) = parser.try_parse() else { panic!("{:?} is over the limit", line) };
+        assert_eq!(e.reply(), Some(&b"Protocol error: too big inline request"[..]));
+    }
+}
+
+#[test]
+fn test_inline_commands_accept_crlf_and_bare_newline() {
+    let parser = |input: &[u8], strict: bool| RespParser {
+        buffer: BytesMut::from(input),
+        max_args: 4,
+        max_bytes: 1024,
+        max_inline: 64 * 1024,
+        strict,
+        sizer: BufferSizer::new(64, 1024),
+    };
+
+    // Either ending, blank lines skipped, pipelined with a RESP array
+    let mut lenient = parser(b"SET key  value\r\n\r\n\nGET\tkey\n*1\r\n$4\r\nPING\r\nQUIT", false);
+    for expected in [&["SET", "key", "value"][..], &["GET", "key"], &["PING"]] {
+        assert!(matches!(lenient.try_parse(), Ok(Some(cmd)) if cmd == args(expected)));
+    }
+    assert!(matches!(lenient.try_parse(), Ok(None)));
+    lenient.buffer.extend_from_slice(b"\n");
+    assert!(matches!(lenient.try_parse(), Ok(Some(cmd)) if cmd == args(&["QUIT"])));
+
+    // Strict mode keeps inline commands but wants \r\n
+    let mut strict = parser(b"PING\r\nPING\n", true);
+    assert!(matches!(strict.try_parse(), Ok(Some(cmd)) if cmd == args(&["PING"])));
+    assert!(matches!(strict.try_parse(), Err(RedistillError::Protocol(_))));
+
+    // The parser's argument limit applies to inline commands too
+    let mut long = parser(b"DEL a b c d\r\n", false);
+    assert!(matches!(long.try_parse(), Err(RedistillError::Protocol(_))));
+}
+
+#[test]
+fn test_resp_array_line_endings() {
+    let parse = |input: &[u8], strict: bool| {
+        let mut parser = RespParser {
+            buffer: BytesMut::from(input),
+            max_args: 16,
+            max_bytes: 1024,
+            max_inline: 64 * 1024,
+            strict,
+            sizer: BufferSizer::new(64, 1024),
+        };
+        let cmd = parser.try_parse();
+        (cmd, parser.buffer)
+    };
+
+    let (cmd, _) = parse(b"*1\r\n$4\r\nPING\r\n", true);
+    assert!(matches!(cmd, Ok(Some(cmd)) if cmd == args(&["PING"])));
+
+    // A bare \n ends a line by default, and is refused in strict mode
+    for input in [
+        &b"*1\n$4\r\nPING\r\n*1\r\n"[..],
+        b"*1\r\n$4\nPING\r\n*1\r\n",
+        b"*1\r\n$4\r\nPING\n*1\r\n",
+        b"*1\n$4\nPING\n*1\r\n",
+    ] {
+        let (cmd, rest) = parse(input, false);
+        assert!(matches!(cmd, Ok(Some(cmd)) if cmd == args(&["PING"])), "{:?}", input);
+        assert_eq!(&rest[..], b"*1\r\n", "{:?}", input);
+        let (cmd, _) = parse(input, true);
+        assert!(matches!(cmd, Err(RedistillError::Protocol(_))), "{:?}", input);
+    }
+
+    // An empty multibulk is skipped with either ending, however short
+    for input in [&b"*0\r\n"[..], b"*0\n"] {
+        let (cmd, rest) = parse(input, false);
+        assert!(matches!(cmd, Ok(None)), "{:?}", input);
+        assert!(rest.is_empty(), "{:?}", input);
+        let mut pipelined = input.to_vec();
+        pipelined.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+        let (cmd, _) = parse(&pipelined, false);
+        assert!(matches!(cmd, Ok(Some(cmd)) if cmd == args(&["PING"])), "{:?}", input);
+    }
+    let (cmd, rest) = parse(b"*0\r\n", true);
+    assert!(matches!(cmd, Ok(None)) && rest.is_empty());
+
+    // Refused in both modes rather than waited on: a \r followed by
+    // another byte, or a bulk string that does not start with '$'
+    for input in [&b"*1\r$4\r\nPING\r\n"[..], b"*1\r\n$4\r\nPINGx\r\n", b"*1\r\n:4\r\n"] {
+        for strict in [false, true] {
+            let (cmd, _) = parse(input, strict);
+            assert!(matches!(cmd, Err(RedistillError::Protocol(_))), "{:?}", input);
+        }
+    }
+    let (cmd, _) = parse(b"*2\r\n$4\r\nECHO\r\n+hi\r\n", false);
+    let Err(e) = cmd else { panic!("a simple string is not a bulk string") };
+    assert_eq!(e.reply(), Some(&b"Protocol error: expected '$'"[..]));
+
+    // An ending split across reads is waited on in both modes
+    for input in [&b"*1\r"[..], b"*1\r\n$4\r", b"*1\r\n$4\r\nPING", b"*1\r\n$4\r\nPING\r"] {
+        for strict in [false, true] {
+            let (cmd, _) = parse(input, strict);
+            assert!(matches!(cmd, Ok(None)), "{:?}", input);
+        }
+    }
+}
+
+#[test]
+fn test_strict_protocol_refuses_non_minimal_lengths() {
+    let parse = |input: &[u8], strict: bool| {
+        let mut parser = RespParser {
+            buffer: BytesMut::from(input),
+            max_args: 16,
+            max_bytes: 1024,
+            max_inline: 64 * 1024,
+            strict,
+            sizer: BufferSizer::new(64, 1024),
+        };
+        parser.try_parse()
+    };
+
+    // Accepted by default, refused in strict mode
+    for input in [
+        &b"*01\r\n$4\r\nPING\r\n"[..],
+        b"*1\r\n$004\r\nPING\r\n",
+        b"*2\r\n$4\r\nECHO\r\n$\r\n\r\n",
+        b"*1\n$4\r\nPING\r\n",
+    ] {
+        assert!(matches!(parse(input, false), Ok(Some(_))), "{:?}", input);
+        assert!(matches!(parse(input, true), Err(RedistillError::Protocol(_))), "{:?}", input);
+    }
+
+    // Refused in both modes
+    for input in [&b"*1\r\n$4\r\nPINGx\r\n"[..], b"*1\r\n*4\r\nPING\r\n"] {
+        assert!(matches!(parse(input, false), Err(RedistillError::Protocol(_))), "{:?}", input);
+        assert!(matches!(parse(input, true), Err(RedistillError::Protocol(_))), "{:?}", input);
+    }
+
+    // Well-formed commands, including zero lengths, pass strict mode
+    let cmd = parse(b"*2\r\n$4\r\nECHO\r\n$0\r\n\r\n", true);
+    assert!(matches!(cmd, Ok(Some(cmd)) if cmd == args(&["ECHO", ""])));
+    assert!(matches!(parse(b"*10\r\n", true), Ok(None)));
+}
+
 // ==================== Edge Cases for New Commands ====================
 
+#[test]
+fn test_shared_integers_reuse_allocation() {
+    let a = shared_or_owned(&Bytes::from("42"));
+    let b = shared_or_owned(&Bytes::from("42"));
+    assert_eq!(a, Bytes::from("42"));
+    assert_eq!(a.as_ptr(), b.as_ptr());
+    assert!(is_shared_integer(&a));
+
+    // INCR results land in the same table
+    let c = integer_to_bytes(42);
+    assert_eq!(c.as_ptr(), a.as_ptr());
+    assert!(is_shared_integer(&integer_to_bytes(0)));
+    assert!(is_shared_integer(&integer_to_bytes(9999)));
+}
+
+#[test]
+fn test_non_shared_integer_values() {
+    // Out of range, negative, padded or non-numeric values are stored as-is
+    for raw in ["10000", "-1", "007", "4a", ""] {
+        let value = Bytes::from(raw);
+        let stored = shared_or_owned(&value);
+        assert_eq!(stored, value);
+        assert!(!is_shared_integer(&stored), "{raw} should not be shared");
+    }
+    assert!(!is_shared_integer(&integer_to_bytes(-5)));
+    assert!(!is_shared_integer(&integer_to_bytes(10000)));
+
+    // A freshly allocated "42" is equal but not the shared object
+    assert!(!is_shared_integer(&Bytes::from(String::from("42"))));
+}
+
 #[test]
 fn test_large_integer_values() {
     let store = create_test_store();
@@ -932,5 +3745,115 @@ fn test_rapid_counter_updates() {
     
     // Final value should be at least 10 (minimum if all races conflict)
     // and at most 1000 (if no races occurred)
-    assert!(final_val >= 10 && final_val <= 1000);
+    assert!((10..=1000).contains(&final_val));
+}
+
+// ==================== Bit Operation Tests ====================
+
+#[test]
+fn test_bitpos_ranges() {
+    let value = [0xFFu8, 0xF0, 0x00];
+    assert_eq!(bitpos(&value, false, 0, None), 12);
+    assert_eq!(bitpos(&value, true, 0, None), 0);
+    assert_eq!(bitpos(&value, true, 2, None), -1);
+    assert_eq!(bitpos(&value, true, -2, Some(-1)), 8);
+
+    // All ones: clear bit is past the end unless the range is explicit
+    assert_eq!(bitpos(&[0xFF], false, 0, None), 8);
+    assert_eq!(bitpos(&[0xFF], false, 0, Some(0)), -1);
+
+    // Missing key / empty range
+    assert_eq!(bitpos(&[], false, 0, None), 0);
+    assert_eq!(bitpos(&[], true, 0, None), -1);
+    assert_eq!(bitpos(&value, true, 2, Some(1)), -1);
+}
+
+#[test]
+fn test_bitfield_get_set_incr() {
+    let ops = parse_bitfield_ops(&args(&[
+        "SET", "u8", "0", "200", "GET", "u4", "0", "INCRBY", "i8", "#1", "-3",
+    ]))
+    .unwrap();
+    let mut value = BytesMut::new();
+    assert_eq!(bitfield_apply(&mut value, &ops), vec![Some(0), Some(12), Some(-3)]);
+    assert_eq!(&value[..], &[200, 0xFD]);
+
+    // Fields read past the end as zero without growing the value
+    let ops = parse_bitfield_ops(&args(&["GET", "u16", "100"])).unwrap();
+    assert_eq!(bitfield_apply(&mut value, &ops), vec![Some(0)]);
+    assert_eq!(value.len(), 2);
+}
+
+#[test]
+fn test_bitfield_overflow_modes() {
+    let mut value = BytesMut::from(&[250u8][..]);
+    let ops = parse_bitfield_ops(&args(&[
+        "INCRBY", "u8", "0", "10", "OVERFLOW", "SAT", "INCRBY", "u8", "0", "300", "OVERFLOW",
+        "FAIL", "INCRBY", "u8", "0", "1", "SET", "i8", "0", "-129",
+    ]))
+    .unwrap();
+    // WRAP 250+10 -> 4, SAT clamps to 255, FAIL leaves the field alone
+    assert_eq!(bitfield_apply(&mut value, &ops), vec![Some(4), Some(255), None, None]);
+    assert_eq!(&value[..], &[255]);
+
+    let ops = parse_bitfield_ops(&args(&["SET", "i64", "0", "-1", "INCRBY", "i64", "0", "1"]))
+        .unwrap();
+    let mut value = BytesMut::new();
+    assert_eq!(bitfield_apply(&mut value, &ops), vec![Some(0), Some(0)]);
+}
+
+#[test]
+fn test_bitfield_writes_are_atomic_and_keep_the_ttl() {
+    let store = Arc::new(create_test_store());
+    let key = Bytes::from("counters");
+    store.set(key.clone(), Bytes::new(), Some(3600), now());
+    let expiry = store.shards[store.hash(&key)].get(key.as_ref()).unwrap().expiry;
+    assert!(expiry.is_some());
+
+    // Concurrent INCRBYs on one field all land
+    let incr = parse_bitfield_ops(&args(&["INCRBY", "u32", "0", "1"])).unwrap();
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let (store, key, incr) = (store.clone(), key.clone(), incr.clone());
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    store.bitfield(&key, &incr, 0, now()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let get = parse_bitfield_ops(&args(&["GET", "u32", "0"])).unwrap();
+    assert_eq!(store.bitfield(&key, &get, 0, now()), Ok((vec![Some(4000)], false)));
+    assert_eq!(store.shards[store.hash(&key)].get(key.as_ref()).unwrap().expiry, expiry);
+
+    // A write that does not go through creates nothing
+    let fail = parse_bitfield_ops(&args(&["OVERFLOW", "FAIL", "INCRBY", "u2", "0", "9"])).unwrap();
+    let missing = Bytes::from("missing");
+    assert_eq!(store.bitfield(&missing, &fail, 0, now()), Ok((vec![None], false)));
+    assert_eq!(store.exists(std::slice::from_ref(&missing), now()), 0);
+
+    // Size limit and type errors
+    let far = parse_bitfield_ops(&args(&["SET", "u8", "800", "1"])).unwrap();
+    assert_eq!(store.bitfield(&missing, &far, 50, now()), Err(BitfieldError::TooLarge));
+    assert_eq!(store.exists(std::slice::from_ref(&missing), now()), 0);
+    store.sadd(&Bytes::from("set"), &args(&["a"]), now()).unwrap();
+    assert_eq!(store.bitfield(&Bytes::from("set"), &incr, 0, now()), Err(BitfieldError::WrongType));
+}
+
+#[test]
+fn test_bitfield_rejects_bad_arguments() {
+    assert!(parse_bitfield_ops(&args(&["GET", "u64", "0"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["GET", "i65", "0"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["GET", "x8", "0"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["GET", "u8", "-1"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["GET", "u8", "4294967290"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["SET", "u8", "18446744073709551615", "1"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["GET", "i64", "#288230376151711743"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["SET", "u8", "0"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["OVERFLOW", "MAYBE"])).is_err());
+    assert!(parse_bitfield_ops(&args(&["GET", "i64", "0"])).is_ok());
+    assert_eq!(parse_bitfield_ops(&args(&[])).unwrap(), vec![]);
 }